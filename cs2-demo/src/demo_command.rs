@@ -1,10 +1,11 @@
 use protobuf::CodedInputStream;
 use protobuf::Message;
 use std::fmt;
+use std::io::{Read, Seek, SeekFrom};
 
 use crate::packet::Packet;
 use crate::proto::demo::{
-    CDemoClassInfo, CDemoFileHeader, CDemoFullPacket, CDemoPacket, CDemoSendTables,
+    CDemoClassInfo, CDemoFileHeader, CDemoFileInfo, CDemoFullPacket, CDemoPacket, CDemoSendTables,
     CDemoStringTables, EDemoCommands,
 };
 use crate::Tick;
@@ -17,7 +18,8 @@ pub enum DemoCommand {
     Stop,
     /// The first command.
     FileHeader(CDemoFileHeader),
-    FileInfo,
+    /// Summary of the demo, found at the end of the file.
+    FileInfo(CDemoFileInfo),
     /// A sync tick. It contains no data.
     SyncTick,
     SendTables(CDemoSendTables),
@@ -39,7 +41,7 @@ impl DemoCommand {
         let content = match cmd {
             0 => DemoCommand::Stop,
             1 => DemoCommand::FileHeader(CDemoFileHeader::parse_from_bytes(data)?),
-            2 => DemoCommand::FileInfo,
+            2 => DemoCommand::FileInfo(CDemoFileInfo::parse_from_bytes(data)?),
             3 => DemoCommand::SyncTick,
             4 => DemoCommand::SendTables(CDemoSendTables::parse_from_bytes(data)?),
             5 => DemoCommand::ClassInfo(CDemoClassInfo::parse_from_bytes(data)?),
@@ -69,6 +71,7 @@ impl fmt::Display for DemoCommand {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             DemoCommand::FileHeader(m) => write!(f, "FileHeader {m}"),
+            DemoCommand::FileInfo(m) => write!(f, "FileInfo {m}"),
             DemoCommand::ClassInfo(m) => write!(f, "ClassInfo {m}"),
             DemoCommand::FullPacket(st, p) => write!(f, "FullPacket {st}, {p:?}"),
             DemoCommand::SendTables(_) => write!(f, "SendTables"),
@@ -79,6 +82,8 @@ impl fmt::Display for DemoCommand {
 
 pub struct DemoParser<'a> {
     reader: CodedInputStream<'a>,
+    /// Offset in the demo file of the first byte read by `reader`.
+    base_offset: u64,
 }
 
 impl<'a> DemoParser<'a> {
@@ -90,10 +95,58 @@ impl<'a> DemoParser<'a> {
         }
         let mut reader = CodedInputStream::new(read);
         reader.skip_raw_bytes(8)?;
-        Ok(Self { reader })
+        Ok(Self {
+            reader,
+            base_offset: 8,
+        })
+    }
+
+    /// Creates a parser for a reader positioned at the start of a demo command, `offset` bytes
+    /// from the beginning of the demo file.
+    pub(crate) fn new_at_offset(read: &'a mut dyn std::io::Read, offset: u64) -> Self {
+        Self {
+            reader: CodedInputStream::new(read),
+            base_offset: offset,
+        }
+    }
+
+    /// Returns the offset in the demo file of the next demo command.
+    pub fn offset(&self) -> u64 {
+        self.base_offset + self.reader.pos()
     }
 
     pub fn parse_next_demo_command(&mut self) -> Result<Option<(Tick, DemoCommand)>> {
+        let Some((tick, cmd, data)) = self.read_next_raw_demo_command()? else {
+            return Ok(None);
+        };
+        Ok(Some((tick, DemoCommand::try_new(cmd, &data)?)))
+    }
+
+    /// Returns the tick, type and decompressed content of the next demo command, without
+    /// decoding it.
+    pub fn read_next_raw_demo_command(&mut self) -> Result<Option<(Tick, u32, Vec<u8>)>> {
+        let Some((cmd, compressed, tick, size)) = self.read_command_header()? else {
+            return Ok(None);
+        };
+        let data = self.reader.read_raw_bytes(size)?;
+        let data = if compressed {
+            snap::raw::Decoder::new().decompress_vec(data.as_slice())?
+        } else {
+            data
+        };
+        Ok(Some((tick, cmd, data)))
+    }
+
+    /// Reads the type and tick of the next demo command, without decoding its content.
+    pub(crate) fn skip_next_demo_command(&mut self) -> Result<Option<(Tick, u32)>> {
+        let Some((cmd, _, tick, size)) = self.read_command_header()? else {
+            return Ok(None);
+        };
+        self.reader.skip_raw_bytes(size)?;
+        Ok(Some((tick, cmd)))
+    }
+
+    fn read_command_header(&mut self) -> Result<Option<(u32, bool, Tick, u32)>> {
         if self.reader.eof()? {
             return Ok(None);
         }
@@ -102,12 +155,87 @@ impl<'a> DemoParser<'a> {
         let compressed = (cmd_flags & (EDemoCommands::DEM_IsCompressed as u32)) != 0;
         let tick = self.reader.read_raw_varint32()? as i32;
         let size = self.reader.read_raw_varint32()?;
-        let data = self.reader.read_raw_bytes(size)?;
-        let data = if compressed {
-            snap::raw::Decoder::new().decompress_vec(data.as_slice())?
-        } else {
-            data
-        };
-        Ok(Some((tick, DemoCommand::try_new(cmd, &data)?)))
+        Ok(Some((cmd, compressed, tick, size)))
+    }
+}
+
+/// Reads the CDemoFileInfo at the end of the demo, using the offset stored in the demo header.
+/// Returns None for demos which were not completely written, e.g. while the match is running.
+pub fn read_file_info<R: Read + Seek>(read: &mut R) -> Result<Option<CDemoFileInfo>> {
+    read.rewind()?;
+    let mut header = [0; 16];
+    read.read_exact(&mut header)?;
+    if &header[..8] != b"PBDEMS2\0" {
+        return Err(Error::InvalidDemoType(header[..8].into()));
+    }
+    let offset = u32::from_le_bytes(header[8..12].try_into().unwrap()) as u64;
+    if offset == 0 {
+        return Ok(None);
+    }
+    read.seek(SeekFrom::Start(offset))?;
+    let mut parser = DemoParser::new_at_offset(read, offset);
+    match parser.parse_next_demo_command()? {
+        Some((_, DemoCommand::FileInfo(file_info))) => Ok(Some(file_info)),
+        _ => Err(Error::InvalidFileInfoOffset),
+    }
+}
+
+/// Reads the first CDemoSendTables and CDemoClassInfo of the demo, without decoding them nor any
+/// other demo command.
+pub fn read_send_tables(read: &mut dyn Read) -> Result<(CDemoSendTables, CDemoClassInfo)> {
+    let mut parser = DemoParser::try_new(read)?;
+    let mut send_tables = None;
+    while let Some((_, cmd, data)) = parser.read_next_raw_demo_command()? {
+        if cmd == EDemoCommands::DEM_SendTables as u32 {
+            send_tables = Some(CDemoSendTables::parse_from_bytes(&data)?);
+        } else if cmd == EDemoCommands::DEM_ClassInfo as u32 {
+            let send_tables = send_tables.ok_or(Error::ClassInfoBeforeSendTables)?;
+            return Ok((send_tables, CDemoClassInfo::parse_from_bytes(&data)?));
+        }
+    }
+    Err(Error::MissingSendTables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testdata;
+    use std::io::Cursor;
+
+    #[test]
+    fn file_info() -> Result<()> {
+        let mut demo = testdata::demo(vec![(EDemoCommands::DEM_SyncTick, 0, vec![])]);
+        assert!(read_file_info(&mut Cursor::new(&demo))?.is_none());
+
+        let mut file_info = CDemoFileInfo::new();
+        file_info.set_playback_ticks(1234);
+        let offset = demo.len() as u32;
+        demo[8..12].copy_from_slice(&offset.to_le_bytes());
+        let file_info = file_info.write_to_bytes().unwrap();
+        demo.extend(&testdata::demo(vec![(EDemoCommands::DEM_FileInfo, 0, file_info)])[16..]);
+        let file_info = read_file_info(&mut Cursor::new(&demo))?.unwrap();
+        assert_eq!(file_info.playback_ticks(), 1234);
+        Ok(())
+    }
+
+    #[test]
+    fn send_tables() -> Result<()> {
+        let send_tables = testdata::send_tables().write_to_bytes().unwrap();
+        let class_info = testdata::class_info().write_to_bytes().unwrap();
+        let demo = testdata::demo(vec![
+            (EDemoCommands::DEM_SyncTick, 0, vec![]),
+            (EDemoCommands::DEM_SendTables, 0, send_tables),
+        ]);
+        assert!(matches!(
+            read_send_tables(&mut demo.as_slice()),
+            Err(Error::MissingSendTables)
+        ));
+
+        let mut demo = demo;
+        demo.extend(&testdata::demo(vec![(EDemoCommands::DEM_ClassInfo, 0, class_info)])[16..]);
+        let (send_tables, class_info) = read_send_tables(&mut demo.as_slice())?;
+        assert_eq!(send_tables, testdata::send_tables());
+        assert_eq!(class_info, testdata::class_info());
+        Ok(())
     }
 }
@@ -3,38 +3,80 @@ mod decoder;
 mod fieldpath;
 mod property;
 mod path_name;
+mod recorder;
 mod send_tables;
 
+use std::io::Cursor;
+use std::ops::Range;
 use std::rc::Rc;
 
 use bitstream_io::BitRead;
+use demo_context::InContext;
 use tracing::{enabled, trace, Level};
 
+use self::decoder::Decoder;
 use self::fieldpath::FieldPath;
-use self::path_name::PathName;
+use self::recorder::Recorder;
 use self::send_tables::{Field, Serializer};
 use crate::proto::netmessages::CSVCMsg_PacketEntities;
 use crate::read::ValveBitReader;
 use crate::BitReader;
-use crate::{Error, Result};
+use crate::{Error, Result, Tick};
 
 pub use self::class::Classes;
+pub use self::path_name::PathName;
 pub use self::property::{Property, TreeEntity};
+pub use self::recorder::{PropertyChanges, PropertyPattern};
 pub use self::send_tables::SendTables;
 
 pub trait Entity: std::fmt::Display {
     fn serializer(&self) -> &Rc<Serializer>;
     fn get_property(&self, fp: &[i32]) -> (Option<&Property>, &Field, PathName);
     fn set_property(&mut self, fp: &[i32], value: Option<Property>);
+    /// Returns the name and value of every property set, in field order. Objects and arrays are
+    /// replaced by the properties they contain.
+    fn properties(&self) -> Vec<(PathName, &Property)> {
+        let mut properties = Vec::new();
+        for i in 0..self.serializer().fields.len() {
+            if let (Some(prop), field, path) = self.get_property(&[i as i32]) {
+                property::collect(&mut properties, path, prop, field);
+            }
+        }
+        properties
+    }
+
+    /// Returns the value of the top level property called `name`.
+    fn get_property_by_name(&self, name: &str) -> Option<&Property> {
+        let i = self
+            .serializer()
+            .fields
+            .iter()
+            .position(|f| f.name().as_ref() == name)?;
+        self.get_property(&[i as i32]).0
+    }
 }
 
 pub type EntityFactory = &'static dyn Fn(Rc<Serializer>) -> Box<dyn Entity>;
 
+/// Position of a string or 64-bit integer property in the entity data of a PacketEntities
+/// message, see [`EntityList::track_ranges`].
+pub(crate) struct PropertyRange {
+    pub(crate) bits: Range<u64>,
+    pub(crate) class: Box<str>,
+    pub(crate) path: String,
+    pub(crate) value: Property,
+    /// Whether the value is encoded on 64 bits instead of as a varint.
+    pub(crate) fixed64: bool,
+}
+
 pub struct EntityList {
     entities: Vec<Option<Box<dyn Entity>>>,
     entity_factory: EntityFactory,
     /// Only used by read_props to avoid allocations.
     field_paths: Vec<FieldPath>,
+    recorder: Option<Recorder>,
+    /// Property ranges of each PacketEntities message read, if tracking.
+    ranges: Option<Vec<Vec<PropertyRange>>>,
 }
 
 impl EntityList {
@@ -43,9 +85,55 @@ impl EntityList {
             entities: Default::default(),
             entity_factory,
             field_paths: Vec::with_capacity(512),
+            recorder: None,
+            ranges: None,
         }
     }
 
+    /// Starts recording the changes of the properties matching `patterns`.
+    pub(crate) fn record(&mut self, patterns: Vec<PropertyPattern>) {
+        self.recorder = Some(Recorder::new(patterns));
+    }
+
+    /// Returns the property changes recorded since the last call, if recording.
+    pub(crate) fn take_property_changes(&mut self) -> Option<PropertyChanges> {
+        Some(self.recorder.as_mut()?.take())
+    }
+
+    /// Starts tracking where string and 64-bit integer properties are in entity data, so that
+    /// they can be replaced in place.
+    pub(crate) fn track_ranges(&mut self) {
+        self.ranges = Some(Vec::new());
+    }
+
+    /// Returns the property ranges of each PacketEntities message read since the last call.
+    pub(crate) fn take_ranges(&mut self) -> Vec<Vec<PropertyRange>> {
+        self.ranges.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Returns the positions of the strings and 64-bit integers in the instance baseline with
+    /// the given key. Keys which are not class ids are skipped.
+    pub(crate) fn baseline_ranges(
+        &self,
+        classes: &Classes,
+        key: &str,
+        baseline: &[u8],
+    ) -> Result<Vec<PropertyRange>> {
+        let Some(class) = classes.baseline_class(key) else {
+            return Ok(Vec::new());
+        };
+        let mut entity = (self.entity_factory)(Rc::clone(&class.serializer));
+        let mut ranges = Vec::new();
+        Self::read_props(
+            &mut BitReader::new(Cursor::new(baseline)),
+            entity.as_mut(),
+            &mut Vec::new(),
+            None,
+            Some(&mut ranges),
+        )?;
+        Ok(ranges)
+    }
+
     pub fn len(&self) -> usize {
         self.entities.len()
     }
@@ -55,13 +143,31 @@ impl EntityList {
         self.len() == 0
     }
 
+    /// Returns the entity with the supplied id, if it exists.
+    pub fn get(&self, id: usize) -> Option<&dyn Entity> {
+        self.entities.get(id)?.as_deref()
+    }
+
+    /// Removes all entities.
+    pub(crate) fn clear(&mut self) {
+        self.entities.clear();
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.clear();
+        }
+    }
+
     pub(crate) fn read_packet_entities(
         &mut self,
         msg: CSVCMsg_PacketEntities,
         classes: &Classes,
+        tick: Tick,
     ) -> Result<()> {
         let mut next_entity_id = 0;
-        let mut reader = BitReader::new(msg.entity_data());
+        let mut reader = BitReader::new(Cursor::new(msg.entity_data()));
+        let mut ranges = self.ranges.as_mut().map(|ranges| {
+            ranges.push(Vec::new());
+            ranges.last_mut().unwrap()
+        });
         for _ in 0..msg.updated_entries() {
             let entity_id = next_entity_id + reader.read_ubitvar()?;
             next_entity_id = entity_id + 1;
@@ -70,11 +176,17 @@ impl EntityList {
             match (remove, new) {
                 (false, false) => {
                     trace!("Update entity {entity_id}");
-                    if let Some(entity) = self.entities[entity_id as usize].as_mut() {
-                        Self::read_props(&mut reader, entity.as_mut(), &mut self.field_paths)?;
-                    } else {
-                        return Err(Error::InvalidEntityId);
-                    }
+                    let Some(entity) = self.entities[entity_id as usize].as_mut() else {
+                        return Err(Error::InvalidEntityId.in_entity(entity_id, None));
+                    };
+                    Self::read_props(
+                        &mut reader,
+                        entity.as_mut(),
+                        &mut self.field_paths,
+                        self.recorder.as_mut().map(|r| (r, tick, entity_id)),
+                        ranges.as_deref_mut(),
+                    )
+                    .map_err(|e| e.in_entity(entity_id, Some(&entity.serializer().name)))?;
                 }
                 (false, true) => {
                     let class_id = reader.read::<u32>(classes.class_id_bits)?;
@@ -83,15 +195,26 @@ impl EntityList {
                     let class = classes.class(class_id);
                     trace!("Create entity {entity_id} {}", class.serializer.name);
                     let mut entity = (self.entity_factory)(Rc::clone(&class.serializer));
+                    let in_entity = |e: Error| e.in_entity(entity_id, Some(&class.serializer.name));
                     if let Some(baseline) = &class.instance_baseline {
                         Self::read_props(
-                            &mut BitReader::new(baseline),
+                            &mut BitReader::new(Cursor::new(&baseline[..])),
                             entity.as_mut(),
                             &mut self.field_paths,
-                        )?;
+                            self.recorder.as_mut().map(|r| (r, tick, entity_id)),
+                            None,
+                        )
+                        .map_err(in_entity)?;
                         trace!("Baseline for entity {entity_id} done");
                     };
-                    Self::read_props(&mut reader, entity.as_mut(), &mut self.field_paths)?;
+                    Self::read_props(
+                        &mut reader,
+                        entity.as_mut(),
+                        &mut self.field_paths,
+                        self.recorder.as_mut().map(|r| (r, tick, entity_id)),
+                        ranges.as_deref_mut(),
+                    )
+                    .map_err(in_entity)?;
                     if self.entities.len() <= entity_id as usize {
                         self.entities.resize_with(entity_id as usize + 1, || None);
                     }
@@ -100,17 +223,24 @@ impl EntityList {
                 (true, _) => {
                     trace!("Delete entity {entity_id}");
                     self.entities[entity_id as usize] = None;
+                    if let Some(recorder) = self.recorder.as_mut() {
+                        recorder.remove(tick, entity_id);
+                    }
                 }
             };
         }
         Ok(())
     }
 
-    /// Read props from `reader`, creating new props or overwriting existing ones.
+    /// Read props from `reader`, creating new props or overwriting existing ones. The changes are
+    /// recorded by `recorder` with their tick and entity id, and the positions of strings and
+    /// 64-bit integers are added to `ranges`.
     fn read_props(
         reader: &mut BitReader,
         entity: &mut dyn Entity,
         fps: &mut Vec<FieldPath>,
+        recorder: Option<(&mut Recorder, Tick, u32)>,
+        mut ranges: Option<&mut Vec<PropertyRange>>,
     ) -> Result<()> {
         let mut fp = FieldPath::new();
         fps.clear();
@@ -122,9 +252,36 @@ impl EntityList {
             fps.push(fp.clone());
         }
         let serializer = Rc::clone(entity.serializer());
+        let mut recorder = recorder.filter(|(r, _, _)| r.records_class(serializer.name()));
         for fp in fps {
             let field = get_field(serializer.as_ref(), fp.data());
-            entity.set_property(fp.data(), field.decoder().decode(reader)?);
+            let decoder = field.decoder();
+            let range_start = match ranges {
+                Some(_) if matches!(decoder, Decoder::String | Decoder::U64 | Decoder::Fixed64) => {
+                    Some(reader.position_in_bits()?)
+                }
+                _ => None,
+            };
+            let value = decoder.decode(reader)?;
+            if let (Some(ranges), Some(start), Some(value)) = (ranges.as_mut(), range_start, &value)
+            {
+                ranges.push(PropertyRange {
+                    bits: start..reader.position_in_bits()?,
+                    class: serializer.name().into(),
+                    path: entity.get_property(fp.data()).2.to_string(),
+                    value: value.clone(),
+                    fixed64: matches!(decoder, Decoder::Fixed64),
+                });
+            }
+            match recorder.as_mut() {
+                Some((recorder, tick, entity_id))
+                    if recorder.may_record(serializer.name(), fp.data()) =>
+                {
+                    entity.set_property(fp.data(), value.clone());
+                    recorder.record(*tick, *entity_id, entity, fp.data(), value);
+                }
+                _ => entity.set_property(fp.data(), value),
+            }
 
             if enabled!(Level::TRACE) {
                 let (prop, field, name) = entity.get_property(fp.data());
@@ -173,8 +330,7 @@ mod tests {
     use super::*;
     use crate::testdata;
 
-    #[test]
-    fn test() -> Result<()> {
+    fn classes() -> Result<Classes> {
         let send_tables = SendTables::try_new(testdata::send_tables())?;
         let mut classes = Classes::try_new(testdata::class_info(), send_tables)?;
         for table in testdata::string_tables().tables {
@@ -187,9 +343,79 @@ mod tests {
                 classes.update_instance_baselines(items);
             }
         }
+        Ok(classes)
+    }
 
+    #[test]
+    fn test() -> Result<()> {
+        let classes = classes()?;
         let mut entities = EntityList::new(&TreeEntity::factory);
-        entities.read_packet_entities(testdata::packet_entities(), &classes)?;
+        entities.read_packet_entities(testdata::packet_entities(), &classes, 0)?;
+        let controller = (0..entities.len())
+            .filter_map(|id| entities.get(id))
+            .find(|e| e.serializer().name() == "CCSPlayerController")
+            .unwrap();
+        assert!(controller.get_property_by_name("m_iTeamNum").is_some());
+        assert!(controller.get_property_by_name("m_nonexistent").is_none());
+        let names: Vec<_> = controller
+            .properties()
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .collect();
+        assert!(names.iter().any(|n| n == "m_iTeamNum"));
+        assert!(names.iter().any(|n| n == "m_pEntity.m_nameStringableIndex"));
+        Ok(())
+    }
+
+    #[test]
+    fn record() -> Result<()> {
+        let classes = classes()?;
+        let mut entities = EntityList::new(&TreeEntity::factory);
+        entities.record(vec![
+            PropertyPattern::new("CCSPlayerController", "m_iTeamNum"),
+            PropertyPattern::new("*", "m_pEntity.m_nameStringableIndex"),
+        ]);
+        entities.read_packet_entities(testdata::packet_entities(), &classes, 7)?;
+        let changes = entities.take_property_changes().unwrap();
+        assert!(changes.tick.iter().all(|&tick| tick == 7));
+        let rows: Vec<_> = (0..changes.len())
+            .map(|i| (changes.class[i].as_ref(), changes.property[i].as_ref()))
+            .collect();
+        assert!(rows.contains(&("CCSPlayerController", "m_iTeamNum")));
+        assert!(rows.contains(&("CCSPlayerPawn", "m_pEntity.m_nameStringableIndex")));
+        assert!(!rows.contains(&("CCSPlayerPawn", "m_iTeamNum")));
+
+        // Entity ids and properties are unique within a tick.
+        let mut keys: Vec<_> = (0..changes.len())
+            .map(|i| (changes.entity_id[i], changes.property[i].as_ref()))
+            .collect();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys.len(), changes.len());
+        assert!(entities.take_property_changes().unwrap().is_empty());
+
+        // A full update writes the same values again.
+        entities.read_packet_entities(testdata::packet_entities(), &classes, 8)?;
+        assert!(entities.take_property_changes().unwrap().is_empty());
+
+        // The properties of deleted entities are removed.
+        let controller = (0..entities.len())
+            .find(|&id| {
+                entities
+                    .get(id)
+                    .is_some_and(|e| e.serializer().name() == "CCSPlayerController")
+            })
+            .unwrap() as u32;
+        let recorded = (0..changes.len())
+            .filter(|&i| changes.entity_id[i] == controller)
+            .count();
+        entities.read_packet_entities(testdata::delete_entities(&[controller]), &classes, 9)?;
+        let changes = entities.take_property_changes().unwrap();
+        assert_eq!(changes.len(), recorded);
+        assert!(changes.tick.iter().all(|&tick| tick == 9));
+        assert!(changes.entity_id.iter().all(|&id| id == controller));
+        assert!(changes.value.iter().all(Option::is_none));
+        assert!(changes.property.iter().any(|p| p.as_ref() == "m_iTeamNum"));
         Ok(())
     }
 }
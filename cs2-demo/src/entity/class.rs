@@ -8,7 +8,7 @@ use crate::{Error, Result};
 
 pub type ClassId = u32;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(super) struct Class {
     class_id: ClassId,
     pub(super) serializer: Rc<Serializer>,
@@ -30,7 +30,7 @@ impl Class {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct Classes {
     classes: Vec<Class>,
     pub(super) class_id_bits: u32,
@@ -72,6 +72,11 @@ impl Classes {
     pub(super) fn class(&self, class_id: ClassId) -> &Class {
         &self.classes[class_id as usize]
     }
+
+    /// Returns the class of an instance baseline key, if it is a valid class id.
+    pub(super) fn baseline_class(&self, key: &str) -> Option<&Class> {
+        self.classes.get(key.parse::<ClassId>().ok()? as usize)
+    }
 }
 
 #[cfg(test)]
@@ -1,10 +1,10 @@
 use std::{fmt, rc::Rc};
 
 use super::path_name::PathNameItem;
-use super::send_tables::{Field, Serializer};
+use super::send_tables::{ArrayField, Field, Serializer, VectorField};
 use super::{Entity, PathName};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Object {
     pub(super) properties: Box<[Option<Property>]>,
 }
@@ -18,7 +18,7 @@ impl Object {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Property {
     Bool(bool),
     I32(i32),
@@ -123,7 +123,7 @@ impl Entity for TreeEntity {
                     (p, Field::Array(f)) if p.is_none() => {
                         *p = Some(Property::Array(vec![None; f.size as usize]));
                         match p {
-                            Some(Property::Array(a)) => (&mut a[i as usize], &f.element.as_ref()),
+                            Some(Property::Array(a)) => (&mut a[i as usize], f.element.as_ref()),
                             _ => unreachable!(),
                         }
                     }
@@ -159,52 +159,49 @@ impl Entity for TreeEntity {
 
 impl fmt::Display for TreeEntity {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fn dfs(
-            f: &mut fmt::Formatter<'_>,
-            path: PathName,
-            prop: &Property,
-            field: &Field,
-        ) -> fmt::Result {
-            let path = path.push_field(field.name());
-            match (prop, field) {
-                (Property::Object(o), Field::Object(fo)) => {
-                    print_object(f, path, o, &fo.serializer)?
-                }
-                (Property::Array(a), Field::Array(fa)) => {
-                    for (i, e) in a.iter().enumerate() {
-                        let path = path.clone().push_index(i);
-                        dfs(f, path, e.as_ref().unwrap(), &fa.element)?;
-                    }
-                }
-                (Property::Array(a), Field::Vector(fv)) => {
-                    for (i, e) in a.iter().enumerate() {
-                        let path = path.clone().push_index(i);
-                        dfs(f, path, e.as_ref().unwrap(), &fv.element)?;
-                    }
-                }
-                _ => writeln!(f, "{} = {}", path, prop)?,
-            }
-            Ok(())
+        for (path, prop) in self.properties() {
+            writeln!(f, "{}.{path} = {prop}", self.serializer.name)?;
         }
+        Ok(())
+    }
+}
 
-        fn print_object(
-            f: &mut fmt::Formatter<'_>,
-            path: PathName,
-            object: &Object,
-            serializer: &Serializer,
-        ) -> fmt::Result {
-            for (i, e) in object.properties.iter().enumerate() {
-                let field = &serializer.fields[i];
-                if let Some(prop) = e {
-                    dfs(f, path.clone(), prop, field)?;
+/// Adds the properties set in `object` to `properties`, see [`Entity::properties`].
+fn collect_object<'a>(
+    properties: &mut Vec<(PathName, &'a Property)>,
+    path: &PathName,
+    object: &'a Object,
+    serializer: &Serializer,
+) {
+    for (prop, field) in object.properties.iter().zip(&serializer.fields) {
+        if let Some(prop) = prop {
+            let path = path.clone().push_field(field.name());
+            collect(properties, path, prop, field);
+        }
+    }
+}
+
+/// Adds `prop`, or the properties it contains if it is an object or an array, to `properties`.
+pub(super) fn collect<'a>(
+    properties: &mut Vec<(PathName, &'a Property)>,
+    path: PathName,
+    prop: &'a Property,
+    field: &Field,
+) {
+    match (prop, field) {
+        (Property::Object(o), Field::Object(fo)) => {
+            collect_object(properties, &path, o, &fo.serializer)
+        }
+        (
+            Property::Array(a),
+            Field::Array(ArrayField { element, .. }) | Field::Vector(VectorField { element, .. }),
+        ) => {
+            for (i, e) in a.iter().enumerate() {
+                if let Some(e) = e {
+                    collect(properties, path.clone().push_index(i), e, element);
                 }
             }
-            Ok(())
         }
-
-        let path = PathName {
-            items: vec![PathNameItem::Field(Rc::from(self.serializer.name.as_str()))],
-        };
-        print_object(f, path, &self.object, &self.serializer)
+        _ => properties.push((path, prop)),
     }
 }
@@ -0,0 +1,330 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::rc::Rc;
+
+use super::{Entity, Property};
+use crate::Tick;
+
+/// Selects properties by class name and property path, as printed by
+/// [`PathName`](super::PathName), for example `CCSPlayerPawn` and `m_iHealth`. `*` matches any
+/// class, or any segment of the path: `m_vecX.*` matches every element of the `m_vecX` array.
+#[derive(Clone, Debug)]
+pub struct PropertyPattern {
+    pub class: String,
+    pub path: String,
+}
+
+impl PropertyPattern {
+    pub fn new(class: &str, path: &str) -> Self {
+        Self {
+            class: class.to_string(),
+            path: path.to_string(),
+        }
+    }
+
+    fn matches_class(&self, class: &str) -> bool {
+        self.class == "*" || self.class == class
+    }
+
+    fn matches_path(&self, path: &str) -> bool {
+        let mut segments = path.split('.');
+        self.path
+            .split('.')
+            .all(|p| segments.next().is_some_and(|s| p == "*" || p == s))
+            && segments.next().is_none()
+    }
+}
+
+/// Changes of properties, stored as columns with a row per change. There is at most one row per
+/// entity, property and tick, holding the last value written. The value is None when the property
+/// was removed, including with its entity.
+#[derive(Debug, Default)]
+pub struct PropertyChanges {
+    pub tick: Vec<Tick>,
+    pub entity_id: Vec<u32>,
+    pub class: Vec<Rc<str>>,
+    pub property: Vec<Rc<str>>,
+    pub value: Vec<Option<Property>>,
+}
+
+impl PropertyChanges {
+    pub fn len(&self) -> usize {
+        self.tick.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Moves all the rows of `other` to the end of `self`.
+    pub fn append(&mut self, other: &mut Self) {
+        self.tick.append(&mut other.tick);
+        self.entity_id.append(&mut other.entity_id);
+        self.class.append(&mut other.class);
+        self.property.append(&mut other.property);
+        self.value.append(&mut other.value);
+    }
+
+    /// Keeps the rows whose `keep` element is true.
+    fn retain(&mut self, keep: &[bool]) {
+        fn retain<T>(column: &mut Vec<T>, keep: &[bool]) {
+            let mut keep = keep.iter();
+            column.retain(|_| *keep.next().unwrap());
+        }
+        retain(&mut self.tick, keep);
+        retain(&mut self.entity_id, keep);
+        retain(&mut self.class, keep);
+        retain(&mut self.property, keep);
+        retain(&mut self.value, keep);
+    }
+
+    /// Writes the rows as CSV, with a header. Vectors are written as `[x, y, z]` and missing
+    /// values as empty fields.
+    pub fn write_csv(&self, write: &mut dyn io::Write) -> io::Result<()> {
+        fn field(s: &str) -> std::borrow::Cow<'_, str> {
+            if s.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", s.replace('"', "\"\"")).into()
+            } else {
+                s.into()
+            }
+        }
+        writeln!(write, "tick,entity_id,class,property,value")?;
+        for i in 0..self.len() {
+            let value = self.value[i].as_ref().map(Property::to_string);
+            writeln!(
+                write,
+                "{},{},{},{},{}",
+                self.tick[i],
+                self.entity_id[i],
+                field(&self.class[i]),
+                field(&self.property[i]),
+                field(value.as_deref().unwrap_or_default())
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Names of the recorded properties of a class.
+struct ClassProperties {
+    class: Rc<str>,
+    /// Maps field paths to the name of the property, or to None if it is not recorded.
+    names: HashMap<Box<[i32]>, Option<Rc<str>>>,
+}
+
+/// Last recorded values of the properties of an entity.
+struct EntityValues {
+    class: Rc<str>,
+    /// Ordered by name, so that the removal of the entity is recorded in a stable order.
+    values: BTreeMap<Rc<str>, Option<Property>>,
+}
+
+/// Records the changes of the properties matching patterns, see
+/// [`ParseOptions::properties`](crate::ParseOptions::properties).
+pub(super) struct Recorder {
+    patterns: Vec<PropertyPattern>,
+    classes: HashMap<Box<str>, ClassProperties>,
+    changes: PropertyChanges,
+    /// Maps entity id and property name to the row of `changes` holding its last write.
+    rows: HashMap<(u32, Rc<str>), usize>,
+    /// The value of the property of each row of `changes` before its tick, if recorded.
+    previous: Vec<Option<Option<Property>>>,
+    /// Maps entity ids to the last values recorded, which writes are compared with.
+    values: HashMap<u32, EntityValues>,
+}
+
+impl Recorder {
+    pub(super) fn new(patterns: Vec<PropertyPattern>) -> Self {
+        Self {
+            patterns,
+            classes: Default::default(),
+            changes: Default::default(),
+            rows: Default::default(),
+            previous: Default::default(),
+            values: Default::default(),
+        }
+    }
+
+    /// Returns true if some properties of `class` may be recorded.
+    pub(super) fn records_class(&self, class: &str) -> bool {
+        self.patterns.iter().any(|p| p.matches_class(class))
+    }
+
+    /// Returns false if the writes to the property at `fp` of `class` are known not to be
+    /// recorded. The name of a property is only resolved on its first write, by `record`.
+    pub(super) fn may_record(&self, class: &str, fp: &[i32]) -> bool {
+        let name = self.classes.get(class).and_then(|c| c.names.get(fp));
+        !matches!(name, Some(None))
+    }
+
+    /// Records that `value` was written to the property at `fp`, if it matches a pattern and
+    /// differs from the last value recorded. The value must already be set in `entity`.
+    pub(super) fn record(
+        &mut self,
+        tick: Tick,
+        entity_id: u32,
+        entity: &dyn Entity,
+        fp: &[i32],
+        value: Option<Property>,
+    ) {
+        let class = entity.serializer().name();
+        let properties = self
+            .classes
+            .entry(class.into())
+            .or_insert_with(|| ClassProperties {
+                class: Rc::from(class),
+                names: HashMap::new(),
+            });
+        let name = match properties.names.get(fp) {
+            Some(name) => name.clone(),
+            None => {
+                let path = entity.get_property(fp).2.to_string();
+                let recorded = self
+                    .patterns
+                    .iter()
+                    .any(|p| p.matches_class(class) && p.matches_path(&path));
+                let name = recorded.then(|| Rc::from(path));
+                properties.names.insert(fp.into(), name.clone());
+                name
+            }
+        };
+        let Some(property) = name else {
+            return;
+        };
+        let class = Rc::clone(&properties.class);
+        if self
+            .values
+            .get(&entity_id)
+            .is_some_and(|e| e.class != class)
+        {
+            // The entity was replaced by one of another class without being deleted.
+            self.remove(tick, entity_id);
+        }
+        let entity_values = self
+            .values
+            .entry(entity_id)
+            .or_insert_with(|| EntityValues {
+                class: Rc::clone(&class),
+                values: BTreeMap::new(),
+            });
+        // Full updates write every property again.
+        if entity_values.values.get(&property) == Some(&value) {
+            return;
+        }
+        let previous = entity_values
+            .values
+            .insert(Rc::clone(&property), value.clone());
+        self.push(tick, entity_id, class, property, value, previous);
+    }
+
+    /// Records that the recorded properties of the entity `entity_id` were removed with it.
+    pub(super) fn remove(&mut self, tick: Tick, entity_id: u32) {
+        let Some(entity) = self.values.remove(&entity_id) else {
+            return;
+        };
+        for (property, value) in entity.values {
+            if value.is_some() {
+                let class = Rc::clone(&entity.class);
+                self.push(tick, entity_id, class, property, None, Some(value));
+            }
+        }
+    }
+
+    /// Forgets the last recorded values, when all the entities are dropped without being
+    /// deleted.
+    pub(super) fn clear(&mut self) {
+        self.values.clear();
+    }
+
+    fn push(
+        &mut self,
+        tick: Tick,
+        entity_id: u32,
+        class: Rc<str>,
+        property: Rc<str>,
+        value: Option<Property>,
+        previous: Option<Option<Property>>,
+    ) {
+        let key = (entity_id, Rc::clone(&property));
+        match self.rows.get(&key) {
+            Some(&row) if self.changes.tick[row] == tick => self.changes.value[row] = value,
+            _ => {
+                self.rows.insert(key, self.changes.len());
+                self.changes.tick.push(tick);
+                self.changes.entity_id.push(entity_id);
+                self.changes.class.push(class);
+                self.changes.property.push(property);
+                self.changes.value.push(value);
+                self.previous.push(previous);
+            }
+        }
+    }
+
+    /// Returns the changes recorded since the last call.
+    pub(super) fn take(&mut self) -> PropertyChanges {
+        self.rows.clear();
+        let mut changes = std::mem::take(&mut self.changes);
+        let previous = std::mem::take(&mut self.previous);
+        // The writes of a tick can restore the previous value, like the instance baseline then
+        // the entity data of an entity sent again.
+        let keep: Vec<_> = std::iter::zip(&changes.value, &previous)
+            .map(|(value, previous)| previous.as_ref() != Some(value))
+            .collect();
+        if keep.contains(&false) {
+            changes.retain(&keep);
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pattern() {
+        let pattern = PropertyPattern::new("CCSPlayerPawn", "m_vecX.*");
+        assert!(pattern.matches_class("CCSPlayerPawn"));
+        assert!(!pattern.matches_class("CCSPlayerController"));
+        assert!(pattern.matches_path("m_vecX.0002"));
+        assert!(!pattern.matches_path("m_vecX"));
+        assert!(!pattern.matches_path("m_vecX.0002.m_x"));
+        assert!(!pattern.matches_path("m_vecY.0002"));
+        assert!(PropertyPattern::new("*", "m_iHealth").matches_class("CChicken"));
+    }
+
+    #[test]
+    fn csv() -> io::Result<()> {
+        let mut changes = PropertyChanges::default();
+        let mut row = |tick, property: &str, value| {
+            let mut other = PropertyChanges {
+                tick: vec![tick],
+                entity_id: vec![3],
+                class: vec![Rc::from("CCSPlayerPawn")],
+                property: vec![Rc::from(property)],
+                value: vec![value],
+            };
+            changes.append(&mut other);
+        };
+        row(10, "m_iHealth", Some(Property::I32(100)));
+        row(12, "m_vecOrigin", Some(Property::Vec3([1.0, 2.0, 3.5])));
+        row(
+            14,
+            "m_szLastPlaceName",
+            Some(Property::Str("\"A\" site".into())),
+        );
+        row(16, "m_hOwner", None);
+        let mut csv = Vec::new();
+        changes.write_csv(&mut csv)?;
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "tick,entity_id,class,property,value\n\
+             10,3,CCSPlayerPawn,m_iHealth,100\n\
+             12,3,CCSPlayerPawn,m_vecOrigin,\"[1.0, 2.0, 3.5]\"\n\
+             14,3,CCSPlayerPawn,m_szLastPlaceName,\"\"\"A\"\" site\"\n\
+             16,3,CCSPlayerPawn,m_hOwner,\n"
+        );
+        Ok(())
+    }
+}
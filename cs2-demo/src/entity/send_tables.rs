@@ -21,14 +21,35 @@ pub struct Serializer {
     pub(super) fields: Vec<Field>,
 }
 
+impl Serializer {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Prints the tree of fields, with the fields of objects nested under them.
 impl std::fmt::Display for Serializer {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "class {}", self.name)?;
-        for field in &self.fields {
-            writeln!(f, "  {field:?}")?;
+        write_fields(f, &self.fields, 1)
+    }
+}
+
+fn write_fields(f: &mut std::fmt::Formatter<'_>, fields: &[Field], depth: usize) -> fmt::Result {
+    for field in fields {
+        let indent = "  ".repeat(depth);
+        writeln!(f, "{indent}{}: {}", field.name(), field.ctype())?;
+        let mut element = field;
+        while let Field::Array(ArrayField { element: e, .. })
+        | Field::Vector(VectorField { element: e, .. }) = element
+        {
+            element = e;
+        }
+        if let Field::Object(o) = element {
+            write_fields(f, &o.serializer.fields, depth + 1)?;
         }
-        Ok(())
     }
+    Ok(())
 }
 
 #[derive(Clone)]
@@ -1,3 +1,6 @@
+use crate::Tick;
+use demo_context::{Context, InContext};
+
 /// Error type for this library.
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -13,8 +16,14 @@ pub enum Error {
     Decompression(#[from] snap::Error),
     #[error("missing string_table from CDemoFullPacket")]
     MissingStringTable,
+    #[error("no CDemoFileInfo at the offset found in the demo header")]
+    InvalidFileInfoOffset,
     #[error("missing packet from CDemoFullPacket")]
     MissingPacket,
+    #[error("UpdateStringTable for an unknown string table")]
+    InvalidStringTableId,
+    #[error("string table entry index {0} out of range")]
+    InvalidStringTableIndex(usize),
     #[error("cannot parse string table player index")]
     InvalidPlayerIndex,
     #[error("cannot parse sendtables")]
@@ -33,10 +42,43 @@ pub enum Error {
     EntityBeforeClassInfo,
     #[error("ClassInfo before SendTables")]
     ClassInfoBeforeSendTables,
+    #[error("no CDemoSendTables and CDemoClassInfo in the demo")]
+    MissingSendTables,
+    #[error("no packet at or after tick {0} to start the cut from")]
+    CutStartNotFound(Tick),
+    #[error("rewritten entity property {0} does not have the type and size of the original")]
+    RewrittenPropertySize(String),
     #[error("Missing polymorphic type from {field}")]
     MissingPolymorphicType { field: String },
     #[error(transparent)]
     Visitor(#[from] anyhow::Error),
+    /// Adds the part of the demo being parsed to `source`.
+    #[error("{context}")]
+    InContext {
+        context: Context,
+        #[source]
+        source: Box<Error>,
+    },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Returns true if the error was returned by a [`Visitor`](crate::Visitor) method.
+    pub(crate) fn is_visitor(&self) -> bool {
+        match self {
+            Error::Visitor(_) => true,
+            Error::InContext { source, .. } => source.is_visitor(),
+            _ => false,
+        }
+    }
+}
+
+impl InContext for Error {
+    fn in_context(self, context: Context) -> Self {
+        Error::InContext {
+            context,
+            source: Box::new(self),
+        }
+    }
+}
@@ -59,15 +59,6 @@ impl std::fmt::Display for Descriptor {
     }
 }
 
-#[allow(dead_code)]
-pub(crate) fn dump_descriptors(descriptors: HashMap<i32, Descriptor>) {
-    let mut sorted: Vec<_> = descriptors.values().collect();
-    sorted.sort_by_key(|d| &d.name);
-    for d in sorted {
-        println!("{d}");
-    }
-}
-
 pub(crate) fn parse_game_event_list(gel: CMsgSource1LegacyGameEventList) -> GameEventDescriptors {
     gel.descriptors
         .into_iter()
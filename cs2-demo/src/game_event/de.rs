@@ -1,4 +1,4 @@
-use super::Descriptor;
+use super::{Descriptor, DescriptorKey};
 use crate::proto::gameevents::{cmsg_source1legacy_game_event, CMsgSource1LegacyGameEvent};
 use serde::{de, forward_to_deserialize_any};
 use std::fmt::Display;
@@ -23,6 +23,16 @@ pub enum Error {
         event_type: i32,
         rust_type: &'static str,
     },
+    #[error("event {event}.{key} has unknown type {event_type}")]
+    UnknownKeyType {
+        event: String,
+        key: String,
+        event_type: i32,
+    },
+    #[error("event {event} is missing key {key}")]
+    MissingKey { event: String, key: String },
+    #[error("game events cannot be deserialized as {0}")]
+    Unsupported(&'static str),
 }
 
 pub fn from_proto<'a, T: serde::Deserialize<'a>>(
@@ -49,10 +59,23 @@ struct Deserializer<'de> {
     index: i32,
 }
 
-impl<'a> Deserializer<'a> {
-    fn current_ekey(&'a self) -> Result<&'a cmsg_source1legacy_game_event::Key_t> {
-        let ekey = &self.event.keys[self.index as usize];
-        let desc = &self.descriptor.keys[self.index as usize];
+impl Deserializer<'_> {
+    /// Returns the descriptor of the key being deserialized, or None outside of the event keys.
+    fn current_key(&self) -> Option<&DescriptorKey> {
+        let index = usize::try_from(self.index).ok()?;
+        self.descriptor.keys.get(index)
+    }
+
+    fn current_ekey(&self) -> Result<&cmsg_source1legacy_game_event::Key_t> {
+        let Some(desc) = self.current_key() else {
+            return Err(Error::Unsupported("a value outside of a struct"));
+        };
+        let Some(ekey) = self.event.keys.get(self.index as usize) else {
+            return Err(Error::MissingKey {
+                event: self.descriptor.name.clone(),
+                key: desc.name.clone(),
+            });
+        };
         if ekey.type_() != desc.type_ {
             return Err(Error::DescriptorMismatch {
                 event: self.descriptor.name.clone(),
@@ -64,13 +87,14 @@ impl<'a> Deserializer<'a> {
         Ok(ekey)
     }
 
-    fn type_mismatch_error(&'a self, rust_type: &'static str) -> Error {
-        let ekey = &self.event.keys[self.index as usize];
-        let desc = &self.descriptor.keys[self.index as usize];
+    fn type_mismatch_error(&self, event_type: i32, rust_type: &'static str) -> Error {
         Error::TypeMismatch {
             event: self.descriptor.name.clone(),
-            key: desc.name.clone(),
-            event_type: ekey.type_(),
+            key: self
+                .current_key()
+                .map(|k| k.name.clone())
+                .unwrap_or_default(),
+            event_type,
             rust_type,
         }
     }
@@ -79,19 +103,41 @@ impl<'a> Deserializer<'a> {
 impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
     type Error = Error;
 
-    fn deserialize_any<V: de::Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
-        panic!("GameEvent deserializer internal error")
+    /// Deserializes the event as a map, or the current key according to its type.
+    fn deserialize_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.index == -1 {
+            return visitor.visit_map(self);
+        }
+        let ekey = self.current_ekey()?;
+        match ekey.type_() {
+            1 => visitor.visit_str(ekey.val_string()),
+            2 => visitor.visit_f32(ekey.val_float()),
+            3 | 8 => visitor.visit_i32(ekey.val_long()),
+            4 | 9 => visitor.visit_i32(ekey.val_short()),
+            5 => visitor.visit_i32(ekey.val_byte()),
+            6 => visitor.visit_bool(ekey.val_bool()),
+            7 => visitor.visit_u64(ekey.val_uint64()),
+            event_type => Err(Error::UnknownKeyType {
+                event: self.descriptor.name.clone(),
+                key: self.descriptor.keys[self.index as usize].name.clone(),
+                event_type,
+            }),
+        }
     }
 
     forward_to_deserialize_any! { i8 i16 i64 u8 u16 u32 f64 char str bytes
-        byte_buf option unit unit_struct newtype_struct seq tuple tuple_struct map
+        byte_buf unit unit_struct newtype_struct seq tuple tuple_struct map
+    }
+
+    fn deserialize_option<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_some(self)
     }
 
     fn deserialize_bool<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
         let ekey = self.current_ekey()?;
         let value = match ekey.type_() {
             6 => ekey.val_bool(),
-            _ => return Err(self.type_mismatch_error("bool")),
+            t => return Err(self.type_mismatch_error(t, "bool")),
         };
         visitor.visit_bool(value)
     }
@@ -102,7 +148,7 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
             3 | 8 => ekey.val_long(),
             4 | 9 => ekey.val_short(),
             5 => ekey.val_byte(),
-            _ => return Err(self.type_mismatch_error("i32")),
+            t => return Err(self.type_mismatch_error(t, "i32")),
         };
         visitor.visit_i32(value)
     }
@@ -111,7 +157,7 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
         let ekey = self.current_ekey()?;
         let value = match ekey.type_() {
             7 => ekey.val_uint64(),
-            _ => return Err(self.type_mismatch_error("u64")),
+            t => return Err(self.type_mismatch_error(t, "u64")),
         };
         visitor.visit_u64(value)
     }
@@ -120,7 +166,7 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
         let ekey = self.current_ekey()?;
         let value = match ekey.type_() {
             2 => ekey.val_float(),
-            _ => return Err(self.type_mismatch_error("f32")),
+            t => return Err(self.type_mismatch_error(t, "f32")),
         };
         visitor.visit_f32(value)
     }
@@ -129,7 +175,7 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
         let ekey = self.current_ekey()?;
         let value = match ekey.type_() {
             1 => ekey.val_string(),
-            _ => return Err(self.type_mismatch_error("str")),
+            t => return Err(self.type_mismatch_error(t, "str")),
         };
         visitor.visit_str(value)
     }
@@ -152,12 +198,9 @@ impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
         visitor.visit_enum(Enum { de: self })
     }
 
+    /// Deserializes the event name, used as enum variant.
     fn deserialize_identifier<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
-        if self.index == -1 {
-            visitor.visit_borrowed_str(self.descriptor.name.as_str())
-        } else {
-            visitor.visit_borrowed_str(self.descriptor.keys[self.index as usize].name.as_str())
-        }
+        visitor.visit_borrowed_str(self.descriptor.name.as_str())
     }
 
     fn deserialize_ignored_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
@@ -173,10 +216,11 @@ impl<'de> de::MapAccess<'de> for Deserializer<'de> {
         K: de::DeserializeSeed<'de>,
     {
         self.index += 1;
-        if self.index >= self.descriptor.keys.len() as i32 {
+        let Some(key) = self.descriptor.keys.get(self.index as usize) else {
             return Ok(None);
-        }
-        seed.deserialize(self).map(Some)
+        };
+        seed.deserialize(de::value::BorrowedStrDeserializer::new(key.name.as_str()))
+            .map(Some)
     }
 
     fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
@@ -226,7 +270,7 @@ impl<'de, 'a> de::VariantAccess<'de> for Enum<'a, 'de> {
     where
         V: de::Visitor<'de>,
     {
-        panic!("GameEvent deserializer internal error")
+        Err(Error::Unsupported("a tuple variant"))
     }
 
     fn struct_variant<V>(
@@ -237,6 +281,96 @@ impl<'de, 'a> de::VariantAccess<'de> for Enum<'a, 'de> {
     where
         V: de::Visitor<'de>,
     {
-        panic!("GameEvent deserializer internal error")
+        Err(Error::Unsupported("a struct variant"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    enum Event {
+        PlayerHurt(PlayerHurt),
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct PlayerHurt {
+        userid: i32,
+        userid_pawn: i32,
+        weapon: String,
+    }
+
+    fn descriptor() -> Descriptor {
+        let key = |name: &str, type_| DescriptorKey {
+            name: name.to_string(),
+            type_,
+        };
+        Descriptor {
+            eventid: 1,
+            name: "player_hurt".to_string(),
+            keys: vec![key("userid", 9), key("userid_pawn", 8), key("weapon", 1)],
+        }
+    }
+
+    fn event(keys: &[i32]) -> CMsgSource1LegacyGameEvent {
+        let mut event = CMsgSource1LegacyGameEvent::new();
+        for &type_ in keys {
+            let mut key = cmsg_source1legacy_game_event::Key_t::new();
+            key.set_type(type_);
+            match type_ {
+                1 => key.set_val_string("ak47".to_string()),
+                8 => key.set_val_long(1234),
+                9 => key.set_val_short(2),
+                _ => (),
+            }
+            event.keys.push(key);
+        }
+        event
+    }
+
+    #[test]
+    fn player_controller_keys() -> Result<()> {
+        let descriptor = descriptor();
+        let event: Event = from_proto(event(&[9, 8, 1]), &descriptor)?;
+        let expected = PlayerHurt {
+            userid: 2,
+            userid_pawn: 1234,
+            weapon: "ak47".to_string(),
+        };
+        assert_eq!(event, Event::PlayerHurt(expected));
+        Ok(())
+    }
+
+    #[test]
+    fn untyped_event() -> Result<()> {
+        let descriptor = Descriptor {
+            eventid: 2,
+            name: "weapon_fire".to_string(),
+            keys: vec![DescriptorKey {
+                name: "weapon".to_string(),
+                type_: 1,
+            }],
+        };
+        let event: HashMap<String, String> = from_proto(event(&[1]), &descriptor)?;
+        assert_eq!(
+            event,
+            HashMap::from([("weapon".to_string(), "ak47".to_string())])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_events() {
+        let descriptor = descriptor();
+        let result: Result<Event> = from_proto(event(&[9, 8]), &descriptor);
+        assert!(matches!(result, Err(Error::MissingKey { key, .. }) if key == "weapon"));
+        let result: Result<Event> = from_proto(event(&[9, 9, 1]), &descriptor);
+        assert!(matches!(result, Err(Error::DescriptorMismatch { .. })));
+        let result: Result<i32> = from_proto(event(&[9, 8, 1]), &descriptor);
+        assert!(matches!(result, Err(Error::Unsupported(_))));
     }
 }
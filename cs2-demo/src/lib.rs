@@ -4,20 +4,30 @@ mod error;
 pub mod game_event;
 mod message;
 mod packet;
+#[allow(renamed_and_removed_lints)]
 pub mod proto;
 mod read;
+mod rewrite;
+mod seek;
 mod string_table;
 #[cfg(test)]
 mod testdata;
 mod visit;
+mod write;
 
+pub use crate::demo_command::{read_file_info, read_send_tables};
 pub use crate::error::{Error, Result};
 pub use crate::game_event::GameEventDescriptors;
-pub use crate::string_table::{PlayerInfo, UserInfo};
-pub use crate::visit::{parse, Visitor};
+pub use crate::rewrite::{rewrite, Rewriter};
+pub use crate::seek::{DemoIndex, DemoSeeker};
+pub use crate::string_table::{PlayerInfo, StringTable, StringTableEntry, StringTables, UserInfo};
+pub use crate::visit::{parse, parse_with_options, ParseOptions, Visitor};
+pub use crate::write::{cut, DemoWriter};
 pub type Tick = i32;
 
-type BitReader<'a> = bitstream_io::BitReader<&'a [u8], bitstream_io::LittleEndian>;
+// Seekable so that the position of entity properties is known, see `EntityList::track_ranges`.
+type BitReader<'a> =
+    bitstream_io::BitReader<std::io::Cursor<&'a [u8]>, bitstream_io::LittleEndian>;
 
 #[allow(dead_code)]
 pub(crate) fn dump<M>(msg: &M, file: &str)
@@ -2,6 +2,7 @@ use std::fmt;
 use std::io::{Read, Seek, SeekFrom};
 
 use bitstream_io::BitRead;
+use demo_context::InContext;
 use paste::paste;
 use protobuf::Message as protobuf_Message;
 
@@ -39,36 +40,44 @@ macro_rules! create_message_impl {
                 $($(const [<$name:upper>]: u32 = $enum::[<$enum_prefix $name>] as u32;)*)*
                 let msg_type = reader.read_ubitvar()?;
                 let size = reader.read_varuint32()? as usize;
-                match msg_type {
+                let result = match msg_type {
                     $($(
-                        [<$name:upper>] => {
-                            let msg = [<$msg_prefix $name>]::parse_from_bytes(read_buffer(
-                                &mut buffer, size, reader)?)?;
-                            Ok(Message::$name(msg))
-                        }
+                        [<$name:upper>] => read_buffer(&mut buffer, size, reader)
+                            .and_then(|data| Ok([<$msg_prefix $name>]::parse_from_bytes(data)?))
+                            .map(Message::$name),
                     )*)*
                     _ => {
                         reader.seek_bits(SeekFrom::Current(size as i64 * 8))?;
                         Ok(Message::Unknown(msg_type))
                     }
+                };
+                result.map_err(|e| e.in_message(Self::type_name(msg_type)))
+            }
+
+            /// Returns the name of the message type, as in the proto enums.
+            pub(crate) fn type_name(t: u32) -> String {
+                use ::protobuf::Enum;
+                use ::protobuf::EnumFull;
+                $(
+                    if let Some(d) = $enum::from_i32(t as i32) {
+                        return d.descriptor().name().to_string();
+                    }
+                )*
+                "unknown".to_string()
+            }
+
+            pub(crate) fn name(&self) -> &'static str {
+                match self {
+                    Message::Unknown(_) => "Unknown",
+                    $($(Message::$name(_) => stringify!($name),)*)*
                 }
             }
         }
 
         impl fmt::Debug for Message {
             fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-                fn unknown_name(t: u32) -> String {
-                    use ::protobuf::Enum;
-                    use ::protobuf::EnumFull;
-                    $(
-                        if let Some(d) = $enum::from_i32(t as i32) {
-                            return d.descriptor().name().to_string();
-                        }
-                    )*
-                    "unknown".to_string()
-                }
                 match self {
-                    Message::Unknown(t) => write!(f, "Unknown({t}: {})", unknown_name(*t)),
+                    Message::Unknown(t) => write!(f, "Unknown({t}: {})", Self::type_name(*t)),
                     $($(Message::$name(m) => write!(f, "{}({})", stringify!($name), m),)*)*
                 }
             }
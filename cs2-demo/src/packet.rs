@@ -13,7 +13,7 @@ impl Packet {
         let mut buffer = Vec::with_capacity(1024);
         let mut messages = Vec::new();
         let mut reader = bitstream_io::BitReader::new(Cursor::new(packet.data()));
-        while reader.position_in_bits()? < packet.data().len() as u64 * 8 - 7 {
+        while reader.position_in_bits()? < (packet.data().len() as u64 * 8).saturating_sub(7) {
             messages.push(Message::try_new(&mut reader, &mut buffer)?);
         }
         Ok(Packet { messages })
@@ -1,22 +1,6 @@
 use bitstream_io::BitRead;
 use std::io;
 
-pub(crate) trait ReadExt: io::Read {
-    /// Read `size` bytes and convert them to a [`String`].
-    /// Every trailing null terminator will be removed.
-    fn read_string_limited(&mut self, size: usize) -> io::Result<String>;
-}
-
-impl<R: io::Read> ReadExt for R {
-    fn read_string_limited(&mut self, size: usize) -> io::Result<String> {
-        let mut buf: Vec<u8> = vec![0; size];
-        self.read_exact(buf.as_mut_slice())?;
-
-        let s = String::from_utf8_lossy(&buf).into_owned();
-        Ok(s.trim_end_matches('\0').to_string())
-    }
-}
-
 const COORD_INTEGER_BITS: u32 = 14;
 const COORD_FRACTIONAL_BITS: u32 = 5;
 const COORD_RESOLUTION: f32 = 1_f32 / (1 << COORD_FRACTIONAL_BITS) as f32;
@@ -153,16 +137,17 @@ fn zigzag_decode(n: u32) -> i32 {
 mod tests {
     use super::*;
     use crate::BitReader;
+    use std::io::Cursor;
 
     #[test]
     fn varint() {
-        let mut read = BitReader::new(&[0x01]);
+        let mut read = BitReader::new(Cursor::new([0x01].as_slice()));
         assert_eq!(read.read_varuint32().unwrap(), 1);
 
-        let mut read = BitReader::new(&[0x81, 0x23]);
+        let mut read = BitReader::new(Cursor::new([0x81, 0x23].as_slice()));
         assert_eq!(read.read_varuint32().unwrap(), 4481);
 
-        let mut read = BitReader::new(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        let mut read = BitReader::new(Cursor::new([0xFF, 0xFF, 0xFF, 0xFF, 0xFF].as_slice()));
         assert_eq!(read.read_varuint32().unwrap(), 4294967295);
     }
 
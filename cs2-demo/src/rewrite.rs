@@ -0,0 +1,578 @@
+use std::collections::VecDeque;
+use std::io::{Cursor, Read, Seek, Write};
+
+use bitstream_io::{BitRead, BitWrite, BitWriter, LittleEndian};
+use protobuf::{Enum, Message};
+
+use crate::demo_command::{DemoCommand, DemoParser};
+use crate::entity::{Property, PropertyRange, TreeEntity};
+use crate::game_event::{parse_game_event_list, Descriptor, GameEventDescriptors};
+use crate::proto::demo::{
+    CDemoFileHeader, CDemoFullPacket, CDemoPacket, CDemoStringTables, EDemoCommands,
+};
+use crate::proto::gameevents::{
+    CMsgSource1LegacyGameEvent, CMsgSource1LegacyGameEventList, EBaseGameEvents,
+};
+use crate::proto::netmessages::{
+    CSVCMsg_CreateStringTable, CSVCMsg_PacketEntities, CSVCMsg_ServerInfo,
+    CSVCMsg_UpdateStringTable, SVC_Messages,
+};
+use crate::proto::networkbasetypes::CMsgPlayerInfo;
+use crate::proto::usermessages::{CUserMessageSayText2, EBaseUserMessages};
+use crate::read::ValveBitReader;
+use crate::string_table::{
+    encode_string_table, parse_create_string_table, parse_update_string_table, StringTableInfo,
+    StringTableUpdate, INSTANCEBASELINE, USERINFO,
+};
+use crate::visit::DemoVisit;
+use crate::write::{DemoWriter, ValveBitWriter};
+use crate::{Error, ParseOptions, Result, Visitor};
+
+/// Hooks called by [`rewrite`] on the parts of a demo which can be modified in place.
+pub trait Rewriter {
+    fn rewrite_file_header(&mut self, _header: &mut CDemoFileHeader) {}
+    fn rewrite_server_info(&mut self, _server_info: &mut CSVCMsg_ServerInfo) {}
+    /// Called for each entry of the userinfo string table.
+    fn rewrite_player_info(&mut self, _player_info: &mut CMsgPlayerInfo) {}
+    fn rewrite_game_event(&mut self, _event: &mut CMsgSource1LegacyGameEvent, _: &Descriptor) {}
+    /// Called for chat messages.
+    fn rewrite_say_text2(&mut self, _msg: &mut CUserMessageSayText2) {}
+    /// Called for the string and 64-bit integer properties written by PacketEntities messages,
+    /// with the class name and property path. Entity properties are bit-packed and cannot be
+    /// re-encoded, so a new value must have the same type and encoded size.
+    fn rewrite_entity_property(&mut self, _class: &str, _path: &str, _value: &mut Property) {}
+}
+
+/// Only decodes the entities, to find where their properties are.
+struct EntityVisitor;
+
+impl Visitor for EntityVisitor {}
+
+/// Copies a demo from `read` to `write`, letting `rewriter` modify its content.
+pub fn rewrite<W: Write + Seek>(
+    read: &mut dyn Read,
+    write: W,
+    rewriter: &mut dyn Rewriter,
+) -> Result<W> {
+    let mut parser = DemoParser::try_new(read)?;
+    let mut writer = DemoWriter::try_new(write)?;
+    let mut packet_rewriter = PacketRewriter {
+        rewriter,
+        string_tables: Vec::new(),
+        game_event_descriptors: GameEventDescriptors::new(),
+        entity_ranges: VecDeque::new(),
+    };
+    let mut visitor = EntityVisitor;
+    let mut visit = DemoVisit::new(&mut visitor, &TreeEntity::factory, ParseOptions::default());
+    visit.entities.track_ranges();
+    // Instance baselines can only be decoded once the classes are known, the signon commands
+    // before ClassInfo are rewritten then.
+    let mut pending = Vec::new();
+    while let Some((tick, cmd, data)) = parser.read_next_raw_demo_command()? {
+        // Never breaks, EntityVisitor does not stop the parsing.
+        let _ = visit.visit_demo_command(tick, DemoCommand::try_new(cmd, &data)?)?;
+        pending.push((tick, cmd, data));
+        if visit.classes().is_none() {
+            continue;
+        }
+        packet_rewriter.entity_ranges = visit.entities.take_ranges().into();
+        for (tick, cmd, data) in pending.drain(..) {
+            let data = packet_rewriter.rewrite_command(cmd, data, &visit)?;
+            writer.write_demo_command(cmd, tick, &data)?;
+        }
+    }
+    for (tick, cmd, data) in pending {
+        let data = packet_rewriter.rewrite_command(cmd, data, &visit)?;
+        writer.write_demo_command(cmd, tick, &data)?;
+    }
+    writer.finish()
+}
+
+struct PacketRewriter<'a> {
+    rewriter: &'a mut dyn Rewriter,
+    /// String tables by creation order, like in UpdateStringTable.
+    string_tables: Vec<StringTableInfo>,
+    game_event_descriptors: GameEventDescriptors,
+    /// Properties of each PacketEntities message of the current demo command.
+    entity_ranges: VecDeque<Vec<PropertyRange>>,
+}
+
+impl<'a> PacketRewriter<'a> {
+    /// Returns the new content of a demo command. `visit` has parsed the demo up to this command,
+    /// or further for the signon commands.
+    fn rewrite_command(&mut self, cmd: u32, data: Vec<u8>, visit: &DemoVisit) -> Result<Vec<u8>> {
+        use EDemoCommands::*;
+        Ok(match EDemoCommands::from_i32(cmd as i32) {
+            Some(DEM_FileHeader) => {
+                let mut header = CDemoFileHeader::parse_from_bytes(&data)?;
+                self.rewriter.rewrite_file_header(&mut header);
+                header.write_to_bytes()?
+            }
+            Some(DEM_StringTables) => {
+                let mut string_tables = CDemoStringTables::parse_from_bytes(&data)?;
+                self.rewrite_string_tables(&mut string_tables, visit)?;
+                string_tables.write_to_bytes()?
+            }
+            Some(DEM_Packet) | Some(DEM_SignonPacket) => {
+                let mut packet = CDemoPacket::parse_from_bytes(&data)?;
+                self.rewrite_packet(&mut packet, visit)?;
+                packet.write_to_bytes()?
+            }
+            Some(DEM_FullPacket) => {
+                let mut full_packet = CDemoFullPacket::parse_from_bytes(&data)?;
+                self.rewrite_string_tables(
+                    full_packet.string_table.mut_or_insert_default(),
+                    visit,
+                )?;
+                self.rewrite_packet(full_packet.packet.mut_or_insert_default(), visit)?;
+                full_packet.write_to_bytes()?
+            }
+            _ => data,
+        })
+    }
+
+    fn rewrite_string_tables(
+        &mut self,
+        string_tables: &mut CDemoStringTables,
+        visit: &DemoVisit,
+    ) -> Result<()> {
+        for table in string_tables.tables.iter_mut() {
+            match table.table_name() {
+                USERINFO => {
+                    for item in table.items.iter_mut() {
+                        if !item.data().is_empty() {
+                            let data = self.rewrite_player_info(item.data())?;
+                            item.set_data(data);
+                        }
+                    }
+                }
+                INSTANCEBASELINE => {
+                    for item in table.items.iter_mut() {
+                        let mut data = item.take_data();
+                        self.rewrite_baseline(item.str(), &mut data, visit)?;
+                        item.set_data(data);
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Rewrites the properties of the instance baseline with the given key. Returns true if any
+    /// was changed.
+    fn rewrite_baseline(&mut self, key: &str, data: &mut [u8], visit: &DemoVisit) -> Result<bool> {
+        let Some(classes) = visit.classes() else {
+            return Ok(false);
+        };
+        let mut changed = false;
+        for range in visit.entities.baseline_ranges(classes, key, data)? {
+            changed |= self.rewrite_entity_property(data, range)?;
+        }
+        Ok(changed)
+    }
+
+    fn rewrite_baseline_updates(
+        &mut self,
+        updates: &mut [StringTableUpdate],
+        visit: &DemoVisit,
+    ) -> Result<bool> {
+        let table = visit.string_tables.get(INSTANCEBASELINE);
+        let mut changed = false;
+        for update in updates {
+            let key = match (&update.key, table) {
+                (Some(key), _) => key.clone(),
+                (None, Some(table)) => match table.entries().get(update.index) {
+                    Some(entry) => entry.key.clone(),
+                    None => continue,
+                },
+                (None, None) => continue,
+            };
+            if let Some(data) = update.data.as_mut() {
+                changed |= self.rewrite_baseline(&key, data, visit)?;
+            }
+        }
+        Ok(changed)
+    }
+
+    fn rewrite_player_info(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut player_info = CMsgPlayerInfo::parse_from_bytes(data)?;
+        self.rewriter.rewrite_player_info(&mut player_info);
+        Ok(player_info.write_to_bytes()?)
+    }
+
+    fn rewrite_userinfo_updates(&mut self, updates: &mut [StringTableUpdate]) -> Result<()> {
+        for update in updates {
+            if let Some(data) = update.data.as_mut().filter(|data| !data.is_empty()) {
+                *data = self.rewrite_player_info(data)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rewrite_packet(&mut self, packet: &mut CDemoPacket, visit: &DemoVisit) -> Result<()> {
+        let data = packet.data();
+        let mut reader = bitstream_io::BitReader::endian(Cursor::new(data), LittleEndian);
+        let mut writer = BitWriter::endian(Vec::with_capacity(data.len()), LittleEndian);
+        let mut buffer = Vec::new();
+        while reader.position_in_bits()? < (data.len() as u64 * 8).saturating_sub(7) {
+            let msg_type = reader.read_ubitvar()?;
+            let size = reader.read_varuint32()? as usize;
+            buffer.resize(size, 0);
+            reader.read_bytes(&mut buffer)?;
+            let rewritten = self.rewrite_message(msg_type, &buffer, visit)?;
+            let bytes = rewritten.as_deref().unwrap_or(&buffer);
+            writer.write_ubitvar(msg_type)?;
+            writer.write_varuint32(bytes.len() as u32)?;
+            writer.write_bytes(bytes)?;
+        }
+        writer.byte_align()?;
+        packet.set_data(writer.into_writer());
+        Ok(())
+    }
+
+    /// Rewrites the userinfo or instance baseline entries of a string table message. Returns
+    /// false if the message does not need to be re-encoded.
+    fn rewrite_updates(
+        &mut self,
+        info: &StringTableInfo,
+        updates: &mut [StringTableUpdate],
+        visit: &DemoVisit,
+    ) -> Result<bool> {
+        if info.name == USERINFO {
+            self.rewrite_userinfo_updates(updates)?;
+            Ok(true)
+        } else {
+            self.rewrite_baseline_updates(updates, visit)
+        }
+    }
+
+    /// Lets the rewriter replace a property in `entity_data`, in place. Returns true if it was
+    /// changed.
+    fn rewrite_entity_property(
+        &mut self,
+        entity_data: &mut [u8],
+        range: PropertyRange,
+    ) -> Result<bool> {
+        let mut value = range.value.clone();
+        self.rewriter
+            .rewrite_entity_property(&range.class, &range.path, &mut value);
+        let bytes = match (&range.value, &value) {
+            (Property::Str(old), Property::Str(new)) if old != new => {
+                [new.as_bytes(), &[0]].concat()
+            }
+            (Property::U64(old), Property::U64(new)) if old != new => {
+                let mut writer = BitWriter::endian(Vec::new(), LittleEndian);
+                if range.fixed64 {
+                    writer.write(64, *new)?;
+                } else {
+                    writer.write_varuint64(*new)?;
+                }
+                writer.into_writer()
+            }
+            (Property::Str(_), Property::Str(_)) | (Property::U64(_), Property::U64(_)) => {
+                return Ok(false)
+            }
+            _ => return Err(Error::RewrittenPropertySize(range.path)),
+        };
+        if bytes.len() as u64 * 8 != range.bits.end - range.bits.start {
+            return Err(Error::RewrittenPropertySize(range.path));
+        }
+        for (i, &byte) in bytes.iter().enumerate() {
+            write_byte(entity_data, range.bits.start as usize + i * 8, byte);
+        }
+        Ok(true)
+    }
+
+    /// Returns the new content of the message, or None if it is unchanged.
+    fn rewrite_message(
+        &mut self,
+        msg_type: u32,
+        bytes: &[u8],
+        visit: &DemoVisit,
+    ) -> Result<Option<Vec<u8>>> {
+        const CLEAR_ALL_STRING_TABLES: u32 = SVC_Messages::svc_ClearAllStringTables as u32;
+        const CREATE_STRING_TABLE: u32 = SVC_Messages::svc_CreateStringTable as u32;
+        const UPDATE_STRING_TABLE: u32 = SVC_Messages::svc_UpdateStringTable as u32;
+        const PACKET_ENTITIES: u32 = SVC_Messages::svc_PacketEntities as u32;
+        const SERVER_INFO: u32 = SVC_Messages::svc_ServerInfo as u32;
+        const GAME_EVENT_LIST: u32 = EBaseGameEvents::GE_Source1LegacyGameEventList as u32;
+        const GAME_EVENT: u32 = EBaseGameEvents::GE_Source1LegacyGameEvent as u32;
+        const SAY_TEXT2: u32 = EBaseUserMessages::UM_SayText2 as u32;
+        Ok(match msg_type {
+            CLEAR_ALL_STRING_TABLES => {
+                self.string_tables.clear();
+                None
+            }
+            CREATE_STRING_TABLE => {
+                let mut msg = CSVCMsg_CreateStringTable::parse_from_bytes(bytes)?;
+                let info = StringTableInfo::from(&msg);
+                self.string_tables.push(info.clone());
+                if info.name != USERINFO && info.name != INSTANCEBASELINE {
+                    return Ok(None);
+                }
+                let (_, mut updates) = parse_create_string_table(msg.clone())?;
+                if !self.rewrite_updates(&info, &mut updates, visit)? {
+                    return Ok(None);
+                }
+                let string_data = encode_string_table(&info, &updates)?;
+                msg.set_uncompressed_size(string_data.len() as i32);
+                msg.set_data_compressed(false);
+                msg.set_string_data(string_data);
+                Some(msg.write_to_bytes()?)
+            }
+            UPDATE_STRING_TABLE => {
+                let mut msg = CSVCMsg_UpdateStringTable::parse_from_bytes(bytes)?;
+                let info = self
+                    .string_tables
+                    .get(msg.table_id() as usize)
+                    .ok_or(Error::InvalidStringTableId)?
+                    .clone();
+                if info.name != USERINFO && info.name != INSTANCEBASELINE {
+                    return Ok(None);
+                }
+                let mut updates = parse_update_string_table(msg.clone(), &info)?;
+                if !self.rewrite_updates(&info, &mut updates, visit)? {
+                    return Ok(None);
+                }
+                msg.set_string_data(encode_string_table(&info, &updates)?);
+                Some(msg.write_to_bytes()?)
+            }
+            PACKET_ENTITIES => {
+                let mut msg = CSVCMsg_PacketEntities::parse_from_bytes(bytes)?;
+                let ranges = self.entity_ranges.pop_front().unwrap_or_default();
+                if ranges.is_empty() {
+                    return Ok(None);
+                }
+                let mut entity_data = msg.take_entity_data();
+                let mut changed = false;
+                for range in ranges {
+                    changed |= self.rewrite_entity_property(&mut entity_data, range)?;
+                }
+                if !changed {
+                    return Ok(None);
+                }
+                msg.set_entity_data(entity_data);
+                Some(msg.write_to_bytes()?)
+            }
+            SERVER_INFO => {
+                let mut msg = CSVCMsg_ServerInfo::parse_from_bytes(bytes)?;
+                self.rewriter.rewrite_server_info(&mut msg);
+                Some(msg.write_to_bytes()?)
+            }
+            GAME_EVENT_LIST => {
+                let msg = CMsgSource1LegacyGameEventList::parse_from_bytes(bytes)?;
+                self.game_event_descriptors = parse_game_event_list(msg);
+                None
+            }
+            GAME_EVENT => {
+                let mut msg = CMsgSource1LegacyGameEvent::parse_from_bytes(bytes)?;
+                let Some(descriptor) = self.game_event_descriptors.get(&msg.eventid()) else {
+                    return Ok(None);
+                };
+                self.rewriter.rewrite_game_event(&mut msg, descriptor);
+                Some(msg.write_to_bytes()?)
+            }
+            SAY_TEXT2 => {
+                let mut msg = CUserMessageSayText2::parse_from_bytes(bytes)?;
+                self.rewriter.rewrite_say_text2(&mut msg);
+                Some(msg.write_to_bytes()?)
+            }
+            _ => None,
+        })
+    }
+}
+
+/// Writes the 8 bits starting at `bit`, least significant bit first.
+fn write_byte(data: &mut [u8], bit: usize, byte: u8) {
+    let (index, shift) = (bit / 8, bit % 8);
+    if shift == 0 {
+        data[index] = byte;
+    } else {
+        data[index] = (data[index] & (0xff >> (8 - shift))) | (byte << shift);
+        data[index + 1] = (data[index + 1] & (0xff << shift)) | (byte >> (8 - shift));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::demo::cdemo_string_tables;
+    use crate::testdata;
+
+    struct RenamePlayers;
+
+    impl Rewriter for RenamePlayers {
+        fn rewrite_file_header(&mut self, header: &mut CDemoFileHeader) {
+            header.set_server_name("server".to_string());
+        }
+
+        fn rewrite_player_info(&mut self, player_info: &mut CMsgPlayerInfo) {
+            player_info.set_name("player".to_string());
+        }
+    }
+
+    #[test]
+    fn rewrite_demo() -> Result<()> {
+        use EDemoCommands::*;
+        let mut header = CDemoFileHeader::new();
+        header.set_demo_file_stamp("PBDEMS2".to_string());
+        header.set_server_name("Valve Counter-Strike 2 eu_west Server".to_string());
+        let mut player_info = CMsgPlayerInfo::new();
+        player_info.set_name("someone".to_string());
+        player_info.set_xuid(76561197960265729);
+        let mut item = cdemo_string_tables::Items_t::new();
+        item.set_str("0".to_string());
+        item.set_data(player_info.write_to_bytes()?);
+        let mut table = cdemo_string_tables::Table_t::new();
+        table.set_table_name(USERINFO.to_string());
+        table.items.push(item);
+        let mut string_tables = CDemoStringTables::new();
+        string_tables.tables.push(table);
+        let demo = testdata::demo(vec![
+            (DEM_FileHeader, -1, header.write_to_bytes()?),
+            (DEM_StringTables, 0, string_tables.write_to_bytes()?),
+            (DEM_Packet, 1, CDemoPacket::new().write_to_bytes()?),
+            (DEM_Stop, 1, vec![]),
+        ]);
+
+        let out = rewrite(
+            &mut demo.as_slice(),
+            Cursor::new(Vec::new()),
+            &mut RenamePlayers,
+        )?;
+
+        let out = out.into_inner();
+        let mut read = out.as_slice();
+        let mut parser = DemoParser::try_new(&mut read)?;
+        let (_, _, data) = parser.read_next_raw_demo_command()?.unwrap();
+        let header = CDemoFileHeader::parse_from_bytes(&data)?;
+        assert_eq!(header.server_name(), "server");
+        let (_, _, data) = parser.read_next_raw_demo_command()?.unwrap();
+        let string_tables = CDemoStringTables::parse_from_bytes(&data)?;
+        let player_info =
+            CMsgPlayerInfo::parse_from_bytes(string_tables.tables[0].items[0].data())?;
+        assert_eq!(player_info.name(), "player");
+        assert_eq!(player_info.xuid(), 76561197960265729);
+        let (tick, cmd, _) = parser.read_next_raw_demo_command()?.unwrap();
+        assert_eq!((tick, cmd), (1, DEM_Packet as u32));
+        Ok(())
+    }
+
+    struct RenameEntity;
+
+    impl Rewriter for RenameEntity {
+        fn rewrite_entity_property(&mut self, class: &str, path: &str, value: &mut Property) {
+            match (class, path, value) {
+                ("CCSPlayerController", "m_iszPlayerName", Property::Str(name))
+                    if &**name == "kiralytomi" =>
+                {
+                    *name = "KIRALYTOMI".into()
+                }
+                ("CCSPlayerController", "m_steamID", Property::U64(xuid)) => *xuid += 1,
+                _ => (),
+            }
+        }
+    }
+
+    /// Returns the properties of every entity at the end of the demo.
+    fn entity_state(demo: &[u8]) -> Result<Vec<Vec<(String, String)>>> {
+        let mut read = demo;
+        let mut parser = DemoParser::try_new(&mut read)?;
+        let mut visitor = EntityVisitor;
+        let mut visit = DemoVisit::new(&mut visitor, &TreeEntity::factory, Default::default());
+        while let Some((tick, cmd, data)) = parser.read_next_raw_demo_command()? {
+            let _ = visit.visit_demo_command(tick, DemoCommand::try_new(cmd, &data)?)?;
+        }
+        let entities = &visit.entities;
+        Ok((0..entities.len())
+            .filter_map(|id| entities.get(id))
+            .map(|entity| {
+                let properties = entity.properties();
+                properties
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect()
+            })
+            .collect())
+    }
+
+    #[test]
+    fn rewrite_entity_properties() -> Result<()> {
+        use EDemoCommands::*;
+        let entities = testdata::entities_packet(&testdata::packet_entities());
+        let demo = testdata::demo(vec![
+            (DEM_SendTables, 0, testdata::send_tables().write_to_bytes()?),
+            (DEM_ClassInfo, 0, testdata::class_info().write_to_bytes()?),
+            (
+                DEM_StringTables,
+                0,
+                testdata::string_tables().write_to_bytes()?,
+            ),
+            (DEM_Packet, 1, entities.write_to_bytes()?),
+            (DEM_Stop, 1, vec![]),
+        ]);
+
+        let out = rewrite(
+            &mut demo.as_slice(),
+            Cursor::new(Vec::new()),
+            &mut RenameEntity,
+        )?;
+
+        let mut expected = entity_state(&demo)?;
+        let mut changed = 0;
+        for (name, value) in expected.iter_mut().flatten() {
+            if name == "m_iszPlayerName" && value == "kiralytomi" {
+                *value = "KIRALYTOMI".to_string();
+                changed += 1;
+            } else if name == "m_steamID" {
+                *value = (value.parse::<u64>().unwrap() + 1).to_string();
+                changed += 1;
+            }
+        }
+        assert_eq!(changed, 12);
+        let actual = entity_state(&out.into_inner())?;
+        for (a, e) in actual.iter().flatten().zip(expected.iter().flatten()) {
+            if a != e {
+                println!("DIFF {a:?} {e:?}");
+            }
+        }
+        assert_eq!(actual, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn rewrite_packet_keeps_messages() -> Result<()> {
+        let mut writer = BitWriter::endian(Vec::new(), LittleEndian);
+        let create_string_table = testdata::create_string_table().write_to_bytes()?;
+        writer.write_ubitvar(SVC_Messages::svc_CreateStringTable as u32)?;
+        writer.write_varuint32(create_string_table.len() as u32)?;
+        writer.write_bytes(&create_string_table)?;
+        let mut update_string_table = testdata::update_string_table();
+        update_string_table.set_table_id(0);
+        let update_string_table = update_string_table.write_to_bytes()?;
+        writer.write_ubitvar(SVC_Messages::svc_UpdateStringTable as u32)?;
+        writer.write_varuint32(update_string_table.len() as u32)?;
+        writer.write_bytes(&update_string_table)?;
+        writer.byte_align()?;
+        let data = writer.into_writer();
+        let mut packet = CDemoPacket::new();
+        packet.set_data(data.clone());
+
+        struct Noop;
+        impl Rewriter for Noop {}
+        let mut packet_rewriter = PacketRewriter {
+            rewriter: &mut Noop,
+            string_tables: Vec::new(),
+            game_event_descriptors: GameEventDescriptors::new(),
+            entity_ranges: VecDeque::new(),
+        };
+        let mut visitor = EntityVisitor;
+        let visit = DemoVisit::new(&mut visitor, &TreeEntity::factory, Default::default());
+        packet_rewriter.rewrite_packet(&mut packet, &visit)?;
+
+        assert_eq!(packet.data(), data);
+        assert_eq!(packet_rewriter.string_tables.len(), 1);
+        Ok(())
+    }
+}
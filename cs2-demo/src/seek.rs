@@ -0,0 +1,214 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::demo_command::DemoParser;
+use crate::entity::{EntityFactory, EntityList};
+use crate::proto::demo::EDemoCommands;
+use crate::visit::{DemoVisit, ParseOptions, VisitState};
+use crate::{Result, StringTables, Tick, Visitor};
+
+/// Locations of the commands needed for random access to a demo.
+#[derive(Debug)]
+pub struct DemoIndex {
+    /// Offset of the first demo command after the signon data.
+    signon_end: u64,
+    /// Tick and offset of every FullPacket, ordered by tick.
+    full_packets: Vec<(Tick, u64)>,
+}
+
+impl DemoIndex {
+    /// Builds the index by reading all demo command headers. The content of the commands is
+    /// skipped without being decoded.
+    pub fn try_new(read: &mut dyn Read) -> Result<Self> {
+        let mut parser = DemoParser::try_new(read)?;
+        let header_end = parser.offset();
+        let mut signon_end = None;
+        let mut full_packets = Vec::new();
+        loop {
+            let offset = parser.offset();
+            let Some((tick, cmd)) = parser.skip_next_demo_command()? else {
+                break;
+            };
+            match cmd {
+                c if c == EDemoCommands::DEM_SyncTick as u32 && signon_end.is_none() => {
+                    signon_end = Some(parser.offset())
+                }
+                c if c == EDemoCommands::DEM_FullPacket as u32 => full_packets.push((tick, offset)),
+                _ => (),
+            }
+        }
+        Ok(Self {
+            signon_end: signon_end.unwrap_or(header_end),
+            full_packets,
+        })
+    }
+
+    /// Returns the ticks of all FullPacket commands.
+    pub fn full_packet_ticks(&self) -> impl Iterator<Item = Tick> + '_ {
+        self.full_packets.iter().map(|&(tick, _)| tick)
+    }
+
+    /// Returns the tick and offset of the last FullPacket at or before `tick`.
+    fn full_packet_before(&self, tick: Tick) -> Option<(Tick, u64)> {
+        let pos = self.full_packets.partition_point(|&(t, _)| t <= tick);
+        pos.checked_sub(1).map(|i| self.full_packets[i])
+    }
+}
+
+/// Parses a demo at arbitrary ticks.
+///
+/// FullPacket commands contain the complete string tables and entity state, so seeking to a tick
+/// only needs to parse the commands between the closest FullPacket and that tick.
+pub struct DemoSeeker<'a, R: Read + Seek> {
+    read: R,
+    index: DemoIndex,
+    visit: DemoVisit<'a>,
+    /// State after the signon data, restored before replaying from an earlier tick.
+    signon_state: VisitState,
+    /// Offset of the next demo command to parse.
+    offset: u64,
+    /// Tick the demo state corresponds to.
+    tick: Tick,
+}
+
+impl<'a, R: Read + Seek> DemoSeeker<'a, R> {
+    /// Parses the signon data, which is needed no matter which tick is requested.
+    pub fn try_new(
+        mut read: R,
+        index: DemoIndex,
+        visitor: &'a mut dyn Visitor,
+        entity_factory: EntityFactory,
+    ) -> Result<Self> {
+        read.seek(SeekFrom::Start(0))?;
+        let mut visit = DemoVisit::new(visitor, entity_factory, ParseOptions::default());
+        let offset = {
+            let mut parser = DemoParser::try_new(&mut read)?;
+            while parser.offset() < index.signon_end {
+                let Some((tick, cmd)) = parser.parse_next_demo_command()? else {
+                    break;
+                };
+                if visit.visit_demo_command(tick, cmd)?.is_break() {
+                    break;
+                }
+            }
+            parser.offset()
+        };
+        Ok(Self {
+            read,
+            index,
+            signon_state: visit.state(),
+            visit,
+            offset,
+            tick: Tick::MIN,
+        })
+    }
+
+    /// Updates the demo state to `tick`. All the demo commands replayed are sent to the visitor.
+    pub fn seek(&mut self, tick: Tick) -> Result<()> {
+        let full_packet = self.index.full_packet_before(tick);
+        let replay_from_current =
+            tick >= self.tick && full_packet.is_none_or(|(_, offset)| offset <= self.offset);
+        if !replay_from_current {
+            // String tables and baselines updated after the FullPacket must not be kept.
+            self.visit.restore(&self.signon_state);
+            self.offset = full_packet.map_or(self.index.signon_end, |(_, offset)| offset);
+        }
+        self.read.seek(SeekFrom::Start(self.offset))?;
+        let mut parser = DemoParser::new_at_offset(&mut self.read, self.offset);
+        while let Some((cmd_tick, cmd)) = parser.parse_next_demo_command()? {
+            if cmd_tick > tick {
+                break;
+            }
+            if self.visit.visit_demo_command(cmd_tick, cmd)?.is_break() {
+                break;
+            }
+            self.offset = parser.offset();
+        }
+        self.tick = tick;
+        Ok(())
+    }
+
+    /// Returns the tick of the current demo state.
+    pub fn tick(&self) -> Tick {
+        self.tick
+    }
+
+    pub fn entities(&self) -> &EntityList {
+        &self.visit.entities
+    }
+
+    pub fn string_tables(&self) -> &StringTables {
+        &self.visit.string_tables
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::TreeEntity;
+    use crate::proto::demo::CDemoFullPacket;
+    use crate::testdata;
+    use crate::UserInfo;
+    use protobuf::Message;
+    use std::io::Cursor;
+
+    #[derive(Default)]
+    struct UserInfoCounter {
+        count: usize,
+    }
+
+    impl Visitor for UserInfoCounter {
+        fn visit_userinfo_table(&mut self, _user_info: Vec<UserInfo>) -> anyhow::Result<()> {
+            self.count += 1;
+            Ok(())
+        }
+    }
+
+    fn full_packet() -> Vec<u8> {
+        let mut full_packet = CDemoFullPacket::new();
+        full_packet.string_table = Some(testdata::string_tables()).into();
+        full_packet.packet = Some(testdata::entities_packet(&testdata::packet_entities())).into();
+        full_packet.write_to_bytes().unwrap()
+    }
+
+    #[test]
+    fn seek() -> Result<()> {
+        use EDemoCommands::*;
+        // A single message with type 0 and size 0.
+        let empty_packet = testdata::packet(&[(0, vec![])]).write_to_bytes()?;
+        // Entity 1 is a CCSPlayerController.
+        let delete_packet = testdata::entities_packet(&testdata::delete_entities(&[1]));
+        let demo = testdata::demo(vec![
+            (DEM_SendTables, 0, testdata::send_tables().write_to_bytes()?),
+            (DEM_ClassInfo, 0, testdata::class_info().write_to_bytes()?),
+            (DEM_SyncTick, 0, vec![]),
+            (DEM_Packet, 1, empty_packet),
+            (DEM_FullPacket, 10, full_packet()),
+            (DEM_Packet, 11, delete_packet.write_to_bytes()?),
+            (DEM_FullPacket, 20, full_packet()),
+            (DEM_Stop, 30, vec![]),
+        ]);
+        let index = DemoIndex::try_new(&mut demo.as_slice())?;
+        assert_eq!(index.full_packet_ticks().collect::<Vec<_>>(), vec![10, 20]);
+
+        let mut visitor = UserInfoCounter::default();
+        let mut seeker =
+            DemoSeeker::try_new(Cursor::new(demo), index, &mut visitor, &TreeEntity::factory)?;
+        let mut seek = |tick| -> Result<(bool, bool)> {
+            seeker.seek(tick)?;
+            assert_eq!(seeker.tick(), tick);
+            let has_tables = seeker.string_tables().iter().next().is_some();
+            Ok((seeker.entities().get(1).is_some(), has_tables))
+        };
+        // Restores from the FullPacket at tick 10, then deletes entity 1.
+        assert_eq!(seek(15)?, (false, true));
+        // Continues from tick 15 up to the FullPacket at tick 20.
+        assert_eq!(seek(25)?, (true, true));
+        // Restores from the FullPacket at tick 10.
+        assert_eq!(seek(12)?, (false, true));
+        // Replays from the start because there is no FullPacket before tick 5.
+        assert_eq!(seek(5)?, (false, false));
+        assert!(seeker.entities().is_empty());
+        assert_eq!(visitor.count, 3);
+        Ok(())
+    }
+}
@@ -1,12 +1,14 @@
 use std::collections::VecDeque;
+use std::io::Cursor;
 
-use bitstream_io::BitRead;
+use bitstream_io::{BitRead, BitWrite, BitWriter, LittleEndian};
 use protobuf::Message;
 
 use crate::proto::demo::cdemo_string_tables;
 use crate::proto::netmessages::{CSVCMsg_CreateStringTable, CSVCMsg_UpdateStringTable};
 use crate::proto::networkbasetypes::CMsgPlayerInfo;
 use crate::read::ValveBitReader;
+use crate::write::ValveBitWriter;
 use crate::{BitReader, Error, Result};
 
 #[derive(Debug)]
@@ -25,20 +27,12 @@ pub struct UserInfo {
 }
 
 impl UserInfo {
-    pub(super) fn try_new(
-        string_table_item: &cdemo_string_tables::Items_t,
-    ) -> Result<Option<Self>> {
-        let Some(ref data) = string_table_item.data else {
-            return Ok(None);
-        };
-        let index = string_table_item
-            .str()
-            .parse()
-            .or(Err(Error::InvalidPlayerIndex))?;
-        let msg = CMsgPlayerInfo::parse_from_bytes(data)?;
-        if msg.ishltv() || msg.fakeplayer() {
+    pub(super) fn try_new(entry: &StringTableEntry) -> Result<Option<Self>> {
+        if entry.data.is_empty() {
             return Ok(None);
         }
+        let index = entry.key.parse().or(Err(Error::InvalidPlayerIndex))?;
+        let msg = CMsgPlayerInfo::parse_from_bytes(&entry.data)?;
         Ok(Some(UserInfo {
             index,
             info: PlayerInfo {
@@ -52,16 +46,147 @@ impl UserInfo {
     }
 }
 
-pub(crate) fn parse_userinfo(table: &cdemo_string_tables::Table_t) -> Result<Vec<UserInfo>> {
+/// Returns the players found in the given entries of the userinfo table.
+pub(crate) fn parse_userinfo(table: &StringTable, entries: &[usize]) -> Result<Vec<UserInfo>> {
     let mut user_infos = Vec::new();
-    for item in &table.items {
-        if let Some(user_info) = UserInfo::try_new(item)? {
+    for &index in entries {
+        if let Some(user_info) = UserInfo::try_new(&table.entries[index])? {
             user_infos.push(user_info);
         }
     }
     Ok(user_infos)
 }
 
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StringTableEntry {
+    pub key: String,
+    pub data: Vec<u8>,
+}
+
+/// A string table, as currently known by the parser.
+#[derive(Clone, Debug)]
+pub struct StringTable {
+    name: String,
+    entries: Vec<StringTableEntry>,
+    /// Missing for tables only seen in CDemoStringTables, which cannot receive updates.
+    info: Option<StringTableInfo>,
+}
+
+impl StringTable {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn entries(&self) -> &[StringTableEntry] {
+        &self.entries
+    }
+
+    pub fn get(&self, key: &str) -> Option<&StringTableEntry> {
+        self.entries.iter().find(|e| e.key == key)
+    }
+
+    /// Applies `updates` and returns the indices of the modified entries.
+    fn apply(&mut self, updates: Vec<StringTableUpdate>) -> Result<Vec<usize>> {
+        let mut changed = Vec::with_capacity(updates.len());
+        for update in updates {
+            if update.index >= MAX_ENTRIES {
+                return Err(Error::InvalidStringTableIndex(update.index));
+            }
+            if update.index >= self.entries.len() {
+                self.entries
+                    .resize_with(update.index + 1, StringTableEntry::default);
+            }
+            let entry = &mut self.entries[update.index];
+            if let Some(key) = update.key {
+                entry.key = key;
+            }
+            if let Some(data) = update.data {
+                entry.data = data;
+            }
+            changed.push(update.index);
+        }
+        Ok(changed)
+    }
+}
+
+/// All the string tables of a demo, indexed by creation order like in UpdateStringTable.
+#[derive(Clone, Default, Debug)]
+pub struct StringTables {
+    tables: Vec<StringTable>,
+}
+
+impl StringTables {
+    pub fn get(&self, name: &str) -> Option<&StringTable> {
+        self.tables.iter().find(|t| t.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &StringTable> {
+        self.tables.iter()
+    }
+
+    pub(crate) fn table(&self, table_id: usize) -> &StringTable {
+        &self.tables[table_id]
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.tables.clear();
+    }
+
+    /// Adds the table created by `msg` and returns its id and the indices of its entries.
+    pub(crate) fn create(&mut self, msg: CSVCMsg_CreateStringTable) -> Result<(usize, Vec<usize>)> {
+        let (info, updates) = parse_create_string_table(msg)?;
+        let mut table = StringTable {
+            name: info.name.clone(),
+            entries: Vec::new(),
+            info: Some(info),
+        };
+        let changed = table.apply(updates)?;
+        self.tables.push(table);
+        Ok((self.tables.len() - 1, changed))
+    }
+
+    /// Applies `msg` and returns the table id and the indices of the modified entries.
+    pub(crate) fn update(&mut self, msg: CSVCMsg_UpdateStringTable) -> Result<(usize, Vec<usize>)> {
+        let table_id = msg.table_id() as usize;
+        let table = self
+            .tables
+            .get_mut(table_id)
+            .ok_or(Error::InvalidStringTableId)?;
+        let info = table.info.as_ref().ok_or(Error::InvalidStringTableId)?;
+        let updates = parse_update_string_table(msg, info)?;
+        Ok((table_id, table.apply(updates)?))
+    }
+
+    /// Replaces the content of a table with the snapshot found in CDemoStringTables and returns
+    /// its id.
+    pub(crate) fn replace(&mut self, mut snapshot: cdemo_string_tables::Table_t) -> usize {
+        let entries = snapshot
+            .items
+            .iter_mut()
+            .map(|item| StringTableEntry {
+                key: item.take_str(),
+                data: item.take_data(),
+            })
+            .collect();
+        let name = snapshot.take_table_name();
+        match self.tables.iter().position(|t| t.name == name) {
+            Some(table_id) => {
+                self.tables[table_id].entries = entries;
+                table_id
+            }
+            None => {
+                self.tables.push(StringTable {
+                    name,
+                    entries,
+                    info: None,
+                });
+                self.tables.len() - 1
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub(crate) struct StringTableInfo {
     pub(crate) name: String,
     user_data_size: i32,
@@ -70,25 +195,44 @@ pub(crate) struct StringTableInfo {
     using_varint_bitcounts: bool,
 }
 
+impl From<&CSVCMsg_CreateStringTable> for StringTableInfo {
+    fn from(msg: &CSVCMsg_CreateStringTable) -> Self {
+        Self {
+            name: msg.name().to_string(),
+            user_data_size: msg.user_data_size(),
+            user_data_fixed_size: msg.user_data_fixed_size(),
+            flags: msg.flags(),
+            using_varint_bitcounts: msg.using_varint_bitcounts(),
+        }
+    }
+}
+
+/// Maximum number of entries of a string table. The indices of updates are read from the demo,
+/// this bounds the memory allocated for corrupt ones. Source 1 encodes entry indices with at most
+/// 16 bits and CS2 tables are much smaller.
+const MAX_ENTRIES: usize = 1 << 16;
+
 pub(crate) const INSTANCEBASELINE: &str = "instancebaseline";
 pub(crate) const USERINFO: &str = "userinfo";
 pub(crate) type StringTableData = Vec<(String, Vec<u8>)>;
 
+/// A new or modified string table entry. Missing fields are unchanged.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct StringTableUpdate {
+    pub(crate) index: usize,
+    pub(crate) key: Option<String>,
+    pub(crate) data: Option<Vec<u8>>,
+}
+
 pub(crate) fn parse_create_string_table(
     mut msg: CSVCMsg_CreateStringTable,
-) -> Result<(StringTableInfo, StringTableData)> {
+) -> Result<(StringTableInfo, Vec<StringTableUpdate>)> {
     let bytes = if msg.data_compressed() {
         snap::raw::Decoder::new().decompress_vec(msg.string_data())?
     } else {
         msg.take_string_data()
     };
-    let info = StringTableInfo {
-        name: msg.take_name(),
-        user_data_size: msg.user_data_size(),
-        user_data_fixed_size: msg.user_data_fixed_size(),
-        flags: msg.flags(),
-        using_varint_bitcounts: msg.using_varint_bitcounts(),
-    };
+    let info = StringTableInfo::from(&msg);
     let data = parse_string_table(&info, bytes, msg.num_entries())?;
     Ok((info, data))
 }
@@ -96,7 +240,7 @@ pub(crate) fn parse_create_string_table(
 pub(crate) fn parse_update_string_table(
     mut msg: CSVCMsg_UpdateStringTable,
     info: &StringTableInfo,
-) -> Result<StringTableData> {
+) -> Result<Vec<StringTableUpdate>> {
     parse_string_table(info, msg.take_string_data(), msg.num_changed_entries())
 }
 
@@ -104,17 +248,20 @@ fn parse_string_table(
     info: &StringTableInfo,
     bytes: Vec<u8>,
     num_entries: i32,
-) -> Result<StringTableData> {
-    let mut reader = BitReader::new(&bytes);
-    let mut _index = 0;
+) -> Result<Vec<StringTableUpdate>> {
+    let mut reader = BitReader::new(Cursor::new(bytes.as_slice()));
+    let mut index = -1;
     let mut history: VecDeque<String> = VecDeque::new();
-    let mut items = StringTableData::new();
+    let mut items = Vec::new();
     for _ in 0..num_entries {
-        if !reader.read_bit()? {
-            _index += reader.read_varuint32()? as i32
+        if reader.read_bit()? {
+            index += 1;
+        } else {
+            index = reader.read_varuint32()? as i64 + 1;
         }
+        let mut key = None;
         if reader.read_bit()? {
-            let key = if !reader.read_bit()? {
+            let k = if !reader.read_bit()? {
                 reader.read_string()?
             } else {
                 let position = reader.read::<u32>(5)? as usize;
@@ -130,38 +277,82 @@ fn parse_string_table(
             if history.len() >= 32 {
                 history.pop_front();
             }
-            history.push_back(key.clone());
-
-            let value = if reader.read_bit()? {
-                let mut is_compressed = false;
-                let bits = if info.user_data_fixed_size {
-                    info.user_data_size as u32
-                } else {
-                    if (info.flags & 0x1) != 0 {
-                        is_compressed = reader.read_bit()?;
-                    }
-                    if info.using_varint_bitcounts {
-                        reader.read_ubitvar()? * 8
-                    } else {
-                        reader.read::<u32>(17)? * 8
-                    }
-                };
-                let value = reader.read_to_vec((bits / 8) as usize)?;
-                if is_compressed {
-                    snap::raw::Decoder::new().decompress_vec(&value)?
+            history.push_back(k.clone());
+            key = Some(k);
+        }
+        let mut data = None;
+        if reader.read_bit()? {
+            let mut is_compressed = false;
+            let bits = if info.user_data_fixed_size {
+                info.user_data_size as u32
+            } else {
+                if (info.flags & 0x1) != 0 {
+                    is_compressed = reader.read_bit()?;
+                }
+                if info.using_varint_bitcounts {
+                    reader.read_ubitvar()? * 8
                 } else {
-                    value
+                    reader.read::<u32>(17)? * 8
                 }
-            } else {
-                Vec::new()
             };
-            items.push((key, value));
+            let value = reader.read_to_vec((bits / 8) as usize)?;
+            data = Some(if is_compressed {
+                snap::raw::Decoder::new().decompress_vec(&value)?
+            } else {
+                value
+            });
         }
-        _index += 1;
+        items.push(StringTableUpdate {
+            index: index as usize,
+            key,
+            data,
+        });
     }
     Ok(items)
 }
 
+/// Encodes `updates` in the format read by [`parse_string_table`]. Keys are written in full and
+/// values are not compressed.
+pub(crate) fn encode_string_table(
+    info: &StringTableInfo,
+    updates: &[StringTableUpdate],
+) -> Result<Vec<u8>> {
+    let mut writer = BitWriter::endian(Vec::new(), LittleEndian);
+    let mut index = -1;
+    for update in updates {
+        if update.index as i64 == index + 1 {
+            writer.write_bit(true)?;
+        } else {
+            writer.write_bit(false)?;
+            writer.write_varuint32(update.index as u32 - 1)?;
+        }
+        index = update.index as i64;
+        writer.write_bit(update.key.is_some())?;
+        if let Some(key) = &update.key {
+            // No substring from the key history.
+            writer.write_bit(false)?;
+            writer.write_string(key)?;
+        }
+        writer.write_bit(update.data.is_some())?;
+        if let Some(data) = &update.data {
+            if !info.user_data_fixed_size {
+                if (info.flags & 0x1) != 0 {
+                    // Not compressed.
+                    writer.write_bit(false)?;
+                }
+                if info.using_varint_bitcounts {
+                    writer.write_ubitvar(data.len() as u32)?;
+                } else {
+                    writer.write(17, data.len() as u32)?;
+                }
+            }
+            writer.write_bytes(data)?;
+        }
+    }
+    writer.byte_align()?;
+    Ok(writer.into_writer())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -169,9 +360,13 @@ mod tests {
 
     #[test]
     fn test_parse_userinfo() {
+        let mut string_tables = StringTables::default();
         for table in testdata::string_tables().tables {
-            if table.table_name() == "userinfo" {
-                parse_userinfo(&table).unwrap();
+            let table_id = string_tables.replace(table);
+            let table = string_tables.table(table_id);
+            if table.name() == USERINFO {
+                let entries: Vec<usize> = (0..table.entries().len()).collect();
+                parse_userinfo(table, &entries).unwrap();
             }
         }
     }
@@ -191,4 +386,59 @@ mod tests {
         assert_eq!(data.len(), 2);
         Ok(())
     }
+
+    #[test]
+    fn test_encode_string_table() -> Result<()> {
+        let (info, data) = parse_create_string_table(testdata::create_string_table())?;
+        let bytes = encode_string_table(&info, &data)?;
+        assert_eq!(parse_string_table(&info, bytes, data.len() as i32)?, data);
+
+        let data = parse_update_string_table(testdata::update_string_table(), &info)?;
+        let bytes = encode_string_table(&info, &data)?;
+        assert_eq!(parse_string_table(&info, bytes, data.len() as i32)?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_string_tables() -> Result<()> {
+        let mut string_tables = StringTables::default();
+        let (table_id, changed) = string_tables.create(testdata::create_string_table())?;
+        assert_eq!(changed.len(), 69);
+        let old_entry = string_tables.table(table_id).entries()[56].clone();
+
+        let mut update = testdata::update_string_table();
+        update.set_table_id(table_id as i32);
+        let (_, changed) = string_tables.update(update)?;
+        assert_eq!(changed, vec![56, 57]);
+        let table = string_tables.get(INSTANCEBASELINE).unwrap();
+        assert_eq!(table.entries().len(), 69);
+        assert_eq!(table.entries()[56].key, old_entry.key);
+        assert_eq!(table.get("225"), Some(&table.entries()[56]));
+
+        string_tables.clear();
+        assert!(string_tables.iter().next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn out_of_range_index() -> Result<()> {
+        let mut string_tables = StringTables::default();
+        let (table_id, _) = string_tables.create(testdata::create_string_table())?;
+        let info = string_tables.table(table_id).info.clone().unwrap();
+        let corrupt = StringTableUpdate {
+            index: u32::MAX as usize,
+            key: None,
+            data: Some(vec![1]),
+        };
+        let mut update = CSVCMsg_UpdateStringTable::new();
+        update.set_table_id(table_id as i32);
+        update.set_num_changed_entries(1);
+        update.set_string_data(encode_string_table(&info, &[corrupt])?);
+        assert!(matches!(
+            string_tables.update(update),
+            Err(Error::InvalidStringTableIndex(_))
+        ));
+        assert_eq!(string_tables.table(table_id).entries().len(), 69);
+        Ok(())
+    }
 }
@@ -1,9 +1,68 @@
-use protobuf::Message;
+use bitstream_io::{BitWrite, BitWriter, LittleEndian};
+use protobuf::{CodedOutputStream, Message};
 
-use crate::proto::demo::{CDemoClassInfo, CDemoSendTables, CDemoStringTables};
+use crate::proto::demo::{
+    CDemoClassInfo, CDemoPacket, CDemoSendTables, CDemoStringTables, EDemoCommands,
+};
 use crate::proto::netmessages::{
-    CSVCMsg_CreateStringTable, CSVCMsg_PacketEntities, CSVCMsg_UpdateStringTable,
+    CSVCMsg_CreateStringTable, CSVCMsg_PacketEntities, CSVCMsg_UpdateStringTable, SVC_Messages,
 };
+use crate::write::ValveBitWriter;
+use crate::Tick;
+
+/// Returns a demo file containing `commands`, each given as (command, tick, data).
+pub(crate) fn demo(commands: Vec<(EDemoCommands, Tick, Vec<u8>)>) -> Vec<u8> {
+    let mut demo = b"PBDEMS2\0".to_vec();
+    demo.extend([0; 8]);
+    let mut os = CodedOutputStream::vec(&mut demo);
+    for (cmd, tick, data) in commands {
+        os.write_raw_varint32(cmd as u32).unwrap();
+        os.write_raw_varint32(tick as u32).unwrap();
+        os.write_raw_varint32(data.len() as u32).unwrap();
+        os.write_raw_bytes(&data).unwrap();
+    }
+    os.flush().unwrap();
+    drop(os);
+    demo
+}
+
+/// Returns a packet containing `messages`, each given as (message type, encoded message).
+pub(crate) fn packet(messages: &[(u32, Vec<u8>)]) -> CDemoPacket {
+    let mut writer = BitWriter::endian(Vec::new(), LittleEndian);
+    for (msg_type, bytes) in messages {
+        writer.write_ubitvar(*msg_type).unwrap();
+        writer.write_varuint32(bytes.len() as u32).unwrap();
+        writer.write_bytes(bytes).unwrap();
+    }
+    writer.byte_align().unwrap();
+    let mut packet = CDemoPacket::new();
+    packet.set_data(writer.into_writer());
+    packet
+}
+
+/// Returns a PacketEntities message deleting the entities `ids`, in increasing order.
+pub(crate) fn delete_entities(ids: &[u32]) -> CSVCMsg_PacketEntities {
+    let mut writer = BitWriter::endian(Vec::new(), LittleEndian);
+    let mut next_id = 0;
+    for &id in ids {
+        writer.write_ubitvar(id - next_id).unwrap();
+        next_id = id + 1;
+        // Removed and deleted.
+        writer.write(2, 0b11u8).unwrap();
+    }
+    writer.byte_align().unwrap();
+    let mut msg = CSVCMsg_PacketEntities::new();
+    msg.set_updated_entries(ids.len() as i32);
+    msg.set_is_delta(true);
+    msg.set_entity_data(writer.into_writer());
+    msg
+}
+
+/// Returns a packet containing the PacketEntities message `msg`.
+pub(crate) fn entities_packet(msg: &CSVCMsg_PacketEntities) -> CDemoPacket {
+    let msg_type = SVC_Messages::svc_PacketEntities as u32;
+    packet(&[(msg_type, msg.write_to_bytes().unwrap())])
+}
 
 pub(crate) fn send_tables() -> CDemoSendTables {
     CDemoSendTables::parse_from_bytes(include_bytes!("testdata/cdemosendtables.binpb")).unwrap()
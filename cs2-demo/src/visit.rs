@@ -1,31 +1,57 @@
 use std::io::Read;
+use std::ops::ControlFlow;
 
+use demo_context::InContext;
 use tracing::{trace, trace_span};
 
 use crate::demo_command::{DemoCommand, DemoParser};
-use crate::entity::{Classes, EntityFactory, EntityList, SendTables};
+use crate::entity::{
+    Classes, EntityFactory, EntityList, PropertyChanges, PropertyPattern, SendTables,
+};
 use crate::game_event::{parse_game_event_list, GameEventDescriptors};
 use crate::message::Message;
 use crate::packet::Packet;
-use crate::proto::demo::{CDemoFileHeader, CDemoStringTables};
+use crate::proto::demo::{CDemoFileHeader, CDemoFileInfo, CDemoStringTables, EDemoCommands};
 use crate::proto::gameevents::CMsgSource1LegacyGameEvent;
-use crate::proto::netmessages::CSVCMsg_ServerInfo;
+use crate::proto::netmessages::{CSVCMsg_PacketEntities, CSVCMsg_ServerInfo};
 use crate::string_table::{
-    parse_create_string_table, parse_update_string_table, parse_userinfo, StringTableData,
-    StringTableInfo, INSTANCEBASELINE, USERINFO,
+    parse_userinfo, StringTable, StringTableData, StringTables, INSTANCEBASELINE, USERINFO,
 };
 use crate::{Error, Result, Tick, UserInfo};
 
 pub trait Visitor {
+    /// Called before the first demo command of every tick. Returning `ControlFlow::Break` stops
+    /// the parsing, for example once the other hooks have collected everything needed.
+    fn visit_tick(&mut self, _tick: Tick) -> anyhow::Result<ControlFlow<()>> {
+        Ok(ControlFlow::Continue(()))
+    }
     fn visit_file_header(&mut self, _file_header: CDemoFileHeader) -> anyhow::Result<()> {
         Ok(())
     }
+    /// Called with the summary stored at the end of the demo. Incomplete demos have none.
+    fn visit_file_info(&mut self, _file_info: CDemoFileInfo) -> anyhow::Result<()> {
+        Ok(())
+    }
     fn visit_server_info(&mut self, _server_info: CSVCMsg_ServerInfo) -> anyhow::Result<()> {
         Ok(())
     }
+    /// Called with the players added or modified in the userinfo string table.
     fn visit_userinfo_table(&mut self, _user_info: Vec<UserInfo>) -> anyhow::Result<()> {
         Ok(())
     }
+    /// Called when string table entries are created or modified. `changed` contains the indices
+    /// of the modified entries in `table`.
+    fn visit_string_table(
+        &mut self,
+        _table: &StringTable,
+        _changed: &[usize],
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+    /// Called when all the string tables are removed, before they get created again.
+    fn visit_clear_string_tables(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
     fn visit_game_event(
         &mut self,
         _game_event: CMsgSource1LegacyGameEvent,
@@ -40,6 +66,55 @@ pub trait Visitor {
     ) -> anyhow::Result<()> {
         Ok(())
     }
+    /// Called after each PacketEntities message that changed properties selected by
+    /// [`ParseOptions::properties`].
+    fn visit_property_changes(&mut self, _changes: PropertyChanges) -> anyhow::Result<()> {
+        Ok(())
+    }
+    /// Called in lenient mode with the errors that did not stop the parsing, see
+    /// [`ParseOptions::lenient`]. `last_good_tick` is the tick of the last demo command parsed
+    /// successfully.
+    fn visit_parse_error(
+        &mut self,
+        _error: Error,
+        _last_good_tick: Option<Tick>,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Options controlling which parts of a demo get parsed.
+#[derive(Clone, Debug)]
+pub struct ParseOptions {
+    /// Game events and property changes before this tick are not sent to the visitor. Entities
+    /// are only decoded from the last FullPacket before `start_tick`, which contains all of them,
+    /// once `start_tick` is reached. Everything else is still parsed because it is needed to
+    /// build the state at `start_tick`.
+    pub start_tick: Tick,
+    /// Parsing stops at the first demo command after this tick.
+    pub end_tick: Tick,
+    /// Whether PacketEntities messages are decoded. When false, the [`EntityList`] passed to the
+    /// visitor is always empty but parsing is much faster.
+    pub decode_entities: bool,
+    /// Whether to recover from invalid data instead of failing: demo commands that cannot be
+    /// decoded are skipped and parsing stops at a truncated one. The errors are sent to
+    /// [`Visitor::visit_parse_error`]. Errors returned by the visitor still fail the parsing.
+    pub lenient: bool,
+    /// Properties whose changes are sent to [`Visitor::visit_property_changes`], like game events
+    /// from `start_tick`. Requires `decode_entities`.
+    pub properties: Vec<PropertyPattern>,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            start_tick: Tick::MIN,
+            end_tick: Tick::MAX,
+            decode_entities: true,
+            lenient: false,
+            properties: Vec::new(),
+        }
+    }
 }
 
 pub fn parse(
@@ -47,90 +122,215 @@ pub fn parse(
     visitor: &mut dyn Visitor,
     entity_factory: EntityFactory,
 ) -> Result<()> {
-    DemoVisit::new(DemoParser::try_new(read)?, visitor, entity_factory).parse()
+    parse_with_options(read, visitor, entity_factory, ParseOptions::default())
 }
 
-struct DemoVisit<'a> {
-    parser: DemoParser<'a>,
+pub fn parse_with_options(
+    read: &mut dyn Read,
+    visitor: &mut dyn Visitor,
+    entity_factory: EntityFactory,
+    options: ParseOptions,
+) -> Result<()> {
+    let mut parser = DemoParser::try_new(read)?;
+    let lenient = options.lenient;
+    let mut visit = DemoVisit::new(visitor, entity_factory, options);
+    let mut last_good_tick = None;
+    loop {
+        let offset = parser.offset();
+        let (tick, cmd, data) = match parser.read_next_raw_demo_command() {
+            Ok(Some(command)) => command,
+            Ok(None) => break,
+            // The demo is truncated, there is no next command to skip to.
+            Err(e) if lenient => {
+                let e = e.in_command(offset, None, None);
+                visit.visitor.visit_parse_error(e, last_good_tick)?;
+                break;
+            }
+            Err(e) => return Err(e.in_command(offset, None, None)),
+        };
+        let flow = DemoCommand::try_new(cmd, &data)
+            .and_then(|command| visit.visit_demo_command(tick, command))
+            .map_err(|e| e.in_command(offset, Some(command_name(cmd)), Some(tick)));
+        match flow {
+            Ok(flow) if flow.is_break() => break,
+            Ok(_) => last_good_tick = Some(tick),
+            Err(e) if lenient && !e.is_visitor() => {
+                visit.visitor.visit_parse_error(e, last_good_tick)?
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Returns the name of a demo command type, without the `DEM_` prefix.
+pub(crate) fn command_name(cmd: u32) -> String {
+    use protobuf::Enum;
+    match EDemoCommands::from_i32(cmd as i32) {
+        Some(cmd) => format!("{cmd:?}").trim_start_matches("DEM_").to_string(),
+        None => cmd.to_string(),
+    }
+}
+
+pub(crate) struct DemoVisit<'a> {
     visitor: &'a mut dyn Visitor,
+    options: ParseOptions,
+    /// Tick of the last demo command.
+    tick: Option<Tick>,
     classes: Option<Classes>,
-    entities: EntityList,
-    // Information necessary to parse UpdateStringTable.
-    string_tables: Vec<Option<StringTableInfo>>,
+    pub(crate) entities: EntityList,
+    pub(crate) string_tables: StringTables,
     // Stored temporarily until ClassInfo.
     send_tables: Option<SendTables>,
     // Data from "instancebaselines" string table stored temporarily until `classes` gets created.
     instance_baselines: StringTableData,
+    /// Entity updates before `start_tick`, decoded once it is reached.
+    deferred: Vec<Deferred>,
+}
+
+/// The string tables and the instance baselines of the classes, see [`DemoVisit::state`].
+pub(crate) struct VisitState {
+    classes: Option<Classes>,
+    string_tables: StringTables,
+}
+
+enum Deferred {
+    InstanceBaselines(StringTableData),
+    PacketEntities(Tick, CSVCMsg_PacketEntities),
 }
 
 impl<'a> DemoVisit<'a> {
-    fn new(
-        parser: DemoParser<'a>,
+    pub(crate) fn new(
         visitor: &'a mut dyn Visitor,
         entity_factory: EntityFactory,
+        options: ParseOptions,
     ) -> Self {
+        let mut entities = EntityList::new(entity_factory);
+        if !options.properties.is_empty() {
+            entities.record(options.properties.clone());
+        }
         Self {
-            parser,
             visitor,
+            options,
+            tick: None,
             send_tables: None,
             classes: None,
             string_tables: Default::default(),
             instance_baselines: Default::default(),
-            entities: EntityList::new(entity_factory),
+            deferred: Vec::new(),
+            entities,
         }
     }
 }
 
 impl DemoVisit<'_> {
-    fn parse(&mut self) -> Result<()> {
-        while let Some((tick, cmd)) = self.parser.parse_next_demo_command()? {
-            trace_span!("demo_command").in_scope(|| trace!("#{tick} {cmd}"));
-            match cmd {
-                DemoCommand::FileHeader(header) => self.visitor.visit_file_header(header)?,
-                DemoCommand::SendTables(send) => {
-                    self.send_tables = Some(SendTables::try_new(send)?)
-                }
-                DemoCommand::ClassInfo(ci) => {
-                    let send_tables = self
-                        .send_tables
-                        .take()
-                        .ok_or(Error::ClassInfoBeforeSendTables)?;
-                    let mut classes = Classes::try_new(ci, send_tables)?;
-                    classes.update_instance_baselines(self.instance_baselines.split_off(0));
-                    self.classes = Some(classes);
-                }
-                DemoCommand::StringTables(st) => self.parse_string_tables(st)?,
-                DemoCommand::Packet(p) => self.parse_packet(tick, p)?,
-                DemoCommand::FullPacket(st, p) => {
-                    self.parse_string_tables(st)?;
-                    self.parse_packet(tick, p)?
+    pub(crate) fn visit_demo_command(
+        &mut self,
+        tick: Tick,
+        cmd: DemoCommand,
+    ) -> Result<ControlFlow<()>> {
+        trace_span!("demo_command").in_scope(|| trace!("#{tick} {cmd}"));
+        if tick > self.options.end_tick {
+            return Ok(ControlFlow::Break(()));
+        }
+        if self.tick != Some(tick) {
+            self.tick = Some(tick);
+            if tick >= self.options.start_tick && !self.deferred.is_empty() {
+                self.decode_deferred()
+                    .map_err(|e| e.in_message("PacketEntities"))?;
+            }
+            if self.visitor.visit_tick(tick)?.is_break() {
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+        match cmd {
+            DemoCommand::FileHeader(header) => self.visitor.visit_file_header(header)?,
+            DemoCommand::FileInfo(file_info) => self.visitor.visit_file_info(file_info)?,
+            DemoCommand::SendTables(send) => self.send_tables = Some(SendTables::try_new(send)?),
+            DemoCommand::ClassInfo(ci) => {
+                let send_tables = self
+                    .send_tables
+                    .take()
+                    .ok_or(Error::ClassInfoBeforeSendTables)?;
+                let mut classes = Classes::try_new(ci, send_tables)?;
+                classes.update_instance_baselines(self.instance_baselines.split_off(0));
+                self.classes = Some(classes);
+            }
+            DemoCommand::StringTables(st) => self.parse_string_tables(st)?,
+            DemoCommand::Packet(p) => self.parse_packet(tick, p)?,
+            DemoCommand::FullPacket(st, p) => {
+                if self.defers_entities() {
+                    // The FullPacket contains all the entities, the previous updates are not
+                    // needed anymore.
+                    self.deferred
+                        .retain(|d| matches!(d, Deferred::InstanceBaselines(_)));
+                    self.entities.clear();
                 }
-                _ => (),
+                self.parse_string_tables(st)?;
+                self.parse_packet(tick, p)?
             }
+            _ => (),
         }
-        Ok(())
+        Ok(ControlFlow::Continue(()))
+    }
+
+    pub(crate) fn classes(&self) -> Option<&Classes> {
+        self.classes.as_ref()
+    }
+
+    /// Returns the state that depends on the demo commands parsed so far, besides the entities.
+    pub(crate) fn state(&self) -> VisitState {
+        VisitState {
+            classes: self.classes.clone(),
+            string_tables: self.string_tables.clone(),
+        }
+    }
+
+    /// Restores `state` and removes all the entities.
+    pub(crate) fn restore(&mut self, state: &VisitState) {
+        self.classes.clone_from(&state.classes);
+        self.string_tables.clone_from(&state.string_tables);
+        self.entities.clear();
     }
 
     fn parse_string_tables(&mut self, st: CDemoStringTables) -> Result<()> {
-        for mut table in st.tables {
-            let name = table.take_table_name();
-            match name.as_str() {
-                USERINFO => self.visitor.visit_userinfo_table(parse_userinfo(&table)?)?,
-                INSTANCEBASELINE => {
-                    let data = table
-                        .items
-                        .into_iter()
-                        .map(|mut e| (e.take_str(), e.take_data()))
-                        .collect();
-                    self.update_instance_baselines(data)?;
-                }
-                _ => (),
+        for table in st.tables {
+            let table_id = self.string_tables.replace(table);
+            let changed = (0..self.string_tables.table(table_id).entries().len()).collect();
+            self.string_table_changed(table_id, changed)?;
+        }
+        Ok(())
+    }
+
+    fn string_table_changed(&mut self, table_id: usize, changed: Vec<usize>) -> Result<()> {
+        let table = self.string_tables.table(table_id);
+        match table.name() {
+            USERINFO => self
+                .visitor
+                .visit_userinfo_table(parse_userinfo(table, &changed)?)?,
+            INSTANCEBASELINE => {
+                let data = changed
+                    .iter()
+                    .map(|&i| {
+                        let entry = &table.entries()[i];
+                        (entry.key.clone(), entry.data.clone())
+                    })
+                    .collect();
+                self.update_instance_baselines(data)?;
             }
+            _ => (),
         }
+        let table = self.string_tables.table(table_id);
+        self.visitor.visit_string_table(table, &changed)?;
         Ok(())
     }
 
     fn update_instance_baselines(&mut self, items: StringTableData) -> Result<()> {
+        if self.classes.is_some() && self.defers_entities() {
+            // Entities created by the deferred updates need the baselines of their time.
+            self.deferred.push(Deferred::InstanceBaselines(items));
+            return Ok(());
+        }
         match self.classes.as_mut() {
             Some(classes) => {
                 assert!(self.instance_baselines.is_empty());
@@ -141,51 +341,231 @@ impl DemoVisit<'_> {
         Ok(())
     }
 
+    /// Returns true if entity updates of the current demo command are deferred until
+    /// `start_tick`.
+    fn defers_entities(&self) -> bool {
+        self.options.decode_entities && self.tick.is_some_and(|t| t < self.options.start_tick)
+    }
+
+    fn decode_deferred(&mut self) -> Result<()> {
+        for deferred in std::mem::take(&mut self.deferred) {
+            let classes = self.classes.as_mut().ok_or(Error::EntityBeforeClassInfo)?;
+            match deferred {
+                Deferred::InstanceBaselines(items) => classes.update_instance_baselines(items),
+                Deferred::PacketEntities(tick, pe) => {
+                    self.entities.read_packet_entities(pe, classes, tick)?
+                }
+            }
+        }
+        // Writes before `start_tick` are not sent to the visitor.
+        self.entities.take_property_changes();
+        Ok(())
+    }
+
     fn parse_packet(&mut self, tick: i32, p: Packet) -> Result<()> {
         for msg in p.messages {
-            match msg {
-                Message::PacketEntities(pe) => {
-                    let classes = self.classes.as_ref().ok_or(Error::EntityBeforeClassInfo)?;
-                    self.entities.read_packet_entities(pe, classes)?
+            let name = msg.name();
+            self.parse_message(tick, msg)
+                .map_err(|e| e.in_message(name))?;
+        }
+        Ok(())
+    }
+
+    fn parse_message(&mut self, tick: i32, msg: Message) -> Result<()> {
+        match msg {
+            Message::PacketEntities(_) if !self.options.decode_entities => (),
+            Message::PacketEntities(pe) if self.defers_entities() => {
+                self.deferred.push(Deferred::PacketEntities(tick, pe))
+            }
+            Message::PacketEntities(pe) => {
+                let classes = self.classes.as_ref().ok_or(Error::EntityBeforeClassInfo)?;
+                self.entities.read_packet_entities(pe, classes, tick)?;
+                if let Some(changes) = self.entities.take_property_changes() {
+                    if !changes.is_empty() && tick >= self.options.start_tick {
+                        self.visitor.visit_property_changes(changes)?
+                    }
                 }
-                Message::ServerInfo(si) => self.visitor.visit_server_info(si)?,
-                Message::Source1LegacyGameEventList(gel) => self
-                    .visitor
-                    .visit_game_event_descriptors(parse_game_event_list(gel))?,
-                Message::Source1LegacyGameEvent(ge) => {
+            }
+            Message::ServerInfo(si) => self.visitor.visit_server_info(si)?,
+            Message::Source1LegacyGameEventList(gel) => self
+                .visitor
+                .visit_game_event_descriptors(parse_game_event_list(gel))?,
+            Message::Source1LegacyGameEvent(ge) => {
+                if tick >= self.options.start_tick {
                     self.visitor.visit_game_event(ge, tick, &self.entities)?
                 }
-                Message::ClearAllStringTables(_) => self.string_tables.clear(),
-                Message::CreateStringTable(msg) => {
-                    let info = match msg.name() {
-                        INSTANCEBASELINE => {
-                            let (info, data) = parse_create_string_table(msg)?;
-                            self.update_instance_baselines(data)?;
-                            Some(info)
-                        }
-                        USERINFO => {
-                            // TODO
-                            None
-                        }
-                        _ => None,
-                    };
-                    self.string_tables.push(info);
-                }
-                Message::UpdateStringTable(msg) => {
-                    if let Some(info) = self.string_tables[msg.table_id() as usize].as_ref() {
-                        match info.name.as_str() {
-                            INSTANCEBASELINE => {
-                                let data = parse_update_string_table(msg, info)?;
-                                self.update_instance_baselines(data)?;
-                            }
-                            USERINFO => { /* TODO */ }
-                            _ => {}
-                        }
-                    }
+            }
+            Message::ClearAllStringTables(_) => {
+                self.string_tables.clear();
+                self.visitor.visit_clear_string_tables()?;
+            }
+            Message::CreateStringTable(msg) => {
+                let (table_id, changed) = self.string_tables.create(msg)?;
+                self.string_table_changed(table_id, changed)?;
+            }
+            Message::UpdateStringTable(msg) => {
+                let (table_id, changed) = self.string_tables.update(msg)?;
+                self.string_table_changed(table_id, changed)?;
+            }
+            Message::Unknown(_) => (),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::TreeEntity;
+    use crate::testdata;
+
+    #[derive(Default)]
+    struct TickRecorder {
+        ticks: Vec<Tick>,
+        stop_at: Option<Tick>,
+        /// Last good tick of every parse error.
+        errors: Vec<Option<Tick>>,
+    }
+
+    impl Visitor for TickRecorder {
+        fn visit_tick(&mut self, tick: Tick) -> anyhow::Result<ControlFlow<()>> {
+            self.ticks.push(tick);
+            if self.stop_at == Some(tick) {
+                return Ok(ControlFlow::Break(()));
+            }
+            Ok(ControlFlow::Continue(()))
+        }
+
+        fn visit_parse_error(
+            &mut self,
+            _error: Error,
+            last_good_tick: Option<Tick>,
+        ) -> anyhow::Result<()> {
+            self.errors.push(last_good_tick);
+            Ok(())
+        }
+    }
+
+    fn demo() -> Vec<u8> {
+        use EDemoCommands::*;
+        testdata::demo(vec![
+            (DEM_SyncTick, 0, vec![]),
+            (DEM_SyncTick, 0, vec![]),
+            (DEM_SyncTick, 1, vec![]),
+            (DEM_SyncTick, 2, vec![]),
+            (DEM_Stop, 3, vec![]),
+        ])
+    }
+
+    #[test]
+    fn end_tick() -> Result<()> {
+        let mut visitor = TickRecorder::default();
+        let options = ParseOptions {
+            end_tick: 1,
+            ..Default::default()
+        };
+        parse_with_options(
+            &mut demo().as_slice(),
+            &mut visitor,
+            &TreeEntity::factory,
+            options,
+        )?;
+        assert_eq!(visitor.ticks, vec![0, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn visitor_break() -> Result<()> {
+        let mut visitor = TickRecorder {
+            stop_at: Some(2),
+            ..Default::default()
+        };
+        parse(&mut demo().as_slice(), &mut visitor, &TreeEntity::factory)?;
+        assert_eq!(visitor.ticks, vec![0, 1, 2]);
+        Ok(())
+    }
+
+    #[test]
+    fn deferred_entities() -> Result<()> {
+        fn entity_count(entities: &EntityList) -> usize {
+            (0..entities.len())
+                .filter_map(|id| entities.get(id))
+                .count()
+        }
+        fn visit_commands(commands: Vec<(Tick, DemoCommand)>) -> Result<usize> {
+            let mut visitor = TickRecorder::default();
+            let options = ParseOptions {
+                start_tick: 5,
+                ..Default::default()
+            };
+            let mut visit = DemoVisit::new(&mut visitor, &TreeEntity::factory, options);
+            let mut commands = vec![
+                (0, DemoCommand::SendTables(testdata::send_tables())),
+                (0, DemoCommand::ClassInfo(testdata::class_info())),
+                (0, DemoCommand::StringTables(testdata::string_tables())),
+            ]
+            .into_iter()
+            .chain(commands)
+            .peekable();
+            while let Some((tick, command)) = commands.next() {
+                if commands.peek().is_none() {
+                    // Nothing is decoded before `start_tick`.
+                    assert_eq!(entity_count(&visit.entities), 0);
                 }
-                Message::Unknown(_) => (),
+                assert!(visit.visit_demo_command(tick, command)?.is_continue());
             }
+            Ok(entity_count(&visit.entities))
         }
+        let packet = |messages| DemoCommand::Packet(Packet { messages });
+        let packet_entities = || vec![Message::PacketEntities(testdata::packet_entities())];
+
+        let created = visit_commands(vec![
+            (1, packet(packet_entities())),
+            (5, DemoCommand::SyncTick),
+        ])?;
+        assert!(created > 0);
+        // Updates before a FullPacket are dropped.
+        let full_packet = DemoCommand::FullPacket(
+            testdata::string_tables(),
+            Packet {
+                messages: Vec::new(),
+            },
+        );
+        let created = visit_commands(vec![
+            (1, packet(packet_entities())),
+            (3, full_packet),
+            (5, DemoCommand::SyncTick),
+        ])?;
+        assert_eq!(created, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn lenient() -> Result<()> {
+        use EDemoCommands::*;
+        let mut demo = testdata::demo(vec![
+            (DEM_SyncTick, 0, vec![]),
+            (DEM_Packet, 1, vec![0xff]),
+            (DEM_SyncTick, 2, vec![]),
+        ]);
+        // A SyncTick command at tick 3 whose content is missing.
+        demo.extend([DEM_SyncTick as u8, 3, 10]);
+        let mut visitor = TickRecorder::default();
+        assert!(parse(&mut demo.as_slice(), &mut visitor, &TreeEntity::factory).is_err());
+
+        let mut visitor = TickRecorder::default();
+        let options = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        parse_with_options(
+            &mut demo.as_slice(),
+            &mut visitor,
+            &TreeEntity::factory,
+            options,
+        )?;
+        assert_eq!(visitor.ticks, vec![0, 2]);
+        assert_eq!(visitor.errors, vec![Some(0), Some(2)]);
         Ok(())
     }
 }
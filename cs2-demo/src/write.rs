@@ -0,0 +1,275 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use bitstream_io::BitWrite;
+use protobuf::{CodedOutputStream, Message};
+
+use crate::demo_command::DemoParser;
+use crate::message::Message as PacketMessage;
+use crate::packet::Packet;
+use crate::proto::demo::{CDemoFileInfo, CDemoPacket, EDemoCommands};
+use crate::{Error, Result, Tick};
+
+pub(crate) trait ValveBitWriter {
+    fn write_ubitvar(&mut self, value: u32) -> io::Result<()>;
+    fn write_varuint32(&mut self, value: u32) -> io::Result<()>;
+    fn write_varuint64(&mut self, value: u64) -> io::Result<()>;
+    fn write_string(&mut self, s: &str) -> io::Result<()>;
+}
+
+impl<W: io::Write> ValveBitWriter for bitstream_io::BitWriter<W, bitstream_io::LittleEndian> {
+    /// Write a 32-bit value using the UBitVar Valve format.
+    fn write_ubitvar(&mut self, value: u32) -> io::Result<()> {
+        let last4 = value & 15;
+        match value {
+            0..=15 => self.write(6, value),
+            16..=255 => {
+                self.write(6, last4 | 16)?;
+                self.write(4, value >> 4)
+            }
+            256..=4095 => {
+                self.write(6, last4 | 32)?;
+                self.write(8, value >> 4)
+            }
+            _ => {
+                self.write(6, last4 | 48)?;
+                self.write(32 - 4, value >> 4)
+            }
+        }
+    }
+
+    fn write_varuint32(&mut self, mut value: u32) -> io::Result<()> {
+        while value >= 0x80 {
+            self.write(8, (value & 0x7F) as u8 | 0x80)?;
+            value >>= 7;
+        }
+        self.write(8, value as u8)
+    }
+
+    fn write_varuint64(&mut self, mut value: u64) -> io::Result<()> {
+        while value >= 0x80 {
+            self.write(8, (value & 0x7F) as u8 | 0x80)?;
+            value >>= 7;
+        }
+        self.write(8, value as u8)
+    }
+
+    fn write_string(&mut self, s: &str) -> io::Result<()> {
+        self.write_bytes(s.as_bytes())?;
+        self.write(8, 0u8)
+    }
+}
+
+/// Writes demo commands in the format read by [`DemoParser`].
+pub struct DemoWriter<W: Write + Seek> {
+    write: W,
+    file_info_offset: Option<u64>,
+}
+
+impl<W: Write + Seek> DemoWriter<W> {
+    pub fn try_new(mut write: W) -> Result<Self> {
+        write.write_all(b"PBDEMS2\0")?;
+        // Offsets of CDemoFileInfo and of the spawn groups, set by `finish`.
+        write.write_all(&[0; 8])?;
+        Ok(Self {
+            write,
+            file_info_offset: None,
+        })
+    }
+
+    /// Writes a demo command. `data` is the uncompressed content, it is compressed with snappy
+    /// like the commands found in demos recorded by the game.
+    pub fn write_demo_command(&mut self, cmd: u32, tick: Tick, data: &[u8]) -> Result<()> {
+        if cmd == EDemoCommands::DEM_FileInfo as u32 {
+            self.file_info_offset = Some(self.write.stream_position()?);
+        }
+        let data = snap::raw::Encoder::new().compress_vec(data)?;
+        let mut os = CodedOutputStream::new(&mut self.write);
+        os.write_raw_varint32(cmd | EDemoCommands::DEM_IsCompressed as u32)?;
+        os.write_raw_varint32(tick as u32)?;
+        os.write_raw_varint32(data.len() as u32)?;
+        os.write_raw_bytes(&data)?;
+        os.flush()?;
+        Ok(())
+    }
+
+    /// Stores the offset of the FileInfo command, if one was written, in the demo header and
+    /// returns the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        if let Some(offset) = self.file_info_offset {
+            self.write.seek(SeekFrom::Start(8))?;
+            self.write.write_all(&(offset as u32).to_le_bytes())?;
+            self.write.seek(SeekFrom::End(0))?;
+        }
+        Ok(self.write)
+    }
+}
+
+/// Copies the part of a demo between `start_tick` and `end_tick` to `write`.
+///
+/// The signon commands (file header, send tables, class info, string tables and signon
+/// packets) are copied first. Entity state cannot be re-encoded, so the copy then starts at the
+/// last FullPacket at or before `start_tick`, followed by the Packets leading to `start_tick`.
+/// The copy therefore begins up to one FullPacket interval before `start_tick`; use
+/// [`DemoIndex::full_packet_ticks`](crate::DemoIndex::full_packet_ticks) to pick cut points
+/// without that lead-in.
+pub fn cut<W: Write + Seek>(
+    read: &mut dyn Read,
+    write: W,
+    start_tick: Tick,
+    end_tick: Tick,
+) -> Result<W> {
+    use EDemoCommands::*;
+    let mut parser = DemoParser::try_new(read)?;
+    let mut writer = DemoWriter::try_new(write)?;
+    let mut in_signon = true;
+    let mut started = false;
+    let mut tick_interval = 1.0 / 64.0;
+    let mut first_tick = 0;
+    let mut last_tick = 0;
+    let mut frames = 0;
+    // Packets since the last FullPacket, written once `start_tick` is reached.
+    let mut pending = Vec::new();
+    while let Some((tick, cmd, data)) = parser.read_next_raw_demo_command()? {
+        let is_packet = cmd == DEM_Packet as u32 || cmd == DEM_FullPacket as u32;
+        if in_signon && !is_packet {
+            if cmd == DEM_SignonPacket as u32 {
+                if let Some(interval) = server_tick_interval(&data)? {
+                    tick_interval = interval;
+                }
+            }
+            if cmd != DEM_Stop as u32 && cmd != DEM_FileInfo as u32 {
+                writer.write_demo_command(cmd, tick, &data)?;
+            }
+            continue;
+        }
+        in_signon = false;
+        if tick > end_tick || cmd == DEM_Stop as u32 {
+            break;
+        }
+        if !is_packet {
+            continue;
+        }
+        if !started {
+            if cmd == DEM_FullPacket as u32 && tick <= start_tick {
+                pending.clear();
+            }
+            pending.push((cmd, tick, data));
+            if tick < start_tick {
+                continue;
+            }
+            started = true;
+            first_tick = pending[0].1;
+            for (cmd, tick, data) in pending.drain(..) {
+                frames += 1;
+                writer.write_demo_command(cmd, tick, &data)?;
+                last_tick = tick;
+            }
+            continue;
+        }
+        frames += 1;
+        writer.write_demo_command(cmd, tick, &data)?;
+        last_tick = tick;
+    }
+    if !started {
+        return Err(Error::CutStartNotFound(start_tick));
+    }
+    writer.write_demo_command(DEM_Stop as u32, last_tick, &[])?;
+    let ticks = last_tick - first_tick;
+    let mut file_info = CDemoFileInfo::new();
+    file_info.set_playback_ticks(ticks);
+    file_info.set_playback_frames(frames);
+    file_info.set_playback_time(ticks as f32 * tick_interval);
+    writer.write_demo_command(DEM_FileInfo as u32, last_tick, &file_info.write_to_bytes()?)?;
+    writer.finish()
+}
+
+/// Returns the tick interval if the packet contains CSVCMsg_ServerInfo.
+fn server_tick_interval(data: &[u8]) -> Result<Option<f32>> {
+    let packet = Packet::try_new(CDemoPacket::parse_from_bytes(data)?)?;
+    Ok(packet.messages.into_iter().find_map(|m| match m {
+        PacketMessage::ServerInfo(si) => Some(si.tick_interval()),
+        _ => None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read::ValveBitReader;
+    use crate::read_file_info;
+    use crate::testdata;
+    use std::io::Cursor;
+
+    #[test]
+    fn valve_bit_writer() -> io::Result<()> {
+        let values = [0, 15, 16, 255, 256, 4095, 4096, u32::MAX];
+        let mut writer = bitstream_io::BitWriter::endian(Vec::new(), bitstream_io::LittleEndian);
+        for value in values {
+            writer.write_ubitvar(value)?;
+            writer.write_varuint32(value)?;
+            writer.write_varuint64(value as u64 * 3)?;
+        }
+        writer.write_string("userinfo")?;
+        writer.byte_align()?;
+        let bytes = writer.into_writer();
+        let mut reader = crate::BitReader::new(Cursor::new(bytes.as_slice()));
+        for value in values {
+            assert_eq!(reader.read_ubitvar()?, value);
+            assert_eq!(reader.read_varuint32()?, value);
+            assert_eq!(reader.read_varuint64()?, value as u64 * 3);
+        }
+        assert_eq!(reader.read_string()?, "userinfo");
+        Ok(())
+    }
+
+    #[test]
+    fn cut_demo() -> Result<()> {
+        use EDemoCommands::*;
+        let demo = testdata::demo(vec![
+            (DEM_FileHeader, -1, vec![]),
+            (DEM_SendTables, 0, vec![]),
+            (DEM_SyncTick, 0, vec![]),
+            (DEM_Packet, 1, vec![1]),
+            (DEM_FullPacket, 10, vec![]),
+            (DEM_Packet, 11, vec![2]),
+            (DEM_Packet, 12, vec![3]),
+            (DEM_Packet, 13, vec![4]),
+            (DEM_Packet, 20, vec![5]),
+            (DEM_Stop, 20, vec![]),
+        ]);
+        let result = cut(&mut demo.as_slice(), Cursor::new(Vec::new()), 30, 40);
+        assert!(matches!(result, Err(Error::CutStartNotFound(30))));
+        // Before the first FullPacket, the copy starts right after the signon.
+        let mut out = cut(&mut demo.as_slice(), Cursor::new(Vec::new()), 5, 10)?;
+        let file_info = read_file_info(&mut out)?.unwrap();
+        assert_eq!(file_info.playback_ticks(), 9);
+        assert_eq!(file_info.playback_frames(), 2);
+
+        let mut out = cut(&mut demo.as_slice(), Cursor::new(Vec::new()), 12, 13)?;
+
+        let file_info = read_file_info(&mut out)?.unwrap();
+        assert_eq!(file_info.playback_ticks(), 3);
+        assert_eq!(file_info.playback_frames(), 4);
+
+        let out = out.into_inner();
+        let mut read = out.as_slice();
+        let mut parser = DemoParser::try_new(&mut read)?;
+        let mut commands = Vec::new();
+        while let Some((tick, cmd, data)) = parser.read_next_raw_demo_command()? {
+            commands.push((cmd, tick, data));
+        }
+        let expected = vec![
+            (DEM_FileHeader as u32, -1, vec![]),
+            (DEM_SendTables as u32, 0, vec![]),
+            (DEM_SyncTick as u32, 0, vec![]),
+            (DEM_FullPacket as u32, 10, vec![]),
+            (DEM_Packet as u32, 11, vec![2]),
+            (DEM_Packet as u32, 12, vec![3]),
+            (DEM_Packet as u32, 13, vec![4]),
+            (DEM_Stop as u32, 13, vec![]),
+        ];
+        assert_eq!(commands[..expected.len()], expected);
+        assert_eq!(commands[expected.len()].0, DEM_FileInfo as u32);
+        Ok(())
+    }
+}
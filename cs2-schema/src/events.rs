@@ -0,0 +1,435 @@
+//! Game event descriptors: Rust code generation and compatibility check.
+
+use cs2_demo::entity::TreeEntity;
+use cs2_demo::game_event::Descriptor;
+use cs2_demo::{GameEventDescriptors, ParseOptions, Tick, Visitor};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Write};
+use std::io::Read;
+use std::ops::ControlFlow;
+use std::path::Path;
+
+/// A game event as described in `CMsgSource1LegacyGameEventList`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EventSchema {
+    pub name: String,
+    pub keys: Vec<KeySchema>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KeySchema {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_: i32,
+}
+
+impl From<&Descriptor> for EventSchema {
+    fn from(d: &Descriptor) -> Self {
+        EventSchema {
+            name: d.name.clone(),
+            keys: d
+                .keys
+                .iter()
+                .map(|k| KeySchema {
+                    name: k.name.clone(),
+                    type_: k.type_,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Returns the Rust type of a key type and a description of the key, if any.
+fn key_type(type_: i32) -> Option<(&'static str, Option<&'static str>)> {
+    Some(match type_ {
+        1 => ("String", None),
+        2 => ("f32", None),
+        3..=5 => ("i32", None), // long, short, byte
+        6 => ("bool", None),
+        7 => ("u64", None),
+        8 => ("i32", Some("Entity handle (strict_ehandle).")),
+        9 => ("i32", Some("User id of a player controller.")),
+        _ => return None,
+    })
+}
+
+/// Returns the game events of a demo, sorted by name.
+pub fn read_demo(read: &mut dyn Read) -> anyhow::Result<Vec<EventSchema>> {
+    #[derive(Default)]
+    struct DescriptorVisitor(Option<GameEventDescriptors>);
+
+    impl Visitor for DescriptorVisitor {
+        fn visit_tick(&mut self, _tick: Tick) -> anyhow::Result<ControlFlow<()>> {
+            Ok(match self.0 {
+                Some(_) => ControlFlow::Break(()),
+                None => ControlFlow::Continue(()),
+            })
+        }
+
+        fn visit_game_event_descriptors(
+            &mut self,
+            descriptors: GameEventDescriptors,
+        ) -> anyhow::Result<()> {
+            self.0 = Some(descriptors);
+            Ok(())
+        }
+    }
+
+    let mut visitor = DescriptorVisitor::default();
+    let options = ParseOptions {
+        decode_entities: false,
+        ..Default::default()
+    };
+    cs2_demo::parse_with_options(read, &mut visitor, &TreeEntity::factory, options)?;
+    let Some(descriptors) = visitor.0 else {
+        anyhow::bail!("no game event list in demo");
+    };
+    let mut events: Vec<_> = descriptors.values().map(EventSchema::from).collect();
+    events.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(events)
+}
+
+/// Reads the game events of a demo, or of a JSON dump if `path` ends with `.json`.
+pub fn load(path: &Path) -> anyhow::Result<Vec<EventSchema>> {
+    let mut file = std::fs::File::open(path)?;
+    if path.extension().is_some_and(|ext| ext == "json") {
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    } else {
+        read_demo(&mut file)
+    }
+}
+
+/// Returns the Rust code of a `GameEvent` enum with a variant per event, and of a struct per
+/// event with keys, to be deserialized with `cs2_demo::game_event::de::from_proto`.
+pub fn generate(events: &[EventSchema]) -> String {
+    let mut code = String::new();
+    let mut structs = String::new();
+    code += "// Generated by cs2-schema from the game event list of a demo.\n\n";
+    code += "use serde::Deserialize;\n\n";
+    code += "#[derive(Debug, Deserialize)]\n#[serde(rename_all = \"snake_case\")]\n";
+    code += "pub enum GameEvent {\n";
+    for event in events {
+        let name = camel_case(&event.name);
+        if snake_case(&name) != event.name {
+            writeln!(code, "    #[serde(rename = \"{}\")]", event.name).unwrap();
+        }
+        if event.keys.is_empty() {
+            writeln!(code, "    {name},").unwrap();
+        } else {
+            writeln!(code, "    {name}({name}),").unwrap();
+            structs += "\n";
+            write_struct(&mut structs, &name, event);
+        }
+    }
+    code += "}\n";
+    code + &structs
+}
+
+fn write_struct(code: &mut String, name: &str, event: &EventSchema) {
+    code.push_str("#[derive(Debug, Deserialize)]\n");
+    if event
+        .keys
+        .iter()
+        .any(|k| k.name.chars().any(char::is_uppercase))
+    {
+        code.push_str("#[allow(non_snake_case)]\n");
+    }
+    writeln!(code, "pub struct {name} {{").unwrap();
+    for key in &event.keys {
+        let Some((rust_type, doc)) = key_type(key.type_) else {
+            writeln!(code, "    // {}: unknown type {}", key.name, key.type_).unwrap();
+            continue;
+        };
+        if let Some(doc) = doc {
+            writeln!(code, "    /// {doc}").unwrap();
+        }
+        let field = field_name(&key.name);
+        if field.trim_start_matches("r#") != key.name {
+            writeln!(code, "    #[serde(rename = \"{}\")]", key.name).unwrap();
+        }
+        writeln!(code, "    pub {field}: {rust_type},").unwrap();
+    }
+    code.push_str("}\n");
+}
+
+fn camel_case(name: &str) -> String {
+    let name: String = name
+        .split('_')
+        .flat_map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|c| c.to_ascii_uppercase())
+                .into_iter()
+                .chain(chars)
+        })
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        name
+    } else {
+        format!("Event{name}")
+    }
+}
+
+/// Same as `#[serde(rename_all = "snake_case")]` for variant names.
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            snake.push('_');
+        }
+        snake.push(c.to_ascii_lowercase());
+    }
+    snake
+}
+
+/// Returns a valid Rust field name for `key`.
+fn field_name(key: &str) -> String {
+    // Strict and reserved keywords of the 2024 edition, which can be raw identifiers.
+    const KEYWORDS: &[&str] = &[
+        "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do",
+        "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in",
+        "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+        "return", "static", "struct", "trait", "true", "try", "type", "typeof", "unsafe",
+        "unsized", "use", "virtual", "where", "while", "yield",
+    ];
+    // Keywords that cannot be raw identifiers, and `_` which is not an identifier.
+    const NON_RAW_KEYWORDS: &[&str] = &["crate", "self", "Self", "super", "_"];
+    let mut field: String = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !field.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        field.insert(0, '_');
+    }
+    if KEYWORDS.contains(&field.as_str()) {
+        field.insert_str(0, "r#");
+    } else if NON_RAW_KEYWORDS.contains(&field.as_str()) {
+        field.push('_');
+    }
+    field
+}
+
+/// A difference between the game events of two game versions.
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    AddedEvent(String),
+    RemovedEvent(String),
+    AddedKey {
+        event: String,
+        key: KeySchema,
+    },
+    RemovedKey {
+        event: String,
+        key: String,
+    },
+    KeyType {
+        event: String,
+        key: String,
+        old: i32,
+        new: i32,
+    },
+}
+
+impl Change {
+    /// Returns true if structs generated for the old events can fail to deserialize the new
+    /// ones.
+    pub fn is_breaking(&self) -> bool {
+        let rust_type = |type_| key_type(type_).map(|(rust_type, _)| rust_type);
+        match self {
+            Change::RemovedKey { .. } => true,
+            Change::KeyType { old, new, .. } => rust_type(*old) != rust_type(*new),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::AddedEvent(event) => write!(f, "added event {event}"),
+            Change::RemovedEvent(event) => write!(f, "removed event {event}"),
+            Change::AddedKey { event, key } => {
+                write!(f, "added key {event}.{} of type {}", key.name, key.type_)
+            }
+            Change::RemovedKey { event, key } => write!(f, "removed key {event}.{key}"),
+            Change::KeyType {
+                event,
+                key,
+                old,
+                new,
+            } => write!(f, "changed type of {event}.{key} from {old} to {new}"),
+        }
+    }
+}
+
+/// Returns the changes from the `old` events to the `new` ones.
+pub fn diff(old: &[EventSchema], new: &[EventSchema]) -> Vec<Change> {
+    let find = |events: &[EventSchema], name: &str| events.iter().position(|e| e.name == name);
+    let mut changes = Vec::new();
+    for old_event in old {
+        let Some(i) = find(new, &old_event.name) else {
+            changes.push(Change::RemovedEvent(old_event.name.clone()));
+            continue;
+        };
+        let new_event = &new[i];
+        let event = || old_event.name.clone();
+        for old_key in &old_event.keys {
+            match new_event.keys.iter().find(|k| k.name == old_key.name) {
+                None => changes.push(Change::RemovedKey {
+                    event: event(),
+                    key: old_key.name.clone(),
+                }),
+                Some(new_key) if new_key.type_ != old_key.type_ => changes.push(Change::KeyType {
+                    event: event(),
+                    key: old_key.name.clone(),
+                    old: old_key.type_,
+                    new: new_key.type_,
+                }),
+                Some(_) => (),
+            }
+        }
+        for new_key in &new_event.keys {
+            if !old_event.keys.iter().any(|k| k.name == new_key.name) {
+                changes.push(Change::AddedKey {
+                    event: event(),
+                    key: new_key.clone(),
+                });
+            }
+        }
+    }
+    for new_event in new {
+        if find(old, &new_event.name).is_none() {
+            changes.push(Change::AddedEvent(new_event.name.clone()));
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(name: &str, keys: &[(&str, i32)]) -> EventSchema {
+        EventSchema {
+            name: name.to_string(),
+            keys: keys
+                .iter()
+                .map(|&(name, type_)| KeySchema {
+                    name: name.to_string(),
+                    type_,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn generated_code() {
+        let events = [
+            event(
+                "player_blind",
+                &[("userid", 9), ("entityid", 8), ("type", 1)],
+            ),
+            event("player_disconnect", &[("PlayerID", 4)]),
+            event("round_officially_ended", &[]),
+            event("weapon_fire_on_empty", &[("weapon", 1), ("self", 9)]),
+            event("2d_event", &[("value", 10)]),
+        ];
+        let code = generate(&events);
+        assert_eq!(
+            code,
+            r#"// Generated by cs2-schema from the game event list of a demo.
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameEvent {
+    PlayerBlind(PlayerBlind),
+    PlayerDisconnect(PlayerDisconnect),
+    RoundOfficiallyEnded,
+    WeaponFireOnEmpty(WeaponFireOnEmpty),
+    #[serde(rename = "2d_event")]
+    Event2dEvent(Event2dEvent),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlayerBlind {
+    /// User id of a player controller.
+    pub userid: i32,
+    /// Entity handle (strict_ehandle).
+    pub entityid: i32,
+    pub r#type: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+pub struct PlayerDisconnect {
+    pub PlayerID: i32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WeaponFireOnEmpty {
+    pub weapon: String,
+    /// User id of a player controller.
+    #[serde(rename = "self")]
+    pub self_: i32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Event2dEvent {
+    // value: unknown type 10
+}
+"#
+        );
+    }
+
+    #[test]
+    fn field_names() {
+        assert_eq!(field_name("userid"), "userid");
+        assert_eq!(field_name("type"), "r#type");
+        assert_eq!(field_name("yield"), "r#yield");
+        assert_eq!(field_name("gen"), "r#gen");
+        assert_eq!(field_name("self"), "self_");
+        assert_eq!(field_name("Self"), "Self_");
+        assert_eq!(field_name("super"), "super_");
+        assert_eq!(field_name("crate"), "crate_");
+        assert_eq!(field_name("_"), "__");
+        assert_eq!(field_name("2d.x"), "_2d_x");
+    }
+
+    #[test]
+    fn compatibility() {
+        let old = [
+            event(
+                "player_death",
+                &[("userid", 9), ("weapon", 1), ("dominated", 4)],
+            ),
+            event("round_end", &[("winner", 5), ("reason", 5)]),
+            event("round_poststart", &[]),
+        ];
+        let new = [
+            event(
+                "player_death",
+                &[("userid", 9), ("weapon", 1), ("dominated", 5)],
+            ),
+            event("round_end", &[("winner", 1), ("legacy", 5)]),
+            event("round_mvp", &[]),
+        ];
+        let changes = diff(&old, &new);
+        assert_eq!(
+            changes.iter().map(Change::to_string).collect::<Vec<_>>(),
+            [
+                "changed type of player_death.dominated from 4 to 5",
+                "changed type of round_end.winner from 5 to 1",
+                "removed key round_end.reason",
+                "added key round_end.legacy of type 5",
+                "removed event round_poststart",
+                "added event round_mvp",
+            ]
+        );
+        let breaking: Vec<_> = changes.iter().map(Change::is_breaking).collect();
+        assert_eq!(breaking, [false, true, true, false, false, false]);
+    }
+}
@@ -0,0 +1,4 @@
+//! Tools to follow the changes of the CS2 demo format between game updates.
+
+pub mod events;
+pub mod send_tables;
@@ -0,0 +1,92 @@
+//! Dumps and compares the schema of CS2 demos.
+
+use clap::{Parser, Subcommand};
+use cs2_schema::{events, send_tables};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Print Rust structs for the game events of a demo.
+    Events {
+        /// Demo, or JSON dump of its game events.
+        input: PathBuf,
+        /// Print the game events as JSON instead, to be compared later with `events-diff`.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the differences between the game events of two game versions. Fails if structs
+    /// generated for the old version cannot deserialize the new events.
+    EventsDiff {
+        /// Demo, or JSON dump of its game events, of the old version.
+        old: PathBuf,
+        /// Demo, or JSON dump of its game events, of the new version.
+        new: PathBuf,
+    },
+    /// Print the classes and serializers of a demo as Markdown.
+    SendTables {
+        /// Demo, or JSON dump of its classes and serializers.
+        input: PathBuf,
+        /// Print them as JSON instead, to be compared later with `send-tables-diff`.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the differences between the classes and serializers of two game versions. Fails if
+    /// classes, serializers or fields were removed, or if the type of a field changed.
+    SendTablesDiff {
+        /// Demo, or JSON dump of its classes and serializers, of the old version.
+        old: PathBuf,
+        /// Demo, or JSON dump of its classes and serializers, of the new version.
+        new: PathBuf,
+    },
+}
+
+fn main() -> anyhow::Result<ExitCode> {
+    let cli = Cli::parse();
+    match cli.command {
+        Commands::Events { input, json } => {
+            let events = events::load(&input)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&events)?);
+            } else {
+                print!("{}", events::generate(&events));
+            }
+        }
+        Commands::EventsDiff { old, new } => {
+            let changes = events::diff(&events::load(&old)?, &events::load(&new)?);
+            for change in &changes {
+                let marker = if change.is_breaking() { "!" } else { " " };
+                println!("{marker} {change}");
+            }
+            if changes.iter().any(events::Change::is_breaking) {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Commands::SendTables { input, json } => {
+            let schema = send_tables::load(&input)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&schema)?);
+            } else {
+                print!("{}", schema.to_markdown());
+            }
+        }
+        Commands::SendTablesDiff { old, new } => {
+            let changes = send_tables::diff(&send_tables::load(&old)?, &send_tables::load(&new)?);
+            for change in &changes {
+                let marker = if change.is_breaking() { "!" } else { " " };
+                println!("{marker} {change}");
+            }
+            if changes.iter().any(send_tables::Change::is_breaking) {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
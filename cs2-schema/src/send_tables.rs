@@ -0,0 +1,597 @@
+//! Serializers and classes of the entities: dump as JSON or Markdown and comparison.
+
+use cs2_demo::proto::demo::{CDemoClassInfo, CDemoSendTables};
+use cs2_demo::proto::netmessages::CSVCMsg_FlattenedSerializer;
+use protobuf::CodedInputStream;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Write};
+use std::io::Read;
+use std::path::Path;
+
+/// The networked classes of a demo and the serializers of their fields, as described in
+/// `CDemoClassInfo` and `CDemoSendTables`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Schema {
+    pub classes: Vec<ClassSchema>,
+    pub serializers: Vec<SerializerSchema>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ClassSchema {
+    pub id: i32,
+    /// Name of the class and of its serializer.
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SerializerSchema {
+    pub name: String,
+    pub version: i32,
+    pub fields: Vec<FieldSchema>,
+}
+
+impl SerializerSchema {
+    /// Returns the name used to refer to this serializer from fields.
+    pub fn id(&self) -> String {
+        serializer_id(&self.name, self.version)
+    }
+}
+
+/// A field of a serializer. Fields holding an object refer to the serializer of the object, the
+/// other ones have the attributes of their encoding.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FieldSchema {
+    pub name: String,
+    pub var_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub send_node: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoder: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bit_count: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub low_value: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub high_value: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encode_flags: Option<i32>,
+    /// Id of the serializer of the object, see [`SerializerSchema::id`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serializer: Option<String>,
+    /// Ids of the serializers of the types which can be stored in the field.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub polymorphic_types: Vec<String>,
+}
+
+impl FieldSchema {
+    /// Returns the name of the field prefixed by its send node, which identifies the field in its
+    /// serializer.
+    pub fn path(&self) -> String {
+        match &self.send_node {
+            Some(node) => format!("{node}.{}", self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Returns the name and value of every attribute except the path, `-` when missing.
+    fn attributes(&self) -> [(&'static str, String); 8] {
+        fn show<T: ToString>(value: &Option<T>) -> String {
+            value.as_ref().map_or("-".to_string(), T::to_string)
+        }
+        let polymorphic_types = match self.polymorphic_types.is_empty() {
+            true => "-".to_string(),
+            false => self.polymorphic_types.join(", "),
+        };
+        [
+            ("var_type", self.var_type.clone()),
+            ("encoder", show(&self.encoder)),
+            ("bit_count", show(&self.bit_count)),
+            ("low_value", show(&self.low_value)),
+            ("high_value", show(&self.high_value)),
+            ("encode_flags", show(&self.encode_flags)),
+            ("serializer", show(&self.serializer)),
+            ("polymorphic_types", polymorphic_types),
+        ]
+    }
+}
+
+/// Returns the serializer name, followed by `@version` for versions other than 0.
+fn serializer_id(name: &str, version: i32) -> String {
+    match version {
+        0 => name.to_string(),
+        version => format!("{name}@{version}"),
+    }
+}
+
+impl Schema {
+    /// Returns the schema described by the send tables and class info of a demo. Classes are
+    /// sorted by id and serializers by name and version.
+    pub fn from_proto(
+        send_tables: &CDemoSendTables,
+        class_info: &CDemoClassInfo,
+    ) -> anyhow::Result<Self> {
+        let fs: CSVCMsg_FlattenedSerializer =
+            CodedInputStream::new(&mut send_tables.data()).read_message()?;
+        let symbol = |sym: Option<i32>| -> anyhow::Result<Option<String>> {
+            sym.map(|sym| match fs.symbols.get(sym as usize) {
+                Some(symbol) => Ok(symbol.clone()),
+                None => anyhow::bail!("invalid symbol {sym} in send tables"),
+            })
+            .transpose()
+        };
+        let required = |sym: Option<i32>, what: &str| -> anyhow::Result<String> {
+            symbol(sym)?.ok_or_else(|| anyhow::anyhow!("missing {what} in send tables"))
+        };
+        let serializer_ref = |name: Option<i32>, version: Option<i32>| {
+            anyhow::Ok(symbol(name)?.map(|name| serializer_id(&name, version.unwrap_or(0))))
+        };
+        let mut serializers = Vec::with_capacity(fs.serializers.len());
+        for s in &fs.serializers {
+            let mut fields = Vec::with_capacity(s.fields_index.len());
+            for &fi in &s.fields_index {
+                let Some(f) = fs.fields.get(fi as usize) else {
+                    anyhow::bail!("invalid field index {fi} in send tables");
+                };
+                let polymorphic_types = f
+                    .polymorphic_types
+                    .iter()
+                    .filter_map(|t| {
+                        serializer_ref(
+                            t.polymorphic_field_serializer_name_sym,
+                            t.polymorphic_field_serializer_version,
+                        )
+                        .transpose()
+                    })
+                    .collect::<anyhow::Result<_>>()?;
+                fields.push(FieldSchema {
+                    name: required(f.var_name_sym, "field name")?,
+                    var_type: required(f.var_type_sym, "field type")?,
+                    send_node: symbol(f.send_node_sym)?.filter(|node| !node.is_empty()),
+                    encoder: symbol(f.var_encoder_sym)?,
+                    bit_count: f.bit_count,
+                    low_value: f.low_value,
+                    high_value: f.high_value,
+                    encode_flags: f.encode_flags,
+                    serializer: serializer_ref(
+                        f.field_serializer_name_sym,
+                        f.field_serializer_version,
+                    )?,
+                    polymorphic_types,
+                });
+            }
+            serializers.push(SerializerSchema {
+                name: required(s.serializer_name_sym, "serializer name")?,
+                version: s.serializer_version.unwrap_or(0),
+                fields,
+            });
+        }
+        serializers.sort_by(|a, b| (&a.name, a.version).cmp(&(&b.name, b.version)));
+        let mut classes: Vec<_> = class_info
+            .classes
+            .iter()
+            .map(|c| ClassSchema {
+                id: c.class_id(),
+                name: c.network_name().to_string(),
+            })
+            .collect();
+        classes.sort_by_key(|c| c.id);
+        Ok(Schema {
+            classes,
+            serializers,
+        })
+    }
+
+    /// Returns the schema as a Markdown document: a table of the classes, then a table of the
+    /// fields of each serializer, linked to the serializers of their objects.
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+        md += "# Classes\n\n| Id | Class |\n|---:|---|\n";
+        for class in &self.classes {
+            writeln!(md, "| {} | {} |", class.id, self.link(&class.name)).unwrap();
+        }
+        md += "\n# Serializers\n";
+        for serializer in &self.serializers {
+            writeln!(md, "\n## {}\n", serializer.id()).unwrap();
+            if serializer.fields.is_empty() {
+                md += "No fields.\n";
+                continue;
+            }
+            md += "| Field | Type | Encoder | Bits | Range | Flags | Serializer |\n";
+            md += "|---|---|---|---:|---|---:|---|\n";
+            for field in &serializer.fields {
+                let mut types: Vec<_> = field.serializer.iter().map(|s| self.link(s)).collect();
+                types.extend(field.polymorphic_types.iter().map(|s| self.link(s)));
+                let range = match (field.low_value, field.high_value) {
+                    (None, None) => String::new(),
+                    (low, high) => format!(
+                        "{}..{}",
+                        low.map(|v| v.to_string()).unwrap_or_default(),
+                        high.map(|v| v.to_string()).unwrap_or_default()
+                    ),
+                };
+                writeln!(
+                    md,
+                    "| {} | `{}` | {} | {} | {range} | {} | {} |",
+                    field.path(),
+                    field.var_type,
+                    field.encoder.as_deref().unwrap_or_default(),
+                    field.bit_count.map(|v| v.to_string()).unwrap_or_default(),
+                    field
+                        .encode_flags
+                        .map(|v| v.to_string())
+                        .unwrap_or_default(),
+                    types.join(", ")
+                )
+                .unwrap();
+            }
+        }
+        md
+    }
+
+    /// Returns a Markdown link to the section of the serializer `id`, or `id` if there is none.
+    fn link(&self, id: &str) -> String {
+        if !self.serializers.iter().any(|s| s.id() == id) {
+            return id.to_string();
+        }
+        let anchor: String = id
+            .chars()
+            .filter(|c| c.is_alphanumeric() || matches!(c, '_' | '-'))
+            .collect();
+        format!("[{id}](#{})", anchor.to_lowercase())
+    }
+}
+
+/// Returns the serializer of `other` matching `serializer` of `schema`: the one with the same name
+/// and version or, when the version changed, one with the same name and a version missing from
+/// `schema`.
+fn counterpart<'a>(
+    serializer: &SerializerSchema,
+    schema: &Schema,
+    other: &'a Schema,
+) -> Option<&'a SerializerSchema> {
+    let has_version =
+        |schema: &Schema, s: &SerializerSchema| schema.serializers.iter().any(|t| t.id() == s.id());
+    other
+        .serializers
+        .iter()
+        .find(|s| s.id() == serializer.id())
+        .or_else(|| {
+            other
+                .serializers
+                .iter()
+                .find(|s| s.name == serializer.name && !has_version(schema, s))
+        })
+}
+
+/// Returns the schema of a demo.
+pub fn read_demo(read: &mut dyn Read) -> anyhow::Result<Schema> {
+    let (send_tables, class_info) = cs2_demo::read_send_tables(read)?;
+    Schema::from_proto(&send_tables, &class_info)
+}
+
+/// Reads the schema of a demo, or of a JSON dump if `path` ends with `.json`.
+pub fn load(path: &Path) -> anyhow::Result<Schema> {
+    let mut file = std::fs::File::open(path)?;
+    if path.extension().is_some_and(|ext| ext == "json") {
+        Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+    } else {
+        read_demo(&mut file)
+    }
+}
+
+/// A difference between the schemas of two game versions. Serializers are matched by name
+/// and version, or by name alone when the version changed.
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    AddedClass(String),
+    RemovedClass(String),
+    AddedSerializer(String),
+    RemovedSerializer(String),
+    SerializerVersion {
+        serializer: String,
+        old: i32,
+        new: i32,
+    },
+    AddedField {
+        serializer: String,
+        field: String,
+        var_type: String,
+    },
+    RemovedField {
+        serializer: String,
+        field: String,
+    },
+    FieldAttribute {
+        serializer: String,
+        field: String,
+        attribute: &'static str,
+        old: String,
+        new: String,
+    },
+}
+
+impl Change {
+    /// Returns true if code reading the old classes by name can fail to find them, or can find a
+    /// value of a different type.
+    pub fn is_breaking(&self) -> bool {
+        match self {
+            Change::RemovedClass(_)
+            | Change::RemovedSerializer(_)
+            | Change::RemovedField { .. } => true,
+            Change::FieldAttribute { attribute, .. } => *attribute == "var_type",
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::AddedClass(class) => write!(f, "added class {class}"),
+            Change::RemovedClass(class) => write!(f, "removed class {class}"),
+            Change::AddedSerializer(s) => write!(f, "added serializer {s}"),
+            Change::RemovedSerializer(s) => write!(f, "removed serializer {s}"),
+            Change::SerializerVersion {
+                serializer,
+                old,
+                new,
+            } => write!(f, "changed version of {serializer} from {old} to {new}"),
+            Change::AddedField {
+                serializer,
+                field,
+                var_type,
+            } => write!(f, "added field {serializer}.{field} of type {var_type}"),
+            Change::RemovedField { serializer, field } => {
+                write!(f, "removed field {serializer}.{field}")
+            }
+            Change::FieldAttribute {
+                serializer,
+                field,
+                attribute,
+                old,
+                new,
+            } => write!(
+                f,
+                "changed {attribute} of {serializer}.{field} from {old} to {new}"
+            ),
+        }
+    }
+}
+
+/// Returns the changes from the `old` schema to the `new` one.
+pub fn diff(old: &Schema, new: &Schema) -> Vec<Change> {
+    let has_class = |schema: &Schema, name: &str| schema.classes.iter().any(|c| c.name == name);
+    let mut changes = Vec::new();
+    for class in old.classes.iter().filter(|c| !has_class(new, &c.name)) {
+        changes.push(Change::RemovedClass(class.name.clone()));
+    }
+    for class in new.classes.iter().filter(|c| !has_class(old, &c.name)) {
+        changes.push(Change::AddedClass(class.name.clone()));
+    }
+    for old_serializer in &old.serializers {
+        let serializer = || old_serializer.id();
+        let Some(new_serializer) = counterpart(old_serializer, old, new) else {
+            changes.push(Change::RemovedSerializer(serializer()));
+            continue;
+        };
+        if old_serializer.version != new_serializer.version {
+            changes.push(Change::SerializerVersion {
+                serializer: old_serializer.name.clone(),
+                old: old_serializer.version,
+                new: new_serializer.version,
+            });
+        }
+        for old_field in &old_serializer.fields {
+            let Some(new_field) = new_serializer
+                .fields
+                .iter()
+                .find(|f| f.path() == old_field.path())
+            else {
+                changes.push(Change::RemovedField {
+                    serializer: serializer(),
+                    field: old_field.path(),
+                });
+                continue;
+            };
+            let attributes = old_field.attributes().into_iter();
+            for ((attribute, old), (_, new)) in attributes.zip(new_field.attributes()) {
+                if old != new {
+                    changes.push(Change::FieldAttribute {
+                        serializer: serializer(),
+                        field: old_field.path(),
+                        attribute,
+                        old,
+                        new,
+                    });
+                }
+            }
+        }
+        for new_field in &new_serializer.fields {
+            if !old_serializer
+                .fields
+                .iter()
+                .any(|f| f.path() == new_field.path())
+            {
+                changes.push(Change::AddedField {
+                    serializer: serializer(),
+                    field: new_field.path(),
+                    var_type: new_field.var_type.clone(),
+                });
+            }
+        }
+    }
+    for new_serializer in &new.serializers {
+        if counterpart(new_serializer, new, old).is_none() {
+            changes.push(Change::AddedSerializer(new_serializer.id()));
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protobuf::Message;
+
+    fn field(name: &str, var_type: &str) -> FieldSchema {
+        FieldSchema {
+            name: name.to_string(),
+            var_type: var_type.to_string(),
+            send_node: None,
+            encoder: None,
+            bit_count: None,
+            low_value: None,
+            high_value: None,
+            encode_flags: None,
+            serializer: None,
+            polymorphic_types: Vec::new(),
+        }
+    }
+
+    fn serializer(name: &str, version: i32, fields: Vec<FieldSchema>) -> SerializerSchema {
+        SerializerSchema {
+            name: name.to_string(),
+            version,
+            fields,
+        }
+    }
+
+    #[test]
+    fn demo_schema() -> anyhow::Result<()> {
+        let send_tables = CDemoSendTables::parse_from_bytes(include_bytes!(
+            "../../cs2-demo/src/testdata/cdemosendtables.binpb"
+        ))?;
+        let class_info = CDemoClassInfo::parse_from_bytes(include_bytes!(
+            "../../cs2-demo/src/testdata/cdemoclassinfo.binpb"
+        ))?;
+        let schema = Schema::from_proto(&send_tables, &class_info)?;
+        let serializer = |name| schema.serializers.iter().find(|s| s.name == name);
+        assert!(schema
+            .classes
+            .iter()
+            .any(|c| c.name == "CCSPlayerController"));
+        let controller = serializer("CCSPlayerController").unwrap();
+        assert!(controller.fields.iter().any(|f| f.name == "m_iTeamNum"));
+        let pawn = serializer("CCSPlayerPawn").unwrap();
+        let body = pawn
+            .fields
+            .iter()
+            .find(|f| f.name == "CBodyComponent")
+            .unwrap();
+        assert_eq!(
+            body.serializer.as_deref(),
+            Some("CBodyComponentBaseAnimGraph@6")
+        );
+        assert!(serializer("CBodyComponentBaseAnimGraph").is_some());
+
+        let md = schema.to_markdown();
+        assert!(md.contains("| [CCSPlayerController](#ccsplayercontroller) |"));
+        assert!(md.contains("\n## CCSPlayerController\n"));
+        assert!(md.contains("[CBodyComponentBaseAnimGraph@6](#cbodycomponentbaseanimgraph6)"));
+
+        let json = serde_json::to_string(&schema)?;
+        assert_eq!(serde_json::from_str::<Schema>(&json)?, schema);
+        assert!(diff(&schema, &schema).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn markdown() {
+        let mut origin = field("m_vecOrigin", "Vector");
+        origin.encoder = Some("coord".to_string());
+        origin.bit_count = Some(10);
+        origin.low_value = Some(-1.0);
+        origin.high_value = Some(1.0);
+        let mut body = field("m_CBodyComponent", "CBodyComponent*");
+        body.serializer = Some("CBodyComponent@1".to_string());
+        body.polymorphic_types = vec!["CBodyComponentPoint".to_string()];
+        let schema = Schema {
+            classes: vec![ClassSchema {
+                id: 3,
+                name: "CBaseEntity".to_string(),
+            }],
+            serializers: vec![
+                serializer("CBaseEntity", 0, vec![body]),
+                serializer("CBodyComponent", 1, vec![origin]),
+            ],
+        };
+        assert_eq!(
+            schema.to_markdown(),
+            r#"# Classes
+
+| Id | Class |
+|---:|---|
+| 3 | [CBaseEntity](#cbaseentity) |
+
+# Serializers
+
+## CBaseEntity
+
+| Field | Type | Encoder | Bits | Range | Flags | Serializer |
+|---|---|---|---:|---|---:|---|
+| m_CBodyComponent | `CBodyComponent*` |  |  |  |  | [CBodyComponent@1](#cbodycomponent1), CBodyComponentPoint |
+
+## CBodyComponent@1
+
+| Field | Type | Encoder | Bits | Range | Flags | Serializer |
+|---|---|---|---:|---|---:|---|
+| m_vecOrigin | `Vector` | coord | 10 | -1..1 |  |  |
+"#
+        );
+    }
+
+    #[test]
+    fn compatibility() {
+        let class = |name: &str| ClassSchema {
+            id: 0,
+            name: name.to_string(),
+        };
+        let mut health = field("m_iHealth", "int32");
+        let old = Schema {
+            classes: vec![class("CCSPlayerPawn"), class("CHostage")],
+            serializers: vec![
+                serializer(
+                    "CCSPlayerPawn",
+                    0,
+                    vec![health.clone(), field("m_bIsScoped", "bool")],
+                ),
+                serializer("CHostage", 0, vec![]),
+            ],
+        };
+        health.bit_count = Some(8);
+        let new = Schema {
+            classes: vec![class("CCSPlayerPawn"), class("CChicken")],
+            serializers: vec![
+                serializer(
+                    "CCSPlayerPawn",
+                    1,
+                    vec![
+                        health,
+                        field("m_bIsScoped", "uint8"),
+                        field("m_iArmor", "int32"),
+                    ],
+                ),
+                serializer("CChicken", 0, vec![]),
+            ],
+        };
+        let changes = diff(&old, &new);
+        assert_eq!(
+            changes.iter().map(Change::to_string).collect::<Vec<_>>(),
+            [
+                "removed class CHostage",
+                "added class CChicken",
+                "changed version of CCSPlayerPawn from 0 to 1",
+                "changed bit_count of CCSPlayerPawn.m_iHealth from - to 8",
+                "changed var_type of CCSPlayerPawn.m_bIsScoped from bool to uint8",
+                "added field CCSPlayerPawn.m_iArmor of type int32",
+                "removed serializer CHostage",
+                "added serializer CChicken",
+            ]
+        );
+        let breaking: Vec<_> = changes.iter().map(Change::is_breaking).collect();
+        assert_eq!(
+            breaking,
+            [true, false, false, false, true, false, true, false]
+        );
+    }
+}
@@ -0,0 +1,51 @@
+/* C ABI of csdemoparser, implemented by the csdemoparser-ffi crate. */
+#ifndef CSDEMOPARSER_H
+#define CSDEMOPARSER_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/*
+ * Receives each event of the demo, as a JSON object with the same content as an item of the
+ * "events" array of the csdemoparser output. `event` is only valid during the call. Returning
+ * a non-zero value stops the delivery of events.
+ */
+typedef int (*csdemoparser_event_callback)(const char *event, void *user_data);
+
+/*
+ * All functions returning a string return the csdemoparser output as JSON, to be released with
+ * csdemoparser_free_string. On failure they return NULL and, if `error` is not NULL, store in
+ * it a JSON object {"kind": ..., "message": ...} which must also be released with
+ * csdemoparser_free_string. `kind` is one of "invalid_argument", "io", "parse" or "panic".
+ * Parse errors also have the `code` and `position` of the csdemoparser error output.
+ */
+
+/* Parses the demo at the UTF-8 encoded `path`. */
+char *csdemoparser_parse_file(const char *path, char **error);
+
+/* Parses the demo held in the `len` bytes at `data`. */
+char *csdemoparser_parse_bytes(const uint8_t *data, size_t len, char **error);
+
+/*
+ * Like csdemoparser_parse_file and csdemoparser_parse_bytes, but each event is passed to
+ * `callback` instead of being included in the returned JSON. A NULL `callback` is an
+ * "invalid_argument" error.
+ */
+char *csdemoparser_parse_file_events(const char *path, csdemoparser_event_callback callback,
+                                     void *user_data, char **error);
+char *csdemoparser_parse_bytes_events(const uint8_t *data, size_t len,
+                                      csdemoparser_event_callback callback, void *user_data,
+                                      char **error);
+
+/* Releases a string returned by this library. Does nothing if `s` is NULL. */
+void csdemoparser_free_string(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* CSDEMOPARSER_H */
@@ -0,0 +1,271 @@
+//! C ABI for csdemoparser, to parse demos without spawning a process. The functions are declared
+//! in `include/csdemoparser.h`, which documents the API.
+
+use csdemoparser::demoinfo::DemoInfo;
+use csdemoparser::error::{ErrorCode, ParseError, Position};
+use serde::Serialize;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::fs::File;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+pub type EventCallback = extern "C" fn(event: *const c_char, user_data: *mut c_void) -> c_int;
+
+#[derive(Serialize, Debug)]
+struct Error {
+    kind: ErrorKind,
+    message: String,
+    /// The code and position of parse errors, like in the csdemoparser error output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<ErrorCode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position: Option<Box<Position>>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum ErrorKind {
+    InvalidArgument,
+    Io,
+    Parse,
+    Panic,
+}
+
+impl Error {
+    fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            code: None,
+            position: None,
+        }
+    }
+
+    fn parse(error: ParseError) -> Self {
+        let ParseError {
+            code,
+            message,
+            position,
+        } = error;
+        Self {
+            kind: ErrorKind::Parse,
+            message,
+            code: Some(code),
+            position: Some(Box::new(position)),
+        }
+    }
+}
+
+/// # Safety
+///
+/// `path` must be a NUL-terminated string and `error` must be NULL or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn csdemoparser_parse_file(
+    path: *const c_char,
+    error: *mut *mut c_char,
+) -> *mut c_char {
+    run(error, || to_json(&parse_file(path)?))
+}
+
+/// # Safety
+///
+/// `data` must be valid for reads of `len` bytes and `error` must be NULL or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn csdemoparser_parse_bytes(
+    data: *const u8,
+    len: usize,
+    error: *mut *mut c_char,
+) -> *mut c_char {
+    run(error, || to_json(&parse_bytes(data, len)?))
+}
+
+/// # Safety
+///
+/// `path` must be a NUL-terminated string and `error` must be NULL or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn csdemoparser_parse_file_events(
+    path: *const c_char,
+    callback: Option<EventCallback>,
+    user_data: *mut c_void,
+    error: *mut *mut c_char,
+) -> *mut c_char {
+    run(error, || {
+        let callback = non_null_callback(callback)?;
+        send_events(parse_file(path)?, callback, user_data)
+    })
+}
+
+/// # Safety
+///
+/// `data` must be valid for reads of `len` bytes and `error` must be NULL or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn csdemoparser_parse_bytes_events(
+    data: *const u8,
+    len: usize,
+    callback: Option<EventCallback>,
+    user_data: *mut c_void,
+    error: *mut *mut c_char,
+) -> *mut c_char {
+    run(error, || {
+        let callback = non_null_callback(callback)?;
+        send_events(parse_bytes(data, len)?, callback, user_data)
+    })
+}
+
+/// # Safety
+///
+/// `s` must be NULL or a string returned by this library which was not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn csdemoparser_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+unsafe fn parse_file(path: *const c_char) -> Result<DemoInfo, Error> {
+    if path.is_null() {
+        return Err(Error::new(ErrorKind::InvalidArgument, "path is NULL"));
+    }
+    let path = CStr::from_ptr(path)
+        .to_str()
+        .map_err(|e| Error::new(ErrorKind::InvalidArgument, format!("path: {e}")))?;
+    let mut file = File::open(path).map_err(|e| Error::new(ErrorKind::Io, e.to_string()))?;
+    csdemoparser::parse(&mut file).map_err(|e| Error::parse(ParseError::from(&e)))
+}
+
+unsafe fn parse_bytes(data: *const u8, len: usize) -> Result<DemoInfo, Error> {
+    if data.is_null() {
+        return Err(Error::new(ErrorKind::InvalidArgument, "data is NULL"));
+    }
+    let data = std::slice::from_raw_parts(data, len);
+    csdemoparser::parse_bytes(data).map_err(|e| Error::parse(ParseError::from(&e)))
+}
+
+/// Function pointers cannot be NULL in Rust, a NULL callback is received as None.
+fn non_null_callback(callback: Option<EventCallback>) -> Result<EventCallback, Error> {
+    callback.ok_or_else(|| Error::new(ErrorKind::InvalidArgument, "callback is NULL"))
+}
+
+/// Passes the events to `callback` and returns the rest of `demoinfo` as JSON.
+fn send_events(
+    mut demoinfo: DemoInfo,
+    callback: EventCallback,
+    user_data: *mut c_void,
+) -> Result<String, Error> {
+    for event in std::mem::take(&mut demoinfo.events) {
+        let event = CString::new(to_json(&event)?).unwrap_or_default();
+        if callback(event.as_ptr(), user_data) != 0 {
+            break;
+        }
+    }
+    to_json(&demoinfo)
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<String, Error> {
+    serde_json::to_string(value).map_err(|e| Error::new(ErrorKind::Parse, e.to_string()))
+}
+
+/// Runs `f`, turning panics into errors, and returns its result as a string owned by the caller.
+unsafe fn run(error: *mut *mut c_char, f: impl FnOnce() -> Result<String, Error>) -> *mut c_char {
+    let result = catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|panic| {
+        let message = if let Some(s) = panic.downcast_ref::<&str>() {
+            s.to_string()
+        } else if let Some(s) = panic.downcast_ref::<String>() {
+            s.clone()
+        } else {
+            "unknown panic".to_string()
+        };
+        Err(Error::new(ErrorKind::Panic, message))
+    });
+    let json = match result {
+        Ok(json) => json,
+        Err(e) => {
+            if !error.is_null() {
+                let json = serde_json::to_string(&e).unwrap_or_default();
+                *error = CString::new(json).unwrap_or_default().into_raw();
+            }
+            return ptr::null_mut();
+        }
+    };
+    CString::new(json).unwrap_or_default().into_raw()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe fn take_string(s: *mut c_char) -> String {
+        let string = CStr::from_ptr(s).to_str().unwrap().to_string();
+        csdemoparser_free_string(s);
+        string
+    }
+
+    #[test]
+    fn structured_errors() {
+        unsafe {
+            let mut error = ptr::null_mut();
+            let data = b"NOTADEMO";
+            let result = csdemoparser_parse_bytes(data.as_ptr(), data.len(), &mut error);
+            assert!(result.is_null());
+            let json: serde_json::Value = serde_json::from_str(&take_string(error)).unwrap();
+            assert_eq!(json["kind"], "parse");
+            assert_eq!(json["code"], "invalid_demo_type");
+            assert!(json["position"].is_object());
+
+            let path = CString::new("/nonexistent/demo.dem").unwrap();
+            let result = csdemoparser_parse_file(path.as_ptr(), &mut error);
+            assert!(result.is_null());
+            let json: serde_json::Value = serde_json::from_str(&take_string(error)).unwrap();
+            assert_eq!(json["kind"], "io");
+
+            let result = csdemoparser_parse_file(ptr::null(), ptr::null_mut());
+            assert!(result.is_null());
+
+            let result = csdemoparser_parse_bytes_events(
+                data.as_ptr(),
+                data.len(),
+                None,
+                ptr::null_mut(),
+                &mut error,
+            );
+            assert!(result.is_null());
+            let json: serde_json::Value = serde_json::from_str(&take_string(error)).unwrap();
+            assert_eq!(json["kind"], "invalid_argument");
+        }
+    }
+
+    #[test]
+    fn events_are_sent_to_callback() {
+        extern "C" fn callback(event: *const c_char, user_data: *mut c_void) -> c_int {
+            let events = unsafe { &mut *(user_data as *mut Vec<String>) };
+            events.push(
+                unsafe { CStr::from_ptr(event) }
+                    .to_str()
+                    .unwrap()
+                    .to_string(),
+            );
+            (events.len() == 2) as c_int
+        }
+        let demoinfo = DemoInfo {
+            events: (0..3)
+                .map(|tick| serde_json::json!({ "tick": tick }))
+                .collect(),
+            ..Default::default()
+        };
+        let mut events: Vec<String> = Vec::new();
+        let json = send_events(demoinfo, callback, &mut events as *mut _ as *mut c_void).unwrap();
+        assert_eq!(events, vec![r#"{"tick":0}"#, r#"{"tick":1}"#]);
+        let demoinfo: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(demoinfo["events"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn panics_are_errors() {
+        unsafe {
+            let mut error = ptr::null_mut();
+            let result = run(&mut error, || panic!("boom"));
+            assert!(result.is_null());
+            assert_eq!(take_string(error), r#"{"kind":"panic","message":"boom"}"#);
+        }
+    }
+}
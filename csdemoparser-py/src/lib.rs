@@ -0,0 +1,141 @@
+//! Python bindings for csdemoparser.
+// Triggered by the code generated by #[pyfunction].
+#![allow(clippy::useless_conversion)]
+// Triggered by the gil-refs feature checks of create_exception!.
+#![allow(unexpected_cfgs)]
+
+use csdemoparser::demoinfo::DemoInfo;
+use pyo3::create_exception;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use serde_json::Value;
+use std::fs::File;
+
+create_exception!(
+    csdemoparser,
+    ParseError,
+    PyValueError,
+    "Raised when a demo cannot be parsed. The `code` and `position` attributes are those of the \
+     csdemoparser error output."
+);
+
+/// Parses the demo at `path` and returns the csdemoparser output as a dict.
+#[pyfunction]
+fn parse(py: Python<'_>, path: &str) -> PyResult<PyObject> {
+    let demoinfo = parse_file(py, path)?;
+    let value = serde_json::to_value(&demoinfo).map_err(to_value_error)?;
+    to_python(py, &value)
+}
+
+/// Parses the whole demo at `path`, then returns an iterator over its events. This is not
+/// streaming: events are only final once the demo is parsed, the stats events of coaches are
+/// removed at the end. Each event is only converted to a dict when it is reached, which keeps
+/// a single copy of the events in memory.
+#[pyfunction]
+fn parse_events(py: Python<'_>, path: &str) -> PyResult<EventIterator> {
+    let demoinfo = parse_file(py, path)?;
+    Ok(EventIterator {
+        events: demoinfo.events.into_iter(),
+    })
+}
+
+/// Parses the demo at `path` and returns its `player_death` and `player_hurt` events as
+/// columns, ready for `pandas.DataFrame`: `{"kills": {field: [values]}, "hurts": {...}}`.
+#[pyfunction]
+fn kills_and_hurts(py: Python<'_>, path: &str) -> PyResult<PyObject> {
+    let demoinfo = parse_file(py, path)?;
+    let result = PyDict::new_bound(py);
+    for (name, event_type) in [("kills", "player_death"), ("hurts", "player_hurt")] {
+        let columns = PyDict::new_bound(py);
+        for (field, values) in demoinfo.event_columns(event_type) {
+            let values = values
+                .iter()
+                .map(|v| to_python(py, v))
+                .collect::<PyResult<Vec<_>>>()?;
+            columns.set_item(field, PyList::new_bound(py, values))?;
+        }
+        result.set_item(name, columns)?;
+    }
+    Ok(result.into_py(py))
+}
+
+#[pyclass]
+struct EventIterator {
+    events: std::vec::IntoIter<Value>,
+}
+
+#[pymethods]
+impl EventIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<'_, Self>) -> PyResult<Option<PyObject>> {
+        let py = slf.py();
+        slf.events.next().map(|e| to_python(py, &e)).transpose()
+    }
+}
+
+fn parse_file(py: Python<'_>, path: &str) -> PyResult<DemoInfo> {
+    let mut file = File::open(path)?;
+    py.allow_threads(|| csdemoparser::parse(&mut file))
+        .map_err(|e| parse_error(py, csdemoparser::error::ParseError::from(&e)))
+}
+
+fn parse_error(py: Python<'_>, error: csdemoparser::error::ParseError) -> PyErr {
+    let err = ParseError::new_err(error.message.clone());
+    let set_attributes = || -> PyResult<()> {
+        let value = err.value_bound(py);
+        let code = serde_json::to_value(error.code).map_err(to_value_error)?;
+        value.setattr("code", to_python(py, &code)?)?;
+        let position = serde_json::to_value(&error.position).map_err(to_value_error)?;
+        value.setattr("position", to_python(py, &position)?)
+    };
+    match set_attributes() {
+        Ok(()) => err,
+        Err(e) => e,
+    }
+}
+
+fn to_value_error(e: serde_json::Error) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
+fn to_python(py: Python<'_>, value: &Value) -> PyResult<PyObject> {
+    Ok(match value {
+        Value::Null => py.None(),
+        Value::Bool(b) => b.into_py(py),
+        Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(u), _) => u.into_py(py),
+            (_, Some(i)) => i.into_py(py),
+            _ => n.as_f64().unwrap_or(f64::NAN).into_py(py),
+        },
+        Value::String(s) => s.into_py(py),
+        Value::Array(values) => {
+            let values = values
+                .iter()
+                .map(|v| to_python(py, v))
+                .collect::<PyResult<Vec<_>>>()?;
+            PyList::new_bound(py, values).into_py(py)
+        }
+        Value::Object(fields) => {
+            let dict = PyDict::new_bound(py);
+            for (key, value) in fields {
+                dict.set_item(key, to_python(py, value)?)?;
+            }
+            dict.into_py(py)
+        }
+    })
+}
+
+#[pymodule]
+#[pyo3(name = "csdemoparser")]
+fn csdemoparser_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(parse, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_events, m)?)?;
+    m.add_function(wrap_pyfunction!(kills_and_hurts, m)?)?;
+    m.add_class::<EventIterator>()?;
+    m.add("ParseError", m.py().get_type_bound::<ParseError>())?;
+    Ok(())
+}
@@ -0,0 +1,12 @@
+//! WebAssembly bindings for csdemoparser, to parse demos in the browser.
+
+use wasm_bindgen::prelude::*;
+
+/// Parses the demo held in `data` and returns the csdemoparser output as an object, with the
+/// same content as `JSON.parse` of the JSON output.
+#[wasm_bindgen]
+pub fn parse(data: &[u8]) -> Result<JsValue, JsError> {
+    let demoinfo = csdemoparser::parse_bytes(data).map_err(|e| JsError::new(&format!("{e:#}")))?;
+    let json = serde_json::to_string(&demoinfo)?;
+    js_sys::JSON::parse(&json).map_err(|_| JsError::new("invalid JSON"))
+}
@@ -0,0 +1,491 @@
+use cs2_demo::entity::Property;
+use cs2_demo::game_event::Descriptor;
+use cs2_demo::proto::demo::CDemoFileHeader;
+use cs2_demo::proto::gameevents::{cmsg_source1legacy_game_event, CMsgSource1LegacyGameEvent};
+use cs2_demo::proto::networkbasetypes::CMsgPlayerInfo;
+use cs2_demo::proto::usermessages::CUserMessageSayText2;
+use csgo_demo::proto::cstrike15_usermessages::{CUSRMsg_SayText2, CUSRMsg_ServerRankUpdate};
+use csgo_demo::proto::netmessages::{
+    csvcmsg_game_event, csvcmsg_game_event_list, CSVCMsg_GameEvent,
+};
+use csgo_demo::string_table::PlayerInfo;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufReader, BufWriter, IntoInnerError, Read, Seek, Write};
+
+const STEAM_ID_BASE: u64 = 76561197960265728;
+const SERVER_NAME: &str = "Anonymous";
+/// Names shorter than this are only replaced as whole words in chat text, they would be found
+/// inside other words otherwise.
+const MIN_TEXT_NAME_LEN: usize = 3;
+/// Characters of the pseudonyms too short for `PlayerN` or `PN`.
+const PSEUDONYM_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+/// Events whose `name` keys are player names.
+const PLAYER_EVENTS: &[&str] = &[
+    "player_connect",
+    "player_disconnect",
+    "player_info",
+    "player_changename",
+];
+
+pub(crate) fn anonymize_csgo<W: Write + Seek>(read: &mut dyn Read, write: W) -> anyhow::Result<W> {
+    let mut write = BufWriter::new(write);
+    csgo_demo::rewrite(
+        &mut BufReader::new(read),
+        &mut write,
+        &mut Pseudonyms::default(),
+    )?;
+    Ok(write.into_inner().map_err(IntoInnerError::into_error)?)
+}
+
+pub(crate) fn anonymize_cs2<W: Write + Seek>(read: &mut dyn Read, write: W) -> anyhow::Result<W> {
+    let write = cs2_demo::rewrite(
+        &mut BufReader::new(read),
+        BufWriter::new(write),
+        &mut Pseudonyms::default(),
+    )?;
+    Ok(write.into_inner().map_err(IntoInnerError::into_error)?)
+}
+
+/// Assigns stable pseudonyms to the players of a demo. The n-th player found is named PlayerN,
+/// padded or shortened to the size of the original name, and gets the Steam account id N.
+/// Bots and GOTV keep their names.
+#[derive(Default)]
+struct Pseudonyms {
+    /// Maps xuid to player number.
+    players: HashMap<u64, u32>,
+    /// Maps name to player number.
+    names: HashMap<String, u32>,
+    count: u32,
+    /// Maps player number and name size to pseudonym.
+    pseudonyms: HashMap<(u32, usize), String>,
+    /// All the values of `pseudonyms`, so that two players never get the same one.
+    used_pseudonyms: HashSet<String>,
+}
+
+impl Pseudonyms {
+    fn new_player(&mut self) -> u32 {
+        self.count += 1;
+        self.count
+    }
+
+    /// Returns the number of the player with the given xuid.
+    fn player(&mut self, xuid: u64) -> u32 {
+        if let Some(&n) = self.players.get(&xuid) {
+            return n;
+        }
+        let n = self.new_player();
+        self.players.insert(xuid, n);
+        n
+    }
+
+    fn xuid(&mut self, xuid: u64) -> u64 {
+        if xuid == 0 {
+            return 0;
+        }
+        STEAM_ID_BASE + self.player(xuid) as u64
+    }
+
+    fn account_id(&mut self, account_id: u32) -> u32 {
+        if account_id == 0 {
+            return 0;
+        }
+        self.player(STEAM_ID_BASE + account_id as u64)
+    }
+
+    /// Returns the pseudonym of `name`. `player` is the player number, if known. Otherwise it is
+    /// set to the number of the player who last used this name, or of a new player.
+    fn name(&mut self, name: &str, player: &mut Option<u32>) -> String {
+        if name.is_empty() {
+            return String::new();
+        }
+        let n = match *player {
+            Some(n) => n,
+            None => match self.names.get(name) {
+                Some(&n) => n,
+                None => self.new_player(),
+            },
+        };
+        *player = Some(n);
+        self.names.insert(name.to_string(), n);
+        self.pseudonym(n, name.len())
+    }
+
+    /// Returns the pseudonym of player `n` with the given size in bytes, so that it can replace a
+    /// name in bit-packed data. Players get different pseudonyms, even when shortened.
+    fn pseudonym(&mut self, n: u32, len: usize) -> String {
+        if let Some(pseudonym) = self.pseudonyms.get(&(n, len)) {
+            return pseudonym.clone();
+        }
+        let pseudonym = pseudonym_candidates(n, len)
+            .find(|p| !self.used_pseudonyms.contains(p))
+            // There are more pseudonyms of each size than players in a demo.
+            .unwrap_or_else(|| pseudonym(n, len));
+        self.used_pseudonyms.insert(pseudonym.clone());
+        self.pseudonyms.insert((n, len), pseudonym.clone());
+        pseudonym
+    }
+
+    /// Rewrites the Steam ids found in game events and in CS:GO player info. Values which are
+    /// not Steam ids, like `BOT`, are kept.
+    fn steam_id(&mut self, steam_id: &str) -> String {
+        let Some(account_id) = parse_account_id(steam_id) else {
+            return steam_id.to_string();
+        };
+        let n = self.account_id(account_id);
+        if steam_id.starts_with("[U:1:") {
+            format!("[U:1:{n}]")
+        } else if let Some((universe, _)) = steam_id
+            .strip_prefix("STEAM_")
+            .and_then(|s| s.split_once(':'))
+        {
+            format!("STEAM_{universe}:{}:{}", n & 1, n >> 1)
+        } else {
+            (STEAM_ID_BASE + n as u64).to_string()
+        }
+    }
+
+    /// Replaces the known player names found in `text`.
+    fn text(&self, text: &str) -> String {
+        let mut names: Vec<_> = self.names.iter().collect();
+        // Longest first, in case a name contains another.
+        names.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+        let mut text = text.to_string();
+        for (name, &n) in names {
+            let Some(pseudonym) = self.pseudonyms.get(&(n, name.len())) else {
+                continue;
+            };
+            text = if name.len() >= MIN_TEXT_NAME_LEN {
+                text.replace(name.as_str(), pseudonym)
+            } else {
+                replace_words(&text, name, pseudonym)
+            };
+        }
+        text
+    }
+
+    fn rewrite_event_keys<K: EventKey>(&mut self, event_name: &str, mut keys: Vec<(&str, &mut K)>) {
+        let mut player = None;
+        let mut bot = false;
+        for (name, key) in keys.iter() {
+            match *name {
+                "xuid" | "steamid" if key.uint64() != 0 => player = Some(self.player(key.uint64())),
+                "networkid" => match parse_account_id(key.string()) {
+                    Some(account_id) => player = Some(self.account_id(account_id)),
+                    None => bot |= key.string().eq_ignore_ascii_case("bot"),
+                },
+                "bot" => bot |= key.bool(),
+                _ => (),
+            }
+        }
+        let player_event = PLAYER_EVENTS.contains(&event_name);
+        for (name, key) in keys.iter_mut() {
+            match *name {
+                "name" | "oldname" | "newname" if player_event && !bot => {
+                    let name = self.name(key.string(), &mut player);
+                    key.set_string(name);
+                }
+                "xuid" | "steamid" => {
+                    let xuid = self.xuid(key.uint64());
+                    key.set_uint64(xuid);
+                }
+                "networkid" => {
+                    let steam_id = self.steam_id(key.string());
+                    key.set_string(steam_id);
+                }
+                "friendsid" => {
+                    let account_id = self.account_id(key.long() as u32);
+                    key.set_long(account_id as i32);
+                }
+                "address" => key.set_string(String::new()),
+                _ => (),
+            }
+        }
+    }
+}
+
+impl cs2_demo::Rewriter for Pseudonyms {
+    fn rewrite_file_header(&mut self, header: &mut CDemoFileHeader) {
+        header.set_server_name(SERVER_NAME.to_string());
+    }
+
+    fn rewrite_server_info(
+        &mut self,
+        server_info: &mut cs2_demo::proto::netmessages::CSVCMsg_ServerInfo,
+    ) {
+        server_info.set_host_name(SERVER_NAME.to_string());
+    }
+
+    fn rewrite_player_info(&mut self, player_info: &mut CMsgPlayerInfo) {
+        if player_info.fakeplayer() || player_info.ishltv() {
+            return;
+        }
+        let mut player = Some(self.player(player_info.xuid()));
+        let name = self.name(player_info.name(), &mut player);
+        player_info.set_name(name);
+        player_info.set_xuid(self.xuid(player_info.xuid()));
+        if player_info.has_steamid() {
+            player_info.set_steamid(self.xuid(player_info.steamid()));
+        }
+    }
+
+    fn rewrite_game_event(
+        &mut self,
+        event: &mut CMsgSource1LegacyGameEvent,
+        descriptor: &Descriptor,
+    ) {
+        let keys = descriptor
+            .keys
+            .iter()
+            .map(|k| k.name.as_str())
+            .zip(event.keys.iter_mut())
+            .collect();
+        self.rewrite_event_keys(&descriptor.name, keys);
+    }
+
+    fn rewrite_say_text2(&mut self, msg: &mut CUserMessageSayText2) {
+        let name = self.name(msg.param1(), &mut None);
+        msg.set_param1(name);
+        let text = self.text(msg.param2());
+        msg.set_param2(text);
+    }
+
+    /// Replaces the known player names and xuids, such as the `m_iszPlayerName` and `m_steamID`
+    /// of player controllers. Varint xuids keep their size, Steam ids all have the same high bits.
+    fn rewrite_entity_property(&mut self, _class: &str, _path: &str, value: &mut Property) {
+        match value {
+            Property::Str(s) => {
+                if let Some(&n) = self.names.get(&**s) {
+                    *s = self.pseudonym(n, s.len()).into();
+                }
+            }
+            Property::U64(xuid) if self.players.contains_key(xuid) => *xuid = self.xuid(*xuid),
+            _ => (),
+        }
+    }
+}
+
+impl csgo_demo::Rewriter for Pseudonyms {
+    fn rewrite_server_name(&mut self, server_name: &mut String) {
+        *server_name = SERVER_NAME.to_string();
+    }
+
+    fn rewrite_server_info(
+        &mut self,
+        server_info: &mut csgo_demo::proto::netmessages::CSVCMsg_ServerInfo,
+    ) {
+        server_info.set_host_name(SERVER_NAME.to_string());
+    }
+
+    fn rewrite_player_info(&mut self, player_info: &mut PlayerInfo) {
+        if player_info.fakeplayer || player_info.is_hltv {
+            return;
+        }
+        let mut player = Some(self.player(player_info.xuid));
+        player_info.name = self.name(&player_info.name, &mut player);
+        player_info.friends_name = self.name(&player_info.friends_name, &mut player);
+        player_info.xuid = self.xuid(player_info.xuid);
+        player_info.guid = self.steam_id(&player_info.guid);
+        player_info.friends_id = self.account_id(player_info.friends_id as u32) as i32;
+    }
+
+    fn rewrite_game_event(
+        &mut self,
+        event: &mut CSVCMsg_GameEvent,
+        descriptor: &csvcmsg_game_event_list::Descriptor_t,
+    ) {
+        let keys = descriptor
+            .keys
+            .iter()
+            .map(|k| k.name())
+            .zip(event.keys.iter_mut())
+            .collect();
+        self.rewrite_event_keys(descriptor.name(), keys);
+    }
+
+    fn rewrite_say_text2(&mut self, msg: &mut CUSRMsg_SayText2) {
+        if let Some(name) = msg.params.get_mut(0) {
+            *name = self.name(name, &mut None);
+        }
+        if let Some(text) = msg.params.get_mut(1) {
+            *text = self.text(text);
+        }
+    }
+
+    fn rewrite_server_rank_update(&mut self, msg: &mut CUSRMsg_ServerRankUpdate) {
+        for rank_update in msg.rank_update.iter_mut() {
+            let account_id = self.account_id(rank_update.account_id() as u32);
+            rank_update.set_account_id(account_id as i32);
+        }
+    }
+}
+
+/// Returns the preferred pseudonym of player `n` with the given size in bytes: `PlayerN` or `PN`
+/// padded with `_`, or the last digits of `N` when these are too long.
+fn pseudonym(n: u32, len: usize) -> String {
+    let mut name = format!("Player{n}");
+    if name.len() > len {
+        name = format!("P{n}");
+    }
+    if name.len() > len {
+        name = name[name.len() - len..].to_string();
+    }
+    while name.len() < len {
+        name.push('_');
+    }
+    name
+}
+
+/// Returns the pseudonyms of player `n` with the given size, starting with the preferred one,
+/// followed by all the strings of [`PSEUDONYM_CHARS`] of that size.
+fn pseudonym_candidates(n: u32, len: usize) -> impl Iterator<Item = String> {
+    let base = PSEUDONYM_CHARS.len();
+    let count = u32::try_from(len)
+        .ok()
+        .and_then(|len| base.checked_pow(len))
+        .unwrap_or(usize::MAX);
+    let others = (0..count).map(move |mut i| {
+        let mut name = vec![PSEUDONYM_CHARS[0]; len];
+        for c in name.iter_mut().rev() {
+            *c = PSEUDONYM_CHARS[i % base];
+            i /= base;
+        }
+        String::from_utf8(name).unwrap()
+    });
+    std::iter::once(pseudonym(n, len)).chain(others)
+}
+
+/// Replaces the occurrences of `from` in `text` which are not part of a longer word.
+fn replace_words(text: &str, from: &str, to: &str) -> String {
+    let is_word_char = |c: Option<char>| c.is_some_and(char::is_alphanumeric);
+    let mut result = String::with_capacity(text.len());
+    let mut last = 0;
+    for (start, _) in text.match_indices(from) {
+        let end = start + from.len();
+        if start < last
+            || is_word_char(text[..start].chars().next_back())
+            || is_word_char(text[end..].chars().next())
+        {
+            continue;
+        }
+        result.push_str(&text[last..start]);
+        result.push_str(to);
+        last = end;
+    }
+    result.push_str(&text[last..]);
+    result
+}
+
+/// Returns the account id of a Steam id in the `[U:1:account_id]`, `STEAM_X:Y:Z` or xuid
+/// formats.
+fn parse_account_id(steam_id: &str) -> Option<u32> {
+    if let Some(account_id) = steam_id
+        .strip_prefix("[U:1:")
+        .and_then(|s| s.strip_suffix(']'))
+    {
+        return account_id.parse().ok();
+    }
+    if let Some(s) = steam_id.strip_prefix("STEAM_") {
+        let mut parts = s.split(':').skip(1).map(|p| p.parse::<u32>().ok());
+        let (Some(Some(y)), Some(Some(z)), None) = (parts.next(), parts.next(), parts.next())
+        else {
+            return None;
+        };
+        return Some(z * 2 + y);
+    }
+    let xuid = steam_id.parse::<u64>().ok()?;
+    u32::try_from(xuid.checked_sub(STEAM_ID_BASE)?).ok()
+}
+
+/// Game event key of either CS:GO or CS2.
+trait EventKey {
+    fn string(&self) -> &str;
+    fn set_string(&mut self, value: String);
+    fn long(&self) -> i32;
+    fn set_long(&mut self, value: i32);
+    fn bool(&self) -> bool;
+    fn uint64(&self) -> u64;
+    fn set_uint64(&mut self, value: u64);
+}
+
+macro_rules! impl_event_key {
+    ($key:ty) => {
+        impl EventKey for $key {
+            fn string(&self) -> &str {
+                self.val_string()
+            }
+            fn set_string(&mut self, value: String) {
+                self.set_val_string(value)
+            }
+            fn long(&self) -> i32 {
+                self.val_long()
+            }
+            fn set_long(&mut self, value: i32) {
+                self.set_val_long(value)
+            }
+            fn bool(&self) -> bool {
+                self.val_bool()
+            }
+            fn uint64(&self) -> u64 {
+                self.val_uint64()
+            }
+            fn set_uint64(&mut self, value: u64) {
+                self.set_val_uint64(value)
+            }
+        }
+    };
+}
+
+impl_event_key!(cmsg_source1legacy_game_event::Key_t);
+impl_event_key!(csvcmsg_game_event::Key_t);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pseudonyms_keep_size() {
+        assert_eq!(pseudonym(3, 10), "Player3___");
+        assert_eq!(pseudonym(3, 7), "Player3");
+        assert_eq!(pseudonym(12, 4), "P12_");
+        assert_eq!(pseudonym(123, 2), "23");
+    }
+
+    #[test]
+    fn short_names() {
+        let mut pseudonyms = Pseudonyms::default();
+        let mut name = |n: u32, name: &str| {
+            let mut player = Some(n);
+            pseudonyms.name(name, &mut player)
+        };
+        assert_eq!(name(3, "x"), "3");
+        assert_eq!(name(13, "y"), "A");
+        assert_eq!(name(23, "zz"), "23");
+        assert_eq!(name(123, "ab"), "AA");
+        assert_eq!(name(123, "cd"), "AA");
+        assert_eq!(name(7, "abc"), "P7_");
+        assert_eq!(
+            pseudonyms.text("x: gg y, ab and abc. xylophone"),
+            "3: gg A, AA and P7_. xylophone"
+        );
+    }
+
+    #[test]
+    fn stable_pseudonyms() {
+        let mut pseudonyms = Pseudonyms::default();
+        let xuid = STEAM_ID_BASE + 1234;
+        let mut player = Some(pseudonyms.player(xuid));
+        assert_eq!(pseudonyms.name("someone", &mut player), "Player1");
+        assert_eq!(pseudonyms.xuid(xuid), STEAM_ID_BASE + 1);
+        assert_eq!(pseudonyms.steam_id("[U:1:1234]"), "[U:1:1]");
+        assert_eq!(pseudonyms.steam_id("STEAM_1:0:617"), "STEAM_1:1:0");
+        assert_eq!(
+            pseudonyms.steam_id("76561197960266962"),
+            "76561197960265729"
+        );
+        assert_eq!(pseudonyms.steam_id("BOT"), "BOT");
+        assert_eq!(pseudonyms.text("gg someone"), "gg Player1");
+        let mut player = None;
+        assert_eq!(pseudonyms.name("other", &mut player), "P2___");
+        assert_eq!(player, Some(2));
+    }
+}
@@ -0,0 +1,201 @@
+//! Batch mode: parses many demos on a thread pool.
+
+use anyhow::{anyhow, bail, Context};
+use csdemoparser::demoinfo::DemoInfo;
+use csdemoparser::error::ParseError;
+use csdemoparser::ParseOptions;
+use indicatif::{ParallelProgressIterator, ProgressBar, ProgressStyle};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::Serialize;
+use std::fs::File;
+use std::io::Write;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const DEMO_EXTENSIONS: &[&str] = &[".dem", ".dem.gz", ".dem.bz2", ".zip"];
+
+#[derive(Default)]
+pub(crate) struct Options {
+    /// Write one JSON line per demo to stdout instead of `<demo>.json` files.
+    pub ndjson: bool,
+    /// Number of threads, defaults to the number of logical CPUs.
+    pub threads: Option<usize>,
+    pub parse: ParseOptions,
+}
+
+#[derive(Default)]
+pub(crate) struct Stats {
+    pub parsed: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} parsed, {} skipped, {} failed",
+            self.parsed, self.skipped, self.failed
+        )
+    }
+}
+
+enum Outcome {
+    Parsed,
+    Skipped,
+}
+
+/// A line of the NDJSON output.
+#[derive(Serialize)]
+struct Line<'a> {
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    demoinfo: Option<&'a DemoInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ParseError>,
+}
+
+/// Parses the demos found in `inputs`, which can be demo files, directories searched recursively
+/// for demos, or glob patterns. Failures are reported on stderr without stopping the batch.
+pub(crate) fn run(inputs: &[String], options: &Options) -> anyhow::Result<Stats> {
+    if let Some(threads) = options.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()?;
+    }
+    let demos = find_demos(inputs)?;
+    let stats = Mutex::new(Stats::default());
+    let bar = ProgressBar::new(demos.len() as u64).with_style(ProgressStyle::with_template(
+        "{wide_bar} {pos:>}/{len} [{eta}]",
+    )?);
+    demos
+        .par_iter()
+        .progress_with(bar.clone())
+        .for_each(|demo| {
+            let result = process(demo, options);
+            let mut stats = stats.lock().unwrap();
+            match result {
+                Ok(Outcome::Parsed) => stats.parsed += 1,
+                Ok(Outcome::Skipped) => stats.skipped += 1,
+                Err(e) => {
+                    stats.failed += 1;
+                    bar.suspend(|| eprintln!("{}: {e:#}", demo.display()));
+                }
+            }
+        });
+    bar.finish_and_clear();
+    Ok(stats.into_inner().unwrap())
+}
+
+fn process(demo: &Path, options: &Options) -> anyhow::Result<Outcome> {
+    if options.ndjson {
+        let result = parse(demo, &options.parse);
+        let line = Line {
+            path: demo.to_string_lossy().to_string(),
+            demoinfo: result.as_ref().ok(),
+            error: result.as_ref().err().map(ParseError::from),
+        };
+        let mut line = serde_json::to_vec(&line)?;
+        line.push(b'\n');
+        std::io::stdout().lock().write_all(&line)?;
+        return result.map(|_| Outcome::Parsed);
+    }
+    let output = output_path(demo);
+    if is_up_to_date(demo, &output) {
+        return Ok(Outcome::Skipped);
+    }
+    let json = serde_json::to_vec(&parse(demo, &options.parse)?)?;
+    std::fs::write(&output, json).with_context(|| output.display().to_string())?;
+    Ok(Outcome::Parsed)
+}
+
+fn parse(demo: &Path, options: &ParseOptions) -> anyhow::Result<DemoInfo> {
+    let mut file = File::open(demo)?;
+    catch_unwind(AssertUnwindSafe(|| {
+        csdemoparser::parse_with_options(&mut file, options)
+    }))
+    .unwrap_or_else(|panic| {
+        let message = if let Some(s) = panic.downcast_ref::<&str>() {
+            s.to_string()
+        } else if let Some(s) = panic.downcast_ref::<String>() {
+            s.clone()
+        } else {
+            "unknown panic".to_string()
+        };
+        Err(anyhow!("parser panicked: {message}"))
+    })
+}
+
+fn find_demos(inputs: &[String]) -> anyhow::Result<Vec<PathBuf>> {
+    let mut demos = Vec::new();
+    for input in inputs {
+        let path = Path::new(input);
+        if path.is_dir() {
+            let pattern = glob::Pattern::escape(input) + "/**/*";
+            demos.extend(
+                glob::glob(&pattern)?
+                    .filter_map(Result::ok)
+                    .filter(|path| path.is_file() && is_demo(path)),
+            );
+        } else if path.exists() {
+            demos.push(path.to_path_buf());
+        } else {
+            let matches: Vec<_> = glob::glob(input)?.filter_map(Result::ok).collect();
+            if matches.is_empty() {
+                bail!("{input}: no such file");
+            }
+            demos.extend(matches);
+        }
+    }
+    Ok(demos)
+}
+
+fn is_demo(path: &Path) -> bool {
+    let name = path.to_string_lossy().to_ascii_lowercase();
+    DEMO_EXTENSIONS.iter().any(|ext| name.ends_with(ext))
+}
+
+fn output_path(demo: &Path) -> PathBuf {
+    let mut path = demo.as_os_str().to_owned();
+    path.push(".json");
+    path.into()
+}
+
+/// Returns true if `output` was modified after `demo`.
+fn is_up_to_date(demo: &Path, output: &Path) -> bool {
+    let modified = |path: &Path| path.metadata().and_then(|m| m.modified()).ok();
+    matches!((modified(demo), modified(output)), (Some(demo), Some(output)) if output > demo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("csdemoparser-batch-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub"))?;
+        let demo = dir.join("sub/broken.dem.bz2");
+        std::fs::write(&demo, b"HL2DEMO\0")?;
+        let an_hour_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        File::options()
+            .write(true)
+            .open(&demo)?
+            .set_modified(an_hour_ago)?;
+        std::fs::write(dir.join("notes.txt"), b"")?;
+
+        let demos = find_demos(&[dir.to_string_lossy().to_string()])?;
+        assert_eq!(demos, vec![demo.clone()]);
+        assert!(process(&demo, &Options::default()).is_err());
+        assert!(!output_path(&demo).exists());
+
+        std::fs::write(output_path(&demo), b"{}")?;
+        assert!(matches!(
+            process(&demo, &Options::default()),
+            Ok(Outcome::Skipped)
+        ));
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
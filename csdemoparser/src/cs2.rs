@@ -3,20 +3,28 @@ use crate::demoinfo::{
     RoundEnd, RoundStart,
 };
 
-use crate::game_event::GameEvent;
+use crate::game_event::{is_player_key, rename_reserved_keys, GameEvent};
 use crate::last_jump::LastJump;
+use crate::player_roles::PlayerRoles;
+use crate::player_timeline::PlayerTimeline;
 use crate::Tick;
-use crate::{DemoInfo, Slot, UserId};
-use cs2_demo::entity::{EntityList, TreeEntity};
-use cs2_demo::proto::demo::CDemoFileHeader;
+use crate::{is_parsed_event, DemoInfo, ParseOptions, Slot, UserId, HSBOX_EVENTS};
+use cs2_demo::entity::{Entity, EntityList, Property, TreeEntity};
+use cs2_demo::game_event::Descriptor;
+use cs2_demo::proto::demo::{CDemoFileHeader, CDemoFileInfo};
 use cs2_demo::proto::gameevents::CMsgSource1LegacyGameEvent;
 use cs2_demo::{GameEventDescriptors, UserInfo, Visitor};
-use std::collections::{hash_map, HashMap};
-use tracing::{instrument, trace};
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use tracing::{instrument, trace, warn};
 
-pub fn parse(read: &mut dyn std::io::Read) -> anyhow::Result<DemoInfo> {
-    let mut state = GameState::new();
-    cs2_demo::parse(read, &mut state, &TreeEntity::factory)?;
+pub fn parse(read: &mut dyn std::io::Read, options: &ParseOptions) -> anyhow::Result<DemoInfo> {
+    let mut state = GameState::new(options.clone());
+    let options = cs2_demo::ParseOptions {
+        lenient: options.lenient,
+        ..Default::default()
+    };
+    cs2_demo::parse_with_options(read, &mut state, &TreeEntity::factory, options)?;
     state.get_info()
 }
 
@@ -28,6 +36,11 @@ struct GameState {
     user_id2slot: HashMap<UserId, Slot>,
     /// Maps player slot to player info.
     players: HashMap<Slot, cs2_demo::PlayerInfo>,
+    player_timeline: PlayerTimeline,
+    player_roles: PlayerRoles,
+    /// Tick of the demo command being parsed.
+    tick: Tick,
+    options: ParseOptions,
 
     demoinfo: DemoInfo,
     // DemoInfo field
@@ -35,12 +48,24 @@ struct GameState {
 }
 
 impl Visitor for GameState {
+    fn visit_tick(&mut self, tick: Tick) -> anyhow::Result<ControlFlow<()>> {
+        self.tick = tick;
+        Ok(ControlFlow::Continue(()))
+    }
+
     fn visit_file_header(&mut self, header: CDemoFileHeader) -> anyhow::Result<()> {
         self.demoinfo.servername = header.server_name().to_string();
         self.demoinfo.map = header.map_name().to_string();
         Ok(())
     }
 
+    fn visit_file_info(&mut self, file_info: CDemoFileInfo) -> anyhow::Result<()> {
+        self.demoinfo.duration = Some(file_info.playback_time());
+        // CCSGameInfo has no match id, `game_info.dota.match_id` is only set in Dota 2 demos.
+        self.demoinfo.round_start_ticks = file_info.game_info.cs.round_start_ticks.clone();
+        Ok(())
+    }
+
     fn visit_server_info(
         &mut self,
         server_info: cs2_demo::proto::netmessages::CSVCMsg_ServerInfo,
@@ -60,10 +85,27 @@ impl Visitor for GameState {
         &mut self,
         event: CMsgSource1LegacyGameEvent,
         tick: Tick,
-        _entities: &EntityList,
+        entities: &EntityList,
     ) -> anyhow::Result<()> {
         if let Some(descriptor) = self.game_event_descriptors.get(&event.eventid()) {
-            let event = cs2_demo::game_event::de::from_proto(event, descriptor)?;
+            if !HSBOX_EVENTS.contains(&descriptor.name.as_str()) {
+                match self.extra_event(event, descriptor) {
+                    Ok(event) => self.add_event(tick, Event::Extra(event)),
+                    Err(e) => warn!("skipping game event at tick {tick}: {e}"),
+                }
+                return Ok(());
+            }
+            let round_event = matches!(descriptor.name.as_str(), "round_start" | "round_end");
+            let event = match cs2_demo::game_event::de::from_proto(event, descriptor) {
+                Ok(event) => event,
+                Err(e) => {
+                    warn!("skipping game event at tick {tick}: {e}");
+                    return Ok(());
+                }
+            };
+            if round_event {
+                self.update_player_roles(entities);
+            }
             self.handle_game_event(event, tick)?;
         }
         Ok(())
@@ -73,44 +115,66 @@ impl Visitor for GameState {
         &mut self,
         mut descriptors: GameEventDescriptors,
     ) -> anyhow::Result<()> {
-        let hsbox_events = std::collections::HashSet::from([
-            "bomb_defused",
-            "bomb_exploded",
-            "bot_takeover",
-            "game_restart",
-            "player_connect",
-            "player_death",
-            "player_disconnect",
-            "player_hurt",
-            "player_jump",
-            "player_spawn",
-            "round_end",
-            "round_officially_ended",
-            "round_start",
-            "score_changed",
-            "smokegrenade_detonate",
-            "smokegrenade_expired",
-        ]);
-        descriptors.retain(|_, ed| hsbox_events.contains(ed.name.as_str()));
+        descriptors.retain(|_, ed| is_parsed_event(&ed.name, &self.options));
         self.game_event_descriptors = descriptors;
         Ok(())
     }
+
+    fn visit_parse_error(
+        &mut self,
+        error: cs2_demo::Error,
+        last_good_tick: Option<Tick>,
+    ) -> anyhow::Result<()> {
+        warn!("{:#}", anyhow::Error::from(error));
+        self.demoinfo.set_truncated(last_good_tick);
+        Ok(())
+    }
 }
 
 impl GameState {
-    fn new() -> Self {
-        Default::default()
+    fn new(options: ParseOptions) -> Self {
+        Self {
+            options,
+            ..Default::default()
+        }
     }
 
     fn get_info(mut self) -> anyhow::Result<DemoInfo> {
+        self.demoinfo.players = self.player_timeline.into_players();
         self.demoinfo.events = self
             .events
             .iter()
             .map(serde_json::to_value)
             .collect::<std::result::Result<_, _>>()?;
+        self.player_roles.apply(&mut self.demoinfo, self.tick);
         Ok(self.demoinfo)
     }
 
+    /// Reads the team and coaching team of every player controller.
+    fn update_player_roles(&mut self, entities: &EntityList) {
+        let get_i32 = |entity: &dyn Entity, name| match entity.get_property_by_name(name) {
+            Some(Property::I32(v)) => Some(*v),
+            Some(Property::U32(v)) => Some(*v as i32),
+            _ => None,
+        };
+        for &Slot(slot) in self.players.keys() {
+            // Player controllers use the entity id following the userinfo slot.
+            let Some(controller) = entities.get(slot as usize + 1) else {
+                continue;
+            };
+            if controller.serializer().name() != "CCSPlayerController" {
+                continue;
+            }
+            if let Some(team) = get_i32(controller, "m_iCoachingTeam") {
+                self.player_roles
+                    .update_coaching_team(slot as i32, team, self.tick);
+            }
+            if let Some(team) = get_i32(controller, "m_iTeamNum") {
+                self.player_roles.update_team(slot as i32, team, self.tick);
+            }
+        }
+    }
+
     fn add_event(&mut self, tick: Tick, event: Event) {
         self.events.push(EventTick { tick, event })
     }
@@ -131,6 +195,28 @@ impl GameState {
         userid as u64
     }
 
+    /// Returns the keys of an event emitted as is, with player controllers replaced by xuids.
+    fn extra_event(
+        &self,
+        event: CMsgSource1LegacyGameEvent,
+        descriptor: &Descriptor,
+    ) -> cs2_demo::game_event::de::Result<serde_json::Map<String, serde_json::Value>> {
+        let mut attrs: serde_json::Map<_, _> =
+            cs2_demo::game_event::de::from_proto(event, descriptor)?;
+        rename_reserved_keys(&mut attrs);
+        for key in descriptor
+            .keys
+            .iter()
+            .filter(|k| is_player_key(&k.name, k.type_))
+        {
+            if let Some(userid) = attrs.get(&key.name).and_then(|v| v.as_i64()) {
+                attrs.insert(key.name.clone(), self.maybe_xuid(userid as i32).into());
+            }
+        }
+        attrs.insert("type".to_string(), descriptor.name.clone().into());
+        Ok(attrs)
+    }
+
     #[instrument(level = "trace", skip_all)]
     fn handle_game_event(&mut self, ge: GameEvent, tick: Tick) -> anyhow::Result<()> {
         trace!("#{tick} GameEvent {:?}", ge);
@@ -171,11 +257,9 @@ impl GameState {
                     }),
                 )
             }
-            GameEvent::PlayerConnect(_) => {
-                // TODO: processing
-            }
+            GameEvent::PlayerConnect(e) => self.player_timeline.connect(e.userid),
             GameEvent::PlayerDisconnect(e) => {
-                // TODO: processing
+                self.player_timeline.disconnect(tick, e.userid);
                 let userid = self.maybe_xuid(e.userid);
                 self.add_event(tick, Event::PlayerDisconnected(PlayerDisconnect { userid }))
             }
@@ -224,18 +308,83 @@ impl GameState {
         Ok(())
     }
 
+    /// Adds a new player or updates an existing one, for example after a name change.
     fn update_players(&mut self, ui: UserInfo) {
+        if ui.info.is_hltv {
+            return;
+        }
+        self.player_timeline.update(
+            self.tick,
+            ui.info.xuid,
+            &ui.info.name,
+            ui.index as i32,
+            ui.info.user_id,
+            ui.info.fakeplayer,
+        );
+        if ui.info.fakeplayer {
+            return;
+        }
         let slot = Slot(ui.index);
-        if let hash_map::Entry::Vacant(e) = self.players.entry(slot) {
-            self.demoinfo
-                .player_names
-                .insert(ui.info.xuid.to_string(), ui.info.name.clone());
-            self.demoinfo
-                .player_slots
-                .insert(ui.info.xuid.to_string(), ui.info.user_id);
-            self.user_id2slot
-                .insert(UserId(ui.info.user_id as u16), slot);
-            e.insert(ui.info);
+        self.demoinfo
+            .player_names
+            .insert(ui.info.xuid.to_string(), ui.info.name.clone());
+        self.demoinfo
+            .player_slots
+            .insert(ui.info.xuid.to_string(), ui.info.user_id);
+        self.user_id2slot
+            .insert(UserId(ui.info.user_id as u16), slot);
+        self.players.insert(slot, ui.info);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cs2_demo::game_event::DescriptorKey;
+    use cs2_demo::proto::gameevents::cmsg_source1legacy_game_event::Key_t;
+
+    #[test]
+    fn extra_event() -> anyhow::Result<()> {
+        let mut state = GameState::default();
+        state.update_players(UserInfo {
+            index: 0,
+            info: cs2_demo::PlayerInfo {
+                name: "player".to_string(),
+                xuid: 1007,
+                user_id: 7,
+                fakeplayer: false,
+                is_hltv: false,
+            },
+        });
+        let keys = [("userid", 9), ("attacker", 9), ("entityid", 4), ("tick", 3)];
+        let descriptor = Descriptor {
+            eventid: 1,
+            name: "player_blind".to_string(),
+            keys: keys
+                .iter()
+                .map(|&(name, type_)| DescriptorKey {
+                    name: name.to_string(),
+                    type_,
+                })
+                .collect(),
+        };
+        let mut event = CMsgSource1LegacyGameEvent::new();
+        event.set_eventid(1);
+        for (_, type_) in keys {
+            let mut key = Key_t::new();
+            key.set_type(type_);
+            key.set_val_short(7);
+            key.set_val_long(7);
+            event.keys.push(key);
         }
+        let event = EventTick {
+            tick: 100,
+            event: Event::Extra(state.extra_event(event, &descriptor)?),
+        };
+        assert_eq!(
+            serde_json::to_string(&event)?,
+            r#"{"tick":100,"attacker":1007,"entityid":7,"event_tick":7,"type":"player_blind","userid":1007}"#
+        );
+        Ok(())
     }
 }
@@ -1,25 +1,32 @@
 mod game_event;
 
-use csgo_demo::entity::{Entities, Entity, EntityId, PropValue, Scalar, ServerClasses, TrackProp};
+use crate::error::ResultExt;
+use crate::game_event::rename_reserved_keys;
 use crate::geometry::{through_smoke, Point};
 use crate::last_jump::LastJump;
+use crate::player_roles::PlayerRoles;
+use crate::player_timeline::PlayerTimeline;
 use crate::{account_id_to_xuid, guid_to_xuid, maybe_get_i32, maybe_get_u16, DemoInfo, TeamScore};
+use crate::{ParseOptions, Tick};
 use anyhow::bail;
+use csgo_demo::entity::{Entities, Entity, EntityId, PropValue, Scalar, ServerClasses, TrackProp};
 use csgo_demo::proto::netmessages::CSVCMsg_GameEvent;
-use csgo_demo::{Message, PacketContent};
 use csgo_demo::string_table::{parse_player_infos, PlayerInfo, StringTable, StringTables};
-use crate::Tick;
+use csgo_demo::{Message, PacketContent};
+use demo_context::Context;
 use serde_json::json;
 use std::cell::RefCell;
 use std::collections::{BTreeMap, HashMap};
 use std::io;
 use std::rc::Rc;
-use tracing::instrument;
+use tracing::{instrument, warn};
 
 const VEC_ORIGIN_XY: &str = "m_vecOrigin";
 const VEC_ORIGIN_Z: &str = "m_vecOrigin[2]";
 const VEC_VELOCITY_Z: &str = "m_vecVelocity[2]";
 const IS_SCOPED: &str = "m_bIsScoped";
+const TEAM_NUM: &str = "m_iTeamNum";
+const COACHING_TEAM: &str = "m_iCoachingTeam";
 
 const PLAYER_CLASS: &str = "CCSPlayer";
 
@@ -28,47 +35,95 @@ const GAME_RESTART: &str = "m_bGameRestart";
 
 const TEAM_CLASS: &str = "CCSTeam";
 
-pub fn parse(read: &mut dyn io::Read) -> anyhow::Result<DemoInfo> {
+/// Maximum number of commands queued before DataTables, which are sent at the start of valid
+/// demos.
+const MAX_PENDING_COMMANDS: usize = 1000;
+
+pub fn parse(read: &mut dyn io::Read, options: &ParseOptions) -> anyhow::Result<DemoInfo> {
     let mut parser = csgo_demo::DemoParser::try_new(read)?;
     let server_name = parser.header().server_name().to_string();
+    let duration = *parser.header().duration();
     let mut server_classes = None;
-    let mut packets = vec![];
-    while let Some((header, content)) = parser.parse_next_packet()? {
+    // HeadshotBoxParser needs the server classes, so everything before DataTables is queued
+    // and replayed in order once they are available.
+    let mut pending = vec![];
+    // In lenient mode, an error before the server classes are known stops the parsing once the
+    // queued commands are replayed.
+    let mut error = None;
+    loop {
+        let offset = parser.offset();
+        let (header, content) = match parser.parse_next_packet() {
+            Ok(Some(packet)) => packet,
+            Ok(None) => break,
+            Err(e) if options.lenient => {
+                error = Some(e.into());
+                break;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let tick = *header.tick();
+        let context = Context::Command {
+            offset,
+            command: Some(header.command_name()),
+            tick: Some(tick),
+        };
         match content {
             PacketContent::DataTables(dt) => {
-                server_classes = Some(ServerClasses::try_new(dt)?);
+                match ServerClasses::try_new(dt).in_context(context) {
+                    Ok(classes) => server_classes = Some(classes),
+                    Err(e) if options.lenient => error = Some(e),
+                    Err(e) => return Err(e),
+                }
                 break;
             }
-            PacketContent::Packet(pv) => packets.push((pv, *header.tick())),
-            PacketContent::StringTables(_) => todo!(),
-            _ => (),
+            content => pending.push((content, tick, context)),
         }
-        if packets.len() > 1000 {
-            bail!("no DataTables in the first 1000 packets")
+        if pending.len() > MAX_PENDING_COMMANDS {
+            break;
         }
     }
 
-    let Some(mut server_classes) = server_classes else {
-        bail!("no data tables before the first event")
+    let mut server_classes = match server_classes {
+        Some(server_classes) => server_classes,
+        // Without server classes only the entities are missing from the queued commands.
+        None if error.is_some() => ServerClasses {
+            bits: 0,
+            server_classes: vec![],
+        },
+        None => bail!("no DataTables found in demo"),
     };
     let mut hsbox = HeadshotBoxParser::new(server_name, &mut server_classes);
-    for (pv, tick) in packets {
-        for p in pv {
-            hsbox.handle_packet(p, tick)?;
-        }
+    hsbox.options = options.clone();
+    for (content, tick, context) in pending {
+        hsbox.handle_command(content, tick, context)?;
     }
-    while let Some((header, content)) = parser.parse_next_packet()? {
-        match content {
-            PacketContent::Packet(pv) => {
-                for p in pv {
-                    hsbox.handle_packet(p, *header.tick())?;
+    if let Some(error) = error {
+        hsbox.skip(error);
+    } else {
+        loop {
+            let offset = parser.offset();
+            let (header, content) = match parser.parse_next_packet() {
+                Ok(Some(packet)) => packet,
+                Ok(None) => break,
+                // The demo is truncated, there is no next command to skip to.
+                Err(e) if hsbox.options.lenient => {
+                    hsbox.skip(e.into());
+                    break;
                 }
-            }
-            PacketContent::StringTables(st) => hsbox.handle_string_tables(st)?,
-            _ => (),
+                Err(e) => return Err(e.into()),
+            };
+            let tick = *header.tick();
+            let context = Context::Command {
+                offset,
+                command: Some(header.command_name()),
+                tick: Some(tick),
+            };
+            hsbox.handle_command(content, tick, context)?;
         }
     }
-    hsbox.get_info()
+    let mut demoinfo = hsbox.get_info()?;
+    demoinfo.duration = Some(duration);
+    Ok(demoinfo)
 }
 
 type GameEvent = serde_json::Map<String, serde_json::Value>;
@@ -77,19 +132,25 @@ struct HeadshotBoxParser<'a> {
     game_event_descriptors: HashMap<i32, self::game_event::Descriptor>,
     string_tables: StringTables,
     players: HashMap<i32, PlayerInfo>,
+    player_timeline: PlayerTimeline,
     last_jump: LastJump<i32>,
     tick_interval: f32,
     entities: Entities<'a>,
     smokes: BTreeMap<u16, Point>,
     bot_takeover: HashMap<u64, i32>,
     scoped_since: Rc<RefCell<HashMap<u16, Tick>>>,
+    player_roles: Rc<RefCell<PlayerRoles>>,
     score: Rc<RefCell<TeamScore>>,
     demoinfo: Rc<RefCell<DemoInfo>>,
+    options: ParseOptions,
+    /// Tick of the last demo command handled successfully.
+    last_good_tick: Option<Tick>,
 }
 
 impl<'a> HeadshotBoxParser<'a> {
     fn new(server_name: String, server_classes: &'a mut ServerClasses) -> Self {
         let scoped_since = Rc::new(RefCell::new(HashMap::new()));
+        let player_roles: Rc<RefCell<PlayerRoles>> = Default::default();
         let score: Rc<RefCell<TeamScore>> = Rc::new(RefCell::new(Default::default()));
         let demoinfo = Rc::new(RefCell::new(DemoInfo {
             servername: server_name,
@@ -134,6 +195,32 @@ impl<'a> HeadshotBoxParser<'a> {
                             }
                         }))
                     }
+                    // Player entities use the entity id following the userinfo slot, see
+                    // get_player_entity.
+                    (PLAYER_CLASS, TEAM_NUM) => {
+                        let player_roles = Rc::clone(&player_roles);
+                        TrackProp::Changes(Rc::new(move |entity, tick, value| {
+                            if let PropValue::Scalar(Scalar::I32(team)) = value {
+                                player_roles.borrow_mut().update_team(
+                                    entity.id as i32 - 1,
+                                    *team,
+                                    tick,
+                                );
+                            }
+                        }))
+                    }
+                    (PLAYER_CLASS, COACHING_TEAM) => {
+                        let player_roles = Rc::clone(&player_roles);
+                        TrackProp::Changes(Rc::new(move |entity, tick, value| {
+                            if let PropValue::Scalar(Scalar::I32(team)) = value {
+                                player_roles.borrow_mut().update_coaching_team(
+                                    entity.id as i32 - 1,
+                                    *team,
+                                    tick,
+                                );
+                            }
+                        }))
+                    }
                     (PLAYER_CLASS, VEC_ORIGIN_XY | VEC_ORIGIN_Z | VEC_VELOCITY_Z) => {
                         TrackProp::Value
                     }
@@ -156,23 +243,39 @@ impl<'a> HeadshotBoxParser<'a> {
             game_event_descriptors: Default::default(),
             string_tables: StringTables::new(),
             players: Default::default(),
+            player_timeline: Default::default(),
             last_jump: Default::default(),
             tick_interval: 0.0,
             entities: Entities::new(server_classes),
             smokes: Default::default(),
             bot_takeover: Default::default(),
             scoped_since,
+            player_roles,
             score,
             demoinfo,
+            options: Default::default(),
+            last_good_tick: None,
         }
     }
 
     fn update_players(
         players: &mut HashMap<i32, PlayerInfo>,
+        player_timeline: &mut PlayerTimeline,
         demoinfo: &Rc<RefCell<DemoInfo>>,
         player_info: PlayerInfo,
+        tick: Tick,
     ) {
         let mut demoinfo = demoinfo.borrow_mut();
+        if !player_info.is_hltv {
+            player_timeline.update(
+                tick,
+                player_info.xuid,
+                &player_info.name,
+                player_info.entity_id,
+                player_info.user_id,
+                player_info.fakeplayer,
+            );
+        }
         if !player_info.fakeplayer && !player_info.is_hltv {
             demoinfo
                 .player_slots
@@ -185,11 +288,54 @@ impl<'a> HeadshotBoxParser<'a> {
         players.insert(player_info.user_id, player_info);
     }
 
-    fn handle_string_tables(&mut self, st: Vec<StringTable>) -> anyhow::Result<()> {
+    fn handle_command(
+        &mut self,
+        content: PacketContent,
+        tick: Tick,
+        context: Context,
+    ) -> anyhow::Result<()> {
+        match self.handle_content(content, tick).in_context(context) {
+            Ok(()) => self.last_good_tick = Some(tick),
+            Err(e) if self.options.lenient => self.skip(e),
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    /// Records an error that does not stop the parsing in lenient mode.
+    fn skip(&mut self, error: anyhow::Error) {
+        warn!("{error:#}");
+        self.demoinfo
+            .borrow_mut()
+            .set_truncated(self.last_good_tick);
+    }
+
+    fn handle_content(&mut self, content: PacketContent, tick: Tick) -> anyhow::Result<()> {
+        match content {
+            PacketContent::Packet(pv) => {
+                for p in pv {
+                    let name = p.name();
+                    self.handle_packet(p, tick)
+                        .in_context(Context::Message(name.to_string()))?;
+                }
+            }
+            PacketContent::StringTables(st) => self.handle_string_tables(st, tick)?,
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn handle_string_tables(&mut self, st: Vec<StringTable>, tick: Tick) -> anyhow::Result<()> {
         // demoinfogo clears the players but I don't think this is correct
         self.players.clear();
         for player_info in parse_player_infos(st)? {
-            Self::update_players(&mut self.players, &self.demoinfo, player_info);
+            Self::update_players(
+                &mut self.players,
+                &mut self.player_timeline,
+                &self.demoinfo,
+                player_info,
+                tick,
+            );
         }
         Ok(())
     }
@@ -204,22 +350,38 @@ impl<'a> HeadshotBoxParser<'a> {
             Message::CreateStringTable(table) => {
                 let mut updates = self.string_tables.create_string_table(&table);
                 while let Some(player_info) = updates.next_player_info()? {
-                    Self::update_players(&mut self.players, &self.demoinfo, player_info);
+                    Self::update_players(
+                        &mut self.players,
+                        &mut self.player_timeline,
+                        &self.demoinfo,
+                        player_info,
+                        tick,
+                    );
                 }
             }
             Message::UpdateStringTable(table) => {
                 let mut updates = self.string_tables.update_string_table(&table)?;
                 while let Some(player_info) = updates.next_player_info()? {
-                    Self::update_players(&mut self.players, &self.demoinfo, player_info);
+                    Self::update_players(
+                        &mut self.players,
+                        &mut self.player_timeline,
+                        &self.demoinfo,
+                        player_info,
+                        tick,
+                    );
                 }
             }
             Message::GameEventList(gel) => {
-                self.game_event_descriptors = self::game_event::parse_game_event_list(gel)
+                self.game_event_descriptors =
+                    self::game_event::parse_game_event_list(gel, &self.options)
             }
             Message::GameEvent(event) => {
                 if let Some(descriptor) = self.game_event_descriptors.get(&event.eventid()) {
-                    let attrs = self.event_map(event, descriptor, tick)?;
-                    self.handle_game_event(attrs, tick)?;
+                    let player_keys = descriptor.player_keys();
+                    match self.event_map(event, descriptor, tick) {
+                        Ok(attrs) => self.handle_game_event(attrs, &player_keys, tick)?,
+                        Err(e) => warn!("skipping game event at tick {tick}: {e}"),
+                    }
                 }
             }
             Message::ServerRankUpdate(ranks) => {
@@ -260,7 +422,13 @@ impl<'a> HeadshotBoxParser<'a> {
         None
     }
 
-    fn handle_game_event(&mut self, mut attrs: GameEvent, tick: Tick) -> anyhow::Result<()> {
+    /// Handles a game event whose keys named in `player_keys` hold user ids.
+    fn handle_game_event(
+        &mut self,
+        mut attrs: GameEvent,
+        player_keys: &[String],
+        tick: Tick,
+    ) -> anyhow::Result<()> {
         let emit = |attrs| {
             self.demoinfo
                 .borrow_mut()
@@ -290,7 +458,7 @@ impl<'a> HeadshotBoxParser<'a> {
             }
             "player_death" => {
                 if let Some(attacker_user_id) = maybe_get_i32(attrs.get("attacker")) {
-                    if self.players.get(&attacker_user_id).is_some() {
+                    if self.players.contains_key(&attacker_user_id) {
                         if let Some(jump) = self.last_jump.ticks_since_last_jump(
                             attacker_user_id,
                             tick,
@@ -332,7 +500,14 @@ impl<'a> HeadshotBoxParser<'a> {
             }
             "player_connect" => {
                 if let Some(player_info) = self.handle_player_connect(attrs) {
-                    Self::update_players(&mut self.players, &self.demoinfo, player_info);
+                    self.player_timeline.connect(player_info.user_id);
+                    Self::update_players(
+                        &mut self.players,
+                        &mut self.player_timeline,
+                        &self.demoinfo,
+                        player_info,
+                        tick,
+                    );
                 }
             }
             "player_disconnect" => {
@@ -342,12 +517,14 @@ impl<'a> HeadshotBoxParser<'a> {
                 attrs.remove("networkid");
                 if let Some(user_id) = user_id {
                     self.players.remove(&user_id);
+                    self.player_timeline.disconnect(tick, user_id);
                 }
                 emit(attrs);
             }
             _ => {
-                self.replace_user_id_with_xuid("userid", &mut attrs);
-                self.replace_user_id_with_xuid("attacker", &mut attrs);
+                for key in player_keys {
+                    self.replace_user_id_with_xuid(key, &mut attrs);
+                }
                 emit(attrs);
             }
         }
@@ -378,6 +555,9 @@ impl<'a> HeadshotBoxParser<'a> {
 
     fn get_info(self) -> anyhow::Result<DemoInfo> {
         let mut demoinfo = self.demoinfo.borrow_mut();
+        demoinfo.players = self.player_timeline.into_players();
+        let end_tick = self.last_good_tick.unwrap_or_default();
+        self.player_roles.borrow().apply(&mut demoinfo, end_tick);
         demoinfo.gotv_bots = self
             .players
             .values()
@@ -397,23 +577,31 @@ impl<'a> HeadshotBoxParser<'a> {
     ) -> anyhow::Result<GameEvent> {
         let mut attrs = serde_json::Map::new();
         for (i, descriptor_key) in descriptor.keys.iter().enumerate() {
-            let event_key = &event.keys[i];
             let key = descriptor_key.name.clone();
+            let Some(event_key) = event.keys.get(i) else {
+                bail!("event {}.{key} is missing", descriptor.name);
+            };
             if event_key.type_() != descriptor_key.type_ {
-                bail!("event key type does not match descriptor type");
+                bail!(
+                    "event {}.{key} expected type {} but got type {}",
+                    descriptor.name,
+                    descriptor_key.type_,
+                    event_key.type_()
+                );
             }
             let val = match descriptor_key.type_ {
                 1 => json!(event_key.val_string()),
                 2 => json!(event_key.val_float()),
-                3 => json!(event_key.val_long()),
-                4 => json!(event_key.val_short()),
+                3 | 8 => json!(event_key.val_long()),
+                4 | 9 => json!(event_key.val_short()),
                 5 => json!(event_key.val_byte()),
                 6 => json!(event_key.val_bool()),
                 7 => json!(event_key.val_uint64()),
-                e => bail!("unknown event key type {}", e),
+                e => bail!("event {}.{key} has unknown type {e}", descriptor.name),
             };
             attrs.insert(key, val);
         }
+        rename_reserved_keys(&mut attrs);
         attrs.insert("type".into(), json!(descriptor.name));
         attrs.insert("tick".into(), json!(tick));
         Ok(attrs)
@@ -425,7 +613,7 @@ impl<'a> HeadshotBoxParser<'a> {
         Some(player_info)
     }
 
-    fn get_player_entity(&self, key: &str, attrs: &GameEvent) -> Option<&Entity> {
+    fn get_player_entity(&self, key: &str, attrs: &GameEvent) -> Option<&Entity<'_>> {
         let user_id = maybe_get_i32(attrs.get(key))?;
         let player_info = self.players.get(&user_id)?;
         let entity_id: EntityId = player_info.entity_id as u16 + 1;
@@ -483,9 +671,12 @@ impl<'a> HeadshotBoxParser<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::game_event::is_player_key;
+    use crate::tests::{make_csgo_demo, make_data_tables};
     use assert_json_diff::assert_json_eq;
+    use csgo_demo::proto::netmessages::SVC_Messages;
 
-    fn make_parser(server_classes: &mut ServerClasses) -> HeadshotBoxParser {
+    fn make_parser(server_classes: &mut ServerClasses) -> HeadshotBoxParser<'_> {
         let mut parser = HeadshotBoxParser::new("".to_owned(), server_classes);
         parser.tick_interval = 1f32 / 64f32;
         parser.players.insert(
@@ -521,7 +712,290 @@ mod tests {
 
     fn handle_event(parser: &mut HeadshotBoxParser, event: serde_json::Value, tick: Tick) {
         let attrs = make_game_event(event);
-        parser.handle_game_event(attrs, tick).unwrap()
+        // User ids are shorts in CS:GO.
+        let player_keys: Vec<_> = attrs
+            .keys()
+            .filter(|key| is_player_key(key, 4))
+            .cloned()
+            .collect();
+        parser.handle_game_event(attrs, &player_keys, tick).unwrap()
+    }
+
+    #[test]
+    fn string_tables_before_data_tables() -> anyhow::Result<()> {
+        use protobuf::Message;
+        const STRING_TABLES: u8 = 9;
+        const DATA_TABLES: u8 = 6;
+        const STOP: u8 = 7;
+        const SVC_SEND_TABLE: u8 = 9;
+
+        // No string tables.
+        let string_tables = [1, 0, 0, 0, 0];
+        let mut send_table = csgo_demo::proto::netmessages::CSVCMsg_SendTable::new();
+        send_table.set_is_end(true);
+        let send_table = send_table.write_length_delimited_to_bytes()?;
+        let mut data_tables = vec![SVC_SEND_TABLE];
+        data_tables.extend(send_table);
+        // No server classes.
+        data_tables.extend([0, 0]);
+        let mut data_tables_command = (data_tables.len() as u32).to_le_bytes().to_vec();
+        data_tables_command.extend(data_tables);
+
+        let demo = make_csgo_demo(&[
+            (STRING_TABLES, 0, &string_tables),
+            (DATA_TABLES, 0, &data_tables_command),
+            (STOP, 0, &[]),
+        ]);
+        let demoinfo = crate::parse_bytes(&demo)?;
+        assert_eq!(demoinfo.servername, "server");
+        Ok(())
+    }
+
+    #[test]
+    fn no_data_tables() {
+        const SYNC_TICK: u8 = 3;
+        const DATA_TABLES: u8 = 6;
+        // DataTables come too late.
+        let data_tables = make_data_tables(&[]);
+        let mut commands = vec![(SYNC_TICK, 0, &[][..]); MAX_PENDING_COMMANDS + 1];
+        commands.push((DATA_TABLES, 0, &data_tables));
+        let demo = make_csgo_demo(&commands);
+        let Err(error) = crate::parse_bytes(&demo) else {
+            panic!("parsed a demo without DataTables");
+        };
+        assert_eq!(error.to_string(), "no DataTables found in demo");
+    }
+
+    /// Returns the data of a CS:GO Packet command with the given messages.
+    fn make_packet(messages: &[(SVC_Messages, &dyn protobuf::MessageDyn)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (message_type, message) in messages {
+            data.push(*message_type as u8);
+            data.extend(message.write_length_delimited_to_bytes_dyn().unwrap());
+        }
+        // Command info and sequence numbers.
+        let mut packet = vec![0; 152 + 4 + 4];
+        packet.extend((data.len() as u32).to_le_bytes());
+        packet.extend(data);
+        packet
+    }
+
+    #[test]
+    fn lenient() -> anyhow::Result<()> {
+        use csgo_demo::proto::netmessages::csvcmsg_game_event_list::Descriptor_t;
+        use csgo_demo::proto::netmessages::{CSVCMsg_GameEventList, CSVCMsg_UpdateStringTable};
+        const PACKET: u8 = 2;
+        const DATA_TABLES: u8 = 6;
+
+        let mut descriptor = Descriptor_t::new();
+        descriptor.set_eventid(1);
+        descriptor.set_name("round_start".to_string());
+        let mut event_list = CSVCMsg_GameEventList::new();
+        event_list.descriptors.push(descriptor);
+        let mut round_start = CSVCMsg_GameEvent::new();
+        round_start.set_eventid(1);
+        // There is no string table to update.
+        let mut bad_update = CSVCMsg_UpdateStringTable::new();
+        bad_update.set_table_id(5);
+
+        let data_tables = make_data_tables(&[]);
+        let first = make_packet(&[
+            (SVC_Messages::svc_GameEventList, &event_list),
+            (SVC_Messages::svc_GameEvent, &round_start),
+        ]);
+        let corrupt = make_packet(&[
+            (SVC_Messages::svc_UpdateStringTable, &bad_update),
+            (SVC_Messages::svc_GameEvent, &round_start),
+        ]);
+        let last = make_packet(&[(SVC_Messages::svc_GameEvent, &round_start)]);
+        let demo = make_csgo_demo(&[
+            (DATA_TABLES, 0, &data_tables),
+            (PACKET, 1, &first),
+            (PACKET, 2, &corrupt),
+            (PACKET, 3, &last),
+            // Truncated command.
+            (PACKET, 4, &last[..10]),
+        ]);
+        assert!(crate::parse_bytes(&demo).is_err());
+
+        let options = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let demoinfo = parse(&mut demo.as_slice(), &options)?;
+        assert!(demoinfo.truncated);
+        assert_eq!(demoinfo.last_good_tick, Some(1));
+        let ticks: Vec<_> = demoinfo.events.iter().map(|e| e["tick"].clone()).collect();
+        assert_eq!(ticks, vec![json!(1), json!(3)]);
+
+        // The commands before a truncated DataTables are kept too.
+        let demo = make_csgo_demo(&[(PACKET, 1, &first), (DATA_TABLES, 2, &data_tables[..10])]);
+        let demoinfo = parse(&mut demo.as_slice(), &options)?;
+        assert!(demoinfo.truncated);
+        assert_eq!(demoinfo.last_good_tick, Some(1));
+        assert_eq!(demoinfo.events.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn coach_and_spectator() -> anyhow::Result<()> {
+        use csgo_demo::proto::netmessages::CSVCMsg_PacketEntities;
+        const DATA_TABLES: u8 = 6;
+
+        let data_tables = make_data_tables(&[TEAM_NUM, COACHING_TEAM]);
+        let demo = make_csgo_demo(&[(DATA_TABLES, 0, &data_tables)]);
+        let mut read = demo.as_slice();
+        let mut demo_parser = csgo_demo::DemoParser::try_new(&mut read)?;
+        let Some((_, PacketContent::DataTables(dt))) = demo_parser.parse_next_packet()? else {
+            panic!("no DataTables");
+        };
+        let mut server_classes = ServerClasses::try_new(dt)?;
+        let props: Vec<_> = server_classes.server_classes[0]
+            .props
+            .iter()
+            .map(|p| p.name.clone())
+            .collect();
+
+        let mut parser = HeadshotBoxParser::new("".to_owned(), &mut server_classes);
+        for slot in 0..3 {
+            let player_info = PlayerInfo {
+                xuid: 1000 + slot as u64,
+                name: format!("player{slot}"),
+                user_id: 10 + slot,
+                entity_id: slot,
+                ..Default::default()
+            };
+            HeadshotBoxParser::update_players(
+                &mut parser.players,
+                &mut parser.player_timeline,
+                &parser.demoinfo,
+                player_info,
+                1,
+            );
+        }
+
+        // Creates the entities of the player slots with their team and coaching team, written
+        // as bits in the order they are read.
+        let mut bits = Vec::new();
+        let mut write = |value: u32, count: u32| bits.extend((0..count).map(|i| value >> i & 1));
+        // Spectator, coach and player.
+        let teams = [(1, 0), (3, 3), (2, 0)];
+        for (i, (team, coaching_team)) in teams.into_iter().enumerate() {
+            // Entity id delta (the first entity is 1), not removed, new, class id and serial
+            // number.
+            write(if i == 0 { 1 } else { 0 }, 6);
+            write(0b10, 2);
+            write(0, 1);
+            write(0, 10);
+            // New way field indices 0 and 1, followed by the end marker.
+            write(0b111, 3);
+            write(0b00, 2);
+            write(0x3fff, 14);
+            for name in &props {
+                write(
+                    if name == TEAM_NUM {
+                        team
+                    } else {
+                        coaching_team
+                    } as u32,
+                    8,
+                );
+            }
+        }
+        let mut entity_data = vec![0u8; bits.len().div_ceil(8)];
+        for (i, bit) in bits.iter().enumerate() {
+            entity_data[i / 8] |= (*bit as u8) << (i % 8);
+        }
+        let mut packet_entities = CSVCMsg_PacketEntities::new();
+        packet_entities.set_updated_entries(3);
+        packet_entities.set_entity_data(entity_data);
+        parser.handle_packet(Message::PacketEntities(packet_entities), 2)?;
+
+        let demoinfo = parser.get_info()?;
+        assert_eq!(
+            demoinfo.player_names.keys().collect::<Vec<_>>(),
+            vec!["1002"]
+        );
+        assert_eq!(demoinfo.spectators.keys().collect::<Vec<_>>(), vec!["1000"]);
+        assert_eq!(demoinfo.coaches.keys().collect::<Vec<_>>(), vec!["1001"]);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_game_event() -> anyhow::Result<()> {
+        use csgo_demo::proto::netmessages::csvcmsg_game_event::Key_t;
+        let mut server_classes = make_server_classes();
+        let mut parser = make_parser(&mut server_classes);
+        let descriptor = game_event::Descriptor {
+            name: "player_hurt".to_string(),
+            keys: vec![game_event::DescriptorKey {
+                name: "attacker".to_string(),
+                type_: 9,
+            }],
+        };
+        parser.game_event_descriptors.insert(1, descriptor);
+        let event = |key_types: &[i32]| {
+            let mut event = CSVCMsg_GameEvent::new();
+            event.set_eventid(1);
+            for &type_ in key_types {
+                let mut key = Key_t::new();
+                key.set_type(type_);
+                key.set_val_short(7);
+                event.keys.push(key);
+            }
+            Message::GameEvent(event)
+        };
+        // Events not matching their descriptor are skipped.
+        parser.handle_packet(event(&[4]), 1)?;
+        parser.handle_packet(event(&[]), 1)?;
+        assert!(parser.demoinfo.borrow().events.is_empty());
+        parser.handle_packet(event(&[9]), 2)?;
+        assert_json_eq!(
+            parser.demoinfo.borrow().events,
+            json!([{"type": "player_hurt", "tick": 2, "attacker": 1007}])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn extra_game_event() -> anyhow::Result<()> {
+        use csgo_demo::proto::netmessages::csvcmsg_game_event::Key_t;
+        let mut server_classes = make_server_classes();
+        let mut parser = make_parser(&mut server_classes);
+        let keys = [("userid", 4), ("assister", 4), ("entityid", 4), ("tick", 3)];
+        let descriptor = game_event::Descriptor {
+            name: "player_blind".to_string(),
+            keys: keys
+                .iter()
+                .map(|&(name, type_)| game_event::DescriptorKey {
+                    name: name.to_string(),
+                    type_,
+                })
+                .collect(),
+        };
+        parser.game_event_descriptors.insert(1, descriptor);
+        let mut event = CSVCMsg_GameEvent::new();
+        event.set_eventid(1);
+        for (_, type_) in keys {
+            let mut key = Key_t::new();
+            key.set_type(type_);
+            key.set_val_short(7);
+            key.set_val_long(100);
+            event.keys.push(key);
+        }
+        parser.handle_packet(Message::GameEvent(event), 2)?;
+        assert_json_eq!(
+            parser.demoinfo.borrow().events,
+            json!([{
+                "type": "player_blind",
+                "tick": 2,
+                "userid": 1007,
+                "assister": 1007,
+                "entityid": 7,
+                "event_tick": 100,
+            }])
+        );
+        Ok(())
     }
 
     #[test]
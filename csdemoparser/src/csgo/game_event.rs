@@ -1,5 +1,7 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 
+use crate::game_event::is_player_key;
+use crate::{is_parsed_event, ParseOptions};
 use csgo_demo::proto::netmessages::CSVCMsg_GameEventList;
 
 pub(super) struct DescriptorKey {
@@ -12,6 +14,17 @@ pub(super) struct Descriptor {
     pub keys: Vec<DescriptorKey>,
 }
 
+impl Descriptor {
+    /// Returns the names of the keys holding user ids.
+    pub fn player_keys(&self) -> Vec<String> {
+        self.keys
+            .iter()
+            .filter(|k| is_player_key(&k.name, k.type_))
+            .map(|k| k.name.clone())
+            .collect()
+    }
+}
+
 impl std::fmt::Display for Descriptor {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "struct {} {{", self.name)?;
@@ -33,28 +46,13 @@ impl std::fmt::Display for Descriptor {
     }
 }
 
-pub(super) fn parse_game_event_list(gel: CSVCMsg_GameEventList) -> HashMap<i32, Descriptor> {
-    let hsbox_events = HashSet::from([
-        "bomb_defused",
-        "bomb_exploded",
-        "bot_takeover",
-        "game_restart",
-        "player_connect",
-        "player_death",
-        "player_disconnect",
-        "player_hurt",
-        "player_jump",
-        "player_spawn",
-        "round_end",
-        "round_officially_ended",
-        "round_start",
-        "score_changed",
-        "smokegrenade_detonate",
-        "smokegrenade_expired",
-    ]);
+pub(super) fn parse_game_event_list(
+    gel: CSVCMsg_GameEventList,
+    options: &ParseOptions,
+) -> HashMap<i32, Descriptor> {
     gel.descriptors
         .into_iter()
-        .filter(|d| hsbox_events.contains(d.name()))
+        .filter(|d| is_parsed_event(d.name(), options))
         .map(|d| {
             (
                 d.eventid(),
@@ -0,0 +1,137 @@
+use anyhow::bail;
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use std::io::{Cursor, Read, Seek};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const BZIP2_MAGIC: &[u8] = b"BZh";
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+/// Calls `f` with the decompressed content of `read`, detecting the compression from its first
+/// bytes. Uncompressed input is passed through. For zip archives, the first `.dem` file is used;
+/// it must not be stored with a data descriptor, as the archive is read as a stream.
+pub(crate) fn with_decompressed<T>(
+    read: &mut dyn Read,
+    f: impl FnOnce(&mut dyn Read) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let mut magic = Vec::with_capacity(ZIP_MAGIC.len());
+    (&mut *read)
+        .take(ZIP_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+    let mut read = magic.as_slice().chain(read);
+    if magic.starts_with(GZIP_MAGIC) {
+        f(&mut MultiGzDecoder::new(read))
+    } else if magic.starts_with(BZIP2_MAGIC) {
+        f(&mut MultiBzDecoder::new(read))
+    } else if magic == ZIP_MAGIC {
+        while let Some(mut file) = zip::read::read_zipfile_from_stream(&mut read)? {
+            if file.is_file() && file.name().to_ascii_lowercase().ends_with(".dem") {
+                return f(&mut file);
+            }
+        }
+        bail!("no .dem file in zip archive")
+    } else {
+        f(&mut read)
+    }
+}
+
+/// Input that can also seek, needed to read the file info at the end of CS2 demos or to index
+/// them.
+pub(crate) trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// Same as [`with_decompressed`] for callers that need to seek. Compressed input is decompressed
+/// in memory as decompressors cannot seek, uncompressed input is passed through.
+pub(crate) fn with_seekable_decompressed<T>(
+    read: &mut dyn ReadSeek,
+    f: impl FnOnce(&mut dyn ReadSeek) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let mut magic = Vec::with_capacity(ZIP_MAGIC.len());
+    (&mut *read)
+        .take(ZIP_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+    read.rewind()?;
+    if magic.starts_with(GZIP_MAGIC) || magic.starts_with(BZIP2_MAGIC) || magic == ZIP_MAGIC {
+        let data = with_decompressed(read, |read| {
+            let mut data = Vec::new();
+            read.read_to_end(&mut data)?;
+            Ok(data)
+        })?;
+        f(&mut Cursor::new(data))
+    } else {
+        f(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const DEMO: &[u8] = b"HL2DEMO\0 demo content";
+
+    fn read_all(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        with_decompressed(&mut &data[..], |read| {
+            let mut content = Vec::new();
+            read.read_to_end(&mut content)?;
+            Ok(content)
+        })
+    }
+
+    #[test]
+    fn uncompressed() -> anyhow::Result<()> {
+        assert_eq!(read_all(DEMO)?, DEMO);
+        assert_eq!(read_all(b"PK")?, b"PK");
+        assert_eq!(read_all(b"")?, b"");
+        Ok(())
+    }
+
+    #[test]
+    fn gzip_and_bzip2() -> anyhow::Result<()> {
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(DEMO)?;
+        assert_eq!(read_all(&gz.finish()?)?, DEMO);
+
+        let mut bz2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bz2.write_all(DEMO)?;
+        assert_eq!(read_all(&bz2.finish()?)?, DEMO);
+        Ok(())
+    }
+
+    #[test]
+    fn zip_archive() -> anyhow::Result<()> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default();
+        zip.start_file("readme.txt", options)?;
+        zip.write_all(b"not a demo")?;
+        zip.start_file("match/de_dust2.DEM", options)?;
+        zip.write_all(DEMO)?;
+        let zip = zip.finish()?.into_inner();
+        assert_eq!(read_all(&zip)?, DEMO);
+
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("readme.txt", zip::write::SimpleFileOptions::default())?;
+        let zip = zip.finish()?.into_inner();
+        assert!(read_all(&zip).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn seekable() -> anyhow::Result<()> {
+        let read_end = |data: Vec<u8>| {
+            with_seekable_decompressed(&mut Cursor::new(data), |read| {
+                read.seek(std::io::SeekFrom::End(-7))?;
+                let mut content = Vec::new();
+                read.read_to_end(&mut content)?;
+                Ok(content)
+            })
+        };
+        assert_eq!(read_end(DEMO.to_vec())?, b"content");
+
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(DEMO)?;
+        assert_eq!(read_end(gz.finish()?)?, b"content");
+        Ok(())
+    }
+}
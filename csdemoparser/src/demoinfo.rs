@@ -1,35 +1,136 @@
 use crate::Tick;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 /// The output of csdemoparser.
 #[derive(Serialize, Deserialize, Clone)]
 pub struct DemoInfo {
+    /// Maps coach xuid to name, for players who coached longer than they played. Coaches are not
+    /// included in `player_names` and `player_slots`.
+    #[serde(default)]
+    pub coaches: HashMap<String, String>,
     // TODO: use Vec<EventTick> instead.
     pub events: Vec<serde_json::Value>,
+    /// Duration of the demo, in seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f32>,
     pub gotv_bots: Vec<String>,
     pub map: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mm_rank_update: Option<serde_json::Value>,
     pub player_names: HashMap<String, String>,
     pub player_slots: HashMap<String, i32>,
+    /// Tick at which each round started, as recorded at the end of CS2 demos. This is all the
+    /// CS-specific game info of CS2 demos: unlike Dota 2 demos they record no match id.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub round_start_ticks: Vec<Tick>,
+    /// Identity timeline of every player, in order of appearance.
+    #[serde(default)]
+    pub players: Vec<PlayerIdentity>,
     pub servername: String,
+    /// Maps spectator xuid to name, for players who never joined a team. They are not included
+    /// in `player_names` and `player_slots`.
+    #[serde(default)]
+    pub spectators: HashMap<String, String>,
     pub tickrate: f32,
+    /// Set in lenient mode when part of the demo could not be parsed, see
+    /// [`ParseOptions::lenient`](crate::ParseOptions::lenient).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    /// When `truncated`, tick of the last demo command parsed before the first error.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_good_tick: Option<Tick>,
 }
 
 impl Default for DemoInfo {
     fn default() -> Self {
         DemoInfo {
+            coaches: Default::default(),
             events: Vec::new(),
+            duration: None,
             map: String::new(),
             gotv_bots: Vec::new(),
             mm_rank_update: None,
             player_names: Default::default(),
             player_slots: Default::default(),
+            round_start_ticks: Vec::new(),
+            players: Vec::new(),
             servername: String::new(),
+            spectators: Default::default(),
             tickrate: 0.0,
+            truncated: false,
+            last_good_tick: None,
+        }
+    }
+}
+
+impl DemoInfo {
+    /// Records that part of the demo was skipped in lenient mode. Only the first call sets
+    /// `last_good_tick`.
+    pub(crate) fn set_truncated(&mut self, last_good_tick: Option<Tick>) {
+        if !self.truncated {
+            self.truncated = true;
+            self.last_good_tick = last_good_tick;
         }
     }
+
+    /// Returns the events of type `event_type` as columns, one per field, in the form expected by
+    /// dataframe libraries. Fields missing from some of the events are null for those events.
+    pub fn event_columns(&self, event_type: &str) -> BTreeMap<String, Vec<serde_json::Value>> {
+        let events: Vec<_> = self
+            .events
+            .iter()
+            .filter_map(|e| e.as_object())
+            .filter(|e| e.get("type").and_then(|t| t.as_str()) == Some(event_type))
+            .collect();
+        let mut columns = BTreeMap::new();
+        for (row, event) in events.iter().enumerate() {
+            for (field, value) in event.iter() {
+                if field == "type" {
+                    continue;
+                }
+                columns
+                    .entry(field.clone())
+                    .or_insert_with(|| vec![serde_json::Value::Null; events.len()])[row] =
+                    value.clone();
+            }
+        }
+        columns
+    }
+}
+
+/// Everything known about a player over the course of the demo.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PlayerIdentity {
+    /// 0 for bots.
+    pub xuid: Xuid,
+    pub bot: bool,
+    /// True if the player coached longer than they played.
+    #[serde(default)]
+    pub coach: bool,
+    /// True if the player never joined a team.
+    #[serde(default)]
+    pub spectator: bool,
+    /// Every name used by the player, starting with the one used when first connecting.
+    pub names: Vec<PlayerName>,
+    pub connections: Vec<PlayerConnection>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PlayerName {
+    pub tick: Tick,
+    pub name: String,
+}
+
+/// A period during which the player used the same slot and user id.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PlayerConnection {
+    pub connect_tick: Tick,
+    /// Missing if the player was still connected at the end of the demo.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disconnect_tick: Option<Tick>,
+    pub slot: i32,
+    pub user_id: i32,
 }
 
 #[derive(Serialize)]
@@ -50,6 +151,10 @@ pub enum Event {
     RoundStart(RoundStart),
     RoundEnd(RoundEnd),
     RoundOfficiallyEnded,
+    /// Event emitted as is, see [`ParseOptions::extra_events`](crate::ParseOptions::extra_events).
+    /// It includes its type.
+    #[serde(untagged)]
+    Extra(serde_json::Map<String, serde_json::Value>),
 }
 
 type Xuid = u64;
@@ -122,6 +227,36 @@ mod tests {
             serde_json::to_string(&defuse)?,
             r#"{"tick":1,"type":"bomb_defused","userid":2}"#
         );
+        let pickup = EventTick {
+            tick: 2,
+            event: Event::Extra(
+                serde_json::json!({"type": "item_pickup", "item": "ak47"})
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+            ),
+        };
+        assert_eq!(
+            serde_json::to_string(&pickup)?,
+            r#"{"tick":2,"item":"ak47","type":"item_pickup"}"#
+        );
         Ok(())
     }
+
+    #[test]
+    fn event_columns() {
+        let demoinfo = DemoInfo {
+            events: vec![
+                serde_json::json!({"tick": 1, "type": "player_death", "userid": 2}),
+                serde_json::json!({"tick": 2, "type": "round_start", "timelimit": 115}),
+                serde_json::json!({"tick": 3, "type": "player_death", "userid": 4, "jump": 5}),
+            ],
+            ..Default::default()
+        };
+        let columns = demoinfo.event_columns("player_death");
+        assert_eq!(columns.len(), 3);
+        assert_eq!(columns["tick"], vec![1, 3]);
+        assert_eq!(columns["userid"], vec![2, 4]);
+        assert_eq!(columns["jump"], vec![serde_json::Value::Null, 5.into()]);
+    }
 }
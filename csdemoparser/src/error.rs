@@ -0,0 +1,256 @@
+//! Machine-readable description of parsing errors.
+
+use crate::Tick;
+use demo_context::Context;
+use serde::Serialize;
+
+/// Stable identifier of the kind of a [`ParseError`].
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    Io,
+    /// The demo ends in the middle of a command, usually because it is incomplete.
+    UnexpectedEof,
+    InvalidDemoType,
+    InvalidHeader,
+    UnknownCommand,
+    /// A protobuf message cannot be decoded.
+    InvalidMessage,
+    Decompression,
+    StringTable,
+    SendTables,
+    ClassInfo,
+    Entity,
+    FileInfo,
+    Other,
+}
+
+/// Where in the demo an error happened. Fields are missing when unknown.
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct Position {
+    /// Offset in the uncompressed demo of the demo command being parsed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tick: Option<Tick>,
+    /// Type of the packet message being parsed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entity_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entity_class: Option<String>,
+}
+
+/// A parsing error with a stable code and the position where it happened, built from the error
+/// returned by [`crate::parse`].
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub position: Position,
+}
+
+/// Error raised by csdemoparser while handling a part of the demo, with the same [`Context`] that
+/// the demo parsers add to their own errors.
+#[derive(thiserror::Error, Debug)]
+#[error("{context}")]
+pub(crate) struct ContextError {
+    context: Context,
+    #[source]
+    source: anyhow::Error,
+}
+
+/// Adds a [`Context`] to errors, see [`ContextError`].
+pub(crate) trait ResultExt<T> {
+    fn in_context(self, context: Context) -> anyhow::Result<T>;
+}
+
+impl<T, E: Into<anyhow::Error>> ResultExt<T> for Result<T, E> {
+    fn in_context(self, context: Context) -> anyhow::Result<T> {
+        self.map_err(|e| {
+            ContextError {
+                context,
+                source: e.into(),
+            }
+            .into()
+        })
+    }
+}
+
+impl From<&anyhow::Error> for ParseError {
+    fn from(error: &anyhow::Error) -> Self {
+        let mut position = Position::default();
+        // The innermost known error determines the code.
+        let mut code = ErrorCode::Other;
+        for cause in error.chain() {
+            let cause_code = if let Some(e) = cause.downcast_ref::<ContextError>() {
+                position.update(&e.context);
+                None
+            } else if let Some(e) = cause.downcast_ref::<cs2_demo::Error>() {
+                position.update_cs2(e)
+            } else if let Some(e) = cause.downcast_ref::<csgo_demo::Error>() {
+                position.update_csgo(e)
+            } else {
+                cause.downcast_ref::<std::io::Error>().map(io_code)
+            };
+            code = cause_code.unwrap_or(code);
+        }
+        Self {
+            code,
+            message: format!("{error:#}"),
+            position,
+        }
+    }
+}
+
+impl Position {
+    fn update(&mut self, context: &Context) {
+        match context {
+            Context::Command {
+                offset,
+                command,
+                tick,
+            } => {
+                self.offset = Some(*offset);
+                self.command = command.clone();
+                self.tick = *tick;
+            }
+            Context::Message(message) => self.message = Some(message.clone()),
+            Context::Entity { entity_id, class } => {
+                self.entity_id = Some(*entity_id);
+                self.entity_class = class.clone();
+            }
+        }
+    }
+
+    /// Records the position held by `e`, or returns its code if it is not a context wrapper.
+    fn update_cs2(&mut self, e: &cs2_demo::Error) -> Option<ErrorCode> {
+        use cs2_demo::Error::*;
+        Some(match e {
+            InContext { context, .. } => {
+                self.update(context);
+                return None;
+            }
+            Visitor(_) => return None,
+            Io(e) => io_code(e),
+            Protobuf(e) => protobuf_code(e),
+            MissingStringTable | MissingPacket => ErrorCode::InvalidMessage,
+            InvalidDemoType(_) => ErrorCode::InvalidDemoType,
+            UnknownPacketCommand(_) => ErrorCode::UnknownCommand,
+            Decompression(_) => ErrorCode::Decompression,
+            InvalidFileInfoOffset => ErrorCode::FileInfo,
+            InvalidStringTableId | InvalidStringTableIndex(_) | InvalidPlayerIndex => {
+                ErrorCode::StringTable
+            }
+            InvalidSendTables
+            | DuplicateSerializer
+            | MissingPolymorphicType { .. }
+            | MissingSendTables => ErrorCode::SendTables,
+            MissingClassId | MissingClassName | SkippedClassId | ClassInfoBeforeSendTables => {
+                ErrorCode::ClassInfo
+            }
+            InvalidEntityId | EntityBeforeClassInfo => ErrorCode::Entity,
+            // Only returned when writing demos.
+            CutStartNotFound(_) | RewrittenPropertySize(_) => ErrorCode::Other,
+        })
+    }
+
+    /// Records the position held by `e`, or returns its code if it is not a context wrapper.
+    fn update_csgo(&mut self, e: &csgo_demo::Error) -> Option<ErrorCode> {
+        use csgo_demo::Error::*;
+        Some(match e {
+            InContext { context, .. } => {
+                self.update(context);
+                return None;
+            }
+            Io(e) => io_code(e),
+            Protobuf(e) => protobuf_code(e),
+            HeaderParsing(csgo_demo::HeaderParsingError::InvalidDemoType(_)) => {
+                ErrorCode::InvalidDemoType
+            }
+            HeaderParsing(_) => ErrorCode::InvalidHeader,
+            UnknownPacketCommand(_) => ErrorCode::UnknownCommand,
+            DataTablesParsing(_) | ServerClass(_) => ErrorCode::SendTables,
+            StringTable(_) => ErrorCode::StringTable,
+            Entity(_) => ErrorCode::Entity,
+        })
+    }
+}
+
+fn io_code(e: &std::io::Error) -> ErrorCode {
+    match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => ErrorCode::UnexpectedEof,
+        _ => ErrorCode::Io,
+    }
+}
+
+fn protobuf_code(e: &protobuf::Error) -> ErrorCode {
+    // The kind of protobuf errors is private and they are transparent wrappers whose source() is
+    // the source of the wrapped error, so the io::Error of a truncated reader cannot be reached
+    // either. Only their message tells truncated input apart, see the protobuf_eof test.
+    match e.to_string().as_str() {
+        "Unexpected end of file" | "Unexpected EOF" | "Truncated message" => {
+            ErrorCode::UnexpectedEof
+        }
+        _ => ErrorCode::InvalidMessage,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncated_demo() {
+        let error = crate::parse_bytes(b"HL2DEMO\0").err().unwrap();
+        assert_eq!(ParseError::from(&error).code, ErrorCode::UnexpectedEof);
+
+        let error = crate::parse_bytes(b"NOTADEMO").err().unwrap();
+        assert_eq!(ParseError::from(&error).code, ErrorCode::InvalidDemoType);
+    }
+
+    #[test]
+    fn csgo_context() {
+        let error = Err::<(), _>(csgo_demo::Error::Entity("entity id not found"))
+            .in_context(Context::Message("PacketEntities".to_string()))
+            .in_context(Context::Command {
+                offset: 1234,
+                command: Some("Packet".to_string()),
+                tick: Some(100),
+            })
+            .unwrap_err();
+        let error = ParseError::from(&error);
+        assert_eq!(error.code, ErrorCode::Entity);
+        assert_eq!(
+            error.message,
+            "Packet command at tick 100, offset 1234: PacketEntities message: \
+             Entity error: entity id not found"
+        );
+        assert_eq!(
+            serde_json::to_value(&error.position).unwrap(),
+            serde_json::json!({
+                "offset": 1234,
+                "command": "Packet",
+                "tick": 100,
+                "message": "PacketEntities",
+            })
+        );
+    }
+
+    #[test]
+    fn protobuf_eof() {
+        use cs2_demo::proto::demo::CDemoFileInfo;
+        use protobuf::Message;
+        // A playback_time field whose value is missing.
+        let data = [0x0d, 0x00];
+        let error = CDemoFileInfo::parse_from_bytes(&data).unwrap_err();
+        assert_eq!(protobuf_code(&error), ErrorCode::UnexpectedEof);
+        let error = CDemoFileInfo::parse_from_reader(&mut data.as_slice()).unwrap_err();
+        assert_eq!(protobuf_code(&error), ErrorCode::UnexpectedEof);
+        let error = CDemoFileInfo::parse_from_bytes(&[0x0f]).unwrap_err();
+        assert_eq!(protobuf_code(&error), ErrorCode::InvalidMessage);
+    }
+}
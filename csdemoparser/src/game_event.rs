@@ -2,6 +2,32 @@
 
 use serde::Deserialize;
 
+/// Keys holding user ids in CS:GO game events, whose descriptors type them as shorts. CS2
+/// descriptors have a playercontroller type for them.
+const CSGO_PLAYER_KEYS: &[&str] = &["userid", "attacker", "assister"];
+
+/// Returns true if the game event key `name`, of descriptor type `type_`, holds the user id of a
+/// player.
+pub(crate) fn is_player_key(name: &str, type_: i32) -> bool {
+    match type_ {
+        // short
+        4 => CSGO_PLAYER_KEYS.contains(&name),
+        // short, playercontroller
+        9 => true,
+        _ => false,
+    }
+}
+
+/// Prefixes with `event_` the keys of an emitted game event that clash with the `tick` and
+/// `type` keys added to every event.
+pub(crate) fn rename_reserved_keys(attrs: &mut serde_json::Map<String, serde_json::Value>) {
+    for key in ["tick", "type"] {
+        if let Some(value) = attrs.remove(key) {
+            attrs.insert(format!("event_{key}"), value);
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub(crate) enum GameEvent {
@@ -1,15 +1,27 @@
+mod anonymize;
 mod cs2;
 mod csgo;
+mod decompress;
 pub mod demoinfo;
+pub mod error;
 mod game_event;
 mod geometry;
 mod last_jump;
+pub mod metadata;
+mod player_roles;
+mod player_timeline;
+mod properties;
+pub mod snapshot;
 
+use cs2_demo::entity::{PropertyChanges, PropertyPattern};
 use csgo_demo::entity::{Entity, EntityId, PropValue, Scalar};
+use decompress::ReadSeek;
 use demoinfo::DemoInfo;
+use metadata::DemoMetadata;
+use snapshot::EntitySnapshot;
 use std::{
-    fs::File,
-    io::{Read, Seek},
+    collections::HashSet,
+    io::{Read, Seek, Write},
 };
 
 type Tick = i32;
@@ -17,21 +29,189 @@ type Tick = i32;
 const SOURCE1_DEMO_TYPE: &[u8; 8] = b"HL2DEMO\0";
 const SOURCE2_DEMO_TYPE: &[u8; 8] = b"PBDEMS2\0";
 
-pub fn parse(read: &mut File) -> anyhow::Result<DemoInfo> {
+/// Options of [`parse_with_options`].
+#[derive(Clone, Debug, Default)]
+pub struct ParseOptions {
+    /// Keeps what was parsed instead of failing on truncated or corrupt demos: demo commands
+    /// that cannot be decoded are skipped, and parsing stops at a truncated one.
+    /// [`DemoInfo::truncated`] is set when this happens.
+    pub lenient: bool,
+    /// Game events emitted as they are in the demo, in addition to the ones always processed.
+    /// Their keys are typed according to the event descriptor, and player keys hold xuids.
+    pub extra_events: EventFilter,
+}
+
+/// Selects game events by name.
+#[derive(Clone, Debug, Default)]
+pub enum EventFilter {
+    #[default]
+    None,
+    All,
+    /// Only the events with these names.
+    Allow(HashSet<String>),
+    /// All the events except the ones with these names.
+    Deny(HashSet<String>),
+}
+
+impl EventFilter {
+    pub fn contains(&self, event: &str) -> bool {
+        match self {
+            EventFilter::None => false,
+            EventFilter::All => true,
+            EventFilter::Allow(events) => events.contains(event),
+            EventFilter::Deny(events) => !events.contains(event),
+        }
+    }
+}
+
+/// Game events processed by csdemoparser.
+const HSBOX_EVENTS: &[&str] = &[
+    "bomb_defused",
+    "bomb_exploded",
+    "bot_takeover",
+    "game_restart",
+    "player_connect",
+    "player_death",
+    "player_disconnect",
+    "player_hurt",
+    "player_jump",
+    "player_spawn",
+    "round_end",
+    "round_officially_ended",
+    "round_start",
+    "score_changed",
+    "smokegrenade_detonate",
+    "smokegrenade_expired",
+];
+
+/// Returns true if the game event named `event` is needed with these options.
+fn is_parsed_event(event: &str, options: &ParseOptions) -> bool {
+    HSBOX_EVENTS.contains(&event) || options.extra_events.contains(event)
+}
+
+/// Parses a demo, which can be compressed with gzip or bzip2 or stored in a zip archive. The
+/// input does not need to be seekable.
+pub fn parse(read: &mut dyn Read) -> anyhow::Result<DemoInfo> {
+    parse_with_options(read, &ParseOptions::default())
+}
+
+/// Same as [`parse`], with options.
+pub fn parse_with_options(read: &mut dyn Read, options: &ParseOptions) -> anyhow::Result<DemoInfo> {
+    decompress::with_decompressed(read, |read| {
+        let mut demo_type = [0; 8];
+        read.read_exact(&mut demo_type)?;
+        parse_demo(&demo_type, &mut demo_type.as_slice().chain(read), options)
+    })
+}
+
+/// Parses a demo held in memory.
+pub fn parse_bytes(mut data: &[u8]) -> anyhow::Result<DemoInfo> {
+    parse(&mut data)
+}
+
+fn parse_demo(
+    demo_type: &[u8; 8],
+    read: &mut dyn Read,
+    options: &ParseOptions,
+) -> anyhow::Result<DemoInfo> {
+    match demo_type {
+        SOURCE1_DEMO_TYPE => csgo::parse(read, options),
+        SOURCE2_DEMO_TYPE if std::env::var_os("CS2_EXPERIMENTAL_PARSER").is_some() => {
+            cs2::parse(read, options)
+        }
+        SOURCE2_DEMO_TYPE => anyhow::bail!(
+            "CS2 demo parser is not complete. You can test it by setting the \
+             CS2_EXPERIMENTAL_PARSER environment variable."
+        ),
+        demo_type => Err(cs2_demo::Error::InvalidDemoType(Box::new(*demo_type)).into()),
+    }
+}
+
+/// Returns every change of the CS2 entity properties matching `patterns`, with its tick. CS:GO
+/// demos are not supported. Like [`parse`], the demo can be compressed.
+pub fn record_properties(
+    read: &mut dyn Read,
+    patterns: Vec<PropertyPattern>,
+) -> anyhow::Result<PropertyChanges> {
+    decompress::with_decompressed(read, |read| {
+        let mut demo_type = [0; 8];
+        read.read_exact(&mut demo_type)?;
+        let read = &mut demo_type.as_slice().chain(read);
+        match &demo_type {
+            SOURCE2_DEMO_TYPE => properties::parse_cs2(read, patterns),
+            SOURCE1_DEMO_TYPE => {
+                anyhow::bail!("property recording is only supported for CS2 demos")
+            }
+            demo_type => Err(cs2_demo::Error::InvalidDemoType(Box::new(*demo_type)).into()),
+        }
+    })
+}
+
+/// Reads only the demo metadata: the header and, for CS2 demos, the first userinfo table and the
+/// file info at the end of the demo. Like [`parse`], the demo can be compressed, in which case it
+/// is decompressed in memory.
+pub fn parse_metadata<R: Read + Seek>(read: &mut R) -> anyhow::Result<DemoMetadata> {
+    decompress::with_seekable_decompressed(read, |read| match &read_demo_type(read)? {
+        SOURCE1_DEMO_TYPE => metadata::parse_csgo(read),
+        SOURCE2_DEMO_TYPE => metadata::parse_cs2(read),
+        demo_type => Err(cs2_demo::Error::InvalidDemoType(Box::new(*demo_type)).into()),
+    })
+}
+
+/// Parses the demo up to `tick` and returns the state of all the entities at that tick. For CS2
+/// demos, parsing starts from the last full packet before `tick`. Like [`parse`], the demo can be
+/// compressed, in which case it is decompressed in memory.
+pub fn snapshot<R: Read + Seek>(read: &mut R, tick: Tick) -> anyhow::Result<EntitySnapshot> {
+    decompress::with_seekable_decompressed(read, |read| match &read_demo_type(read)? {
+        SOURCE1_DEMO_TYPE => snapshot::parse_csgo(read, tick),
+        SOURCE2_DEMO_TYPE => snapshot::parse_cs2(read, tick),
+        demo_type => Err(cs2_demo::Error::InvalidDemoType(Box::new(*demo_type)).into()),
+    })
+}
+
+/// Writes a copy of the demo where player names, xuids and Steam ids are replaced with stable
+/// pseudonyms, in player info, player events, chat messages and, for CS2, entity properties and
+/// instance baselines, such as the names and Steam ids of player controllers. The server name is
+/// replaced too. Like [`parse`], the demo can be compressed; the copy is not.
+pub fn anonymize<W: Write + Seek>(read: &mut dyn Read, write: W) -> anyhow::Result<W> {
+    decompress::with_decompressed(read, |read| {
+        let mut demo_type = [0; 8];
+        read.read_exact(&mut demo_type)?;
+        let read = &mut demo_type.as_slice().chain(read);
+        match &demo_type {
+            SOURCE1_DEMO_TYPE => anonymize::anonymize_csgo(read, write),
+            SOURCE2_DEMO_TYPE => anonymize::anonymize_cs2(read, write),
+            demo_type => Err(cs2_demo::Error::InvalidDemoType(Box::new(*demo_type)).into()),
+        }
+    })
+}
+
+/// Copies the part of a CS2 demo between `start_tick` and `end_tick`, see [`cs2_demo::cut`]: the
+/// copy starts at the last full packet before `start_tick`. Like [`parse`], the demo can be
+/// compressed; the copy is not.
+pub fn cut<W: Write + Seek>(
+    read: &mut dyn Read,
+    write: W,
+    start_tick: Tick,
+    end_tick: Tick,
+) -> anyhow::Result<W> {
+    decompress::with_decompressed(read, |read| {
+        let mut demo_type = [0; 8];
+        read.read_exact(&mut demo_type)?;
+        let read = &mut demo_type.as_slice().chain(read);
+        match &demo_type {
+            SOURCE1_DEMO_TYPE => anyhow::bail!("only CS2 demos can be cut"),
+            SOURCE2_DEMO_TYPE => Ok(cs2_demo::cut(read, write, start_tick, end_tick)?),
+            demo_type => Err(cs2_demo::Error::InvalidDemoType(Box::new(*demo_type)).into()),
+        }
+    })
+}
+
+fn read_demo_type(read: &mut dyn ReadSeek) -> anyhow::Result<[u8; 8]> {
     let mut demo_type = [0; 8];
     read.read_exact(&mut demo_type)?;
     read.rewind()?;
-    match &demo_type {
-        SOURCE1_DEMO_TYPE => csgo::parse(read),
-        SOURCE2_DEMO_TYPE => {
-            if std::env::var("CS2_EXPERIMENTAL_PARSER").is_ok() {
-                cs2::parse(read)
-            } else {
-                panic!("CS2 demo parser is not complete. You can test it by seting the CS2_EXPERIMENTAL_PARSER environment variable.")
-            }
-        }
-        _ => Err(cs2_demo::Error::InvalidDemoType(Box::new(demo_type)).into()),
-    }
+    Ok(demo_type)
 }
 
 #[derive(Eq, PartialEq, Hash, Clone, Copy)]
@@ -101,6 +281,64 @@ fn maybe_get_i32(v: Option<&serde_json::Value>) -> Option<i32> {
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    /// Returns a CS:GO demo header followed by the given commands, each given as (command, tick,
+    /// data).
+    pub(crate) fn make_csgo_demo(commands: &[(u8, Tick, &[u8])]) -> Vec<u8> {
+        let string = |s: &str| {
+            let mut buf = s.as_bytes().to_vec();
+            buf.resize(260, 0);
+            buf
+        };
+        let mut demo = b"HL2DEMO\0".to_vec();
+        demo.extend(4u32.to_le_bytes());
+        demo.extend(13881u32.to_le_bytes());
+        demo.extend(string("server"));
+        demo.extend(string("GOTV Demo"));
+        demo.extend(string("de_dust2"));
+        demo.extend(string("csgo"));
+        demo.extend(0f32.to_le_bytes());
+        demo.extend([0; 12]);
+        for (command, tick, data) in commands {
+            demo.push(*command);
+            demo.extend(tick.to_le_bytes());
+            demo.push(0);
+            demo.extend(data.iter());
+        }
+        demo
+    }
+
+    /// Returns the data of a CS:GO DataTables command with a single CCSPlayer server class,
+    /// whose properties are 8-bit unsigned integers named `props`.
+    pub(crate) fn make_data_tables(props: &[&str]) -> Vec<u8> {
+        use csgo_demo::proto::netmessages::csvcmsg_send_table::Sendprop_t;
+        use csgo_demo::proto::netmessages::CSVCMsg_SendTable;
+        use protobuf::Message as _;
+        const SVC_SEND_TABLE: u8 = 9;
+        const DPT_INT: i32 = 0;
+        const SPROP_UNSIGNED: i32 = 1;
+
+        let mut send_table = CSVCMsg_SendTable::new();
+        send_table.set_net_table_name("DT_CSPlayer".to_string());
+        send_table.set_is_end(true);
+        for name in props {
+            let mut prop = Sendprop_t::new();
+            prop.set_type(DPT_INT);
+            prop.set_var_name(name.to_string());
+            prop.set_num_bits(8);
+            prop.set_flags(SPROP_UNSIGNED);
+            send_table.props.push(prop);
+        }
+        let mut data_tables = vec![SVC_SEND_TABLE];
+        data_tables.extend(send_table.write_length_delimited_to_bytes().unwrap());
+        data_tables.extend(1u16.to_le_bytes());
+        data_tables.extend(0u16.to_le_bytes());
+        data_tables.extend(b"CCSPlayer\0DT_CSPlayer\0");
+        let mut command = (data_tables.len() as u32).to_le_bytes().to_vec();
+        command.extend(data_tables);
+        command
+    }
+
     #[test]
     fn test_guid_to_xuid() {
         assert_eq!(
@@ -109,4 +347,93 @@ mod tests {
         );
         assert!(guid_to_xuid("BOT").is_err());
     }
+
+    #[test]
+    fn event_filter() {
+        let options = ParseOptions {
+            extra_events: EventFilter::Deny(HashSet::from(["player_footstep".to_string()])),
+            ..Default::default()
+        };
+        assert!(is_parsed_event("player_death", &options));
+        assert!(is_parsed_event("item_pickup", &options));
+        assert!(!is_parsed_event("player_footstep", &options));
+        assert!(!is_parsed_event("item_pickup", &ParseOptions::default()));
+    }
+
+    #[test]
+    fn record_properties_csgo() {
+        let patterns = vec![PropertyPattern::new("CCSPlayer", "m_iHealth")];
+        let error = record_properties(&mut SOURCE1_DEMO_TYPE.as_slice(), patterns).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "property recording is only supported for CS2 demos"
+        );
+    }
+
+    #[test]
+    fn cs2_parser_is_gated() {
+        if std::env::var_os("CS2_EXPERIMENTAL_PARSER").is_some() {
+            return;
+        }
+        let Err(error) = parse_bytes(SOURCE2_DEMO_TYPE) else {
+            panic!("CS2 demo parsed without CS2_EXPERIMENTAL_PARSER");
+        };
+        assert!(error.to_string().contains("CS2_EXPERIMENTAL_PARSER"));
+    }
+
+    #[test]
+    fn file_info() -> anyhow::Result<()> {
+        use cs2_demo::proto::demo::{CDemoFileInfo, EDemoCommands};
+        use protobuf::Message;
+        let mut file_info = CDemoFileInfo::new();
+        file_info.set_playback_time(1834.5);
+        file_info.set_playback_ticks(117408);
+        let cs = file_info
+            .game_info
+            .mut_or_insert_default()
+            .cs
+            .mut_or_insert_default();
+        cs.round_start_ticks = vec![1120, 9856, 17344];
+        let file_info = file_info.write_to_bytes()?;
+        let mut demo = SOURCE2_DEMO_TYPE.to_vec();
+        demo.extend([0; 8]);
+        demo.extend([EDemoCommands::DEM_FileInfo as u8, 0, file_info.len() as u8]);
+        demo.extend(file_info);
+
+        let demoinfo = cs2::parse(&mut demo.as_slice(), &ParseOptions::default())?;
+        assert_eq!(demoinfo.duration, Some(1834.5));
+        assert_eq!(demoinfo.round_start_ticks, vec![1120, 9856, 17344]);
+        Ok(())
+    }
+
+    #[test]
+    fn compressed_metadata() -> anyhow::Result<()> {
+        use std::io::{Cursor, Write};
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(&make_csgo_demo(&[]))?;
+        let metadata = parse_metadata(&mut Cursor::new(gz.finish()?))?;
+        assert_eq!(metadata.map, "de_dust2");
+        assert_eq!(metadata.servername, "server");
+        Ok(())
+    }
+
+    #[test]
+    fn lenient() -> anyhow::Result<()> {
+        let sync_tick = cs2_demo::proto::demo::EDemoCommands::DEM_SyncTick as u8;
+        let mut demo = SOURCE2_DEMO_TYPE.to_vec();
+        demo.extend([0; 8]);
+        demo.extend([sync_tick, 5, 0]);
+        // Command at tick 6 with 10 missing bytes.
+        demo.extend([sync_tick, 6, 10]);
+        assert!(cs2::parse(&mut demo.as_slice(), &ParseOptions::default()).is_err());
+
+        let options = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let demoinfo = cs2::parse(&mut demo.as_slice(), &options)?;
+        assert!(demoinfo.truncated);
+        assert_eq!(demoinfo.last_good_tick, Some(5));
+        Ok(())
+    }
 }
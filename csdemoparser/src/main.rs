@@ -1,9 +1,15 @@
-use std::{env, error, fs::File};
+mod batch;
+
+use cs2_demo::entity::PropertyPattern;
+use csdemoparser::error::ParseError;
+use csdemoparser::{EventFilter, ParseOptions};
+use std::collections::HashSet;
+use std::{env, error, fs::File, process::ExitCode};
 
 #[cfg(feature = "tracing")]
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
-fn main() -> Result<(), Box<dyn error::Error>> {
+fn main() -> Result<ExitCode, Box<dyn error::Error>> {
     #[cfg(feature = "tracing")]
     {
         tracing_subscriber::registry()
@@ -14,9 +20,127 @@ fn main() -> Result<(), Box<dyn error::Error>> {
 
     let mut args = env::args();
     args.next();
-    let dem_path = args.next().ok_or("need dem file path")?;
+    let mut dem_path = args.next().ok_or("need dem file path")?;
+    if dem_path == "--batch" {
+        let mut options = batch::Options::default();
+        let mut inputs = Vec::new();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--ndjson" => options.ndjson = true,
+                "--threads" => {
+                    options.threads = Some(args.next().ok_or("need number of threads")?.parse()?)
+                }
+                _ if parse_option(&arg, &mut args, &mut options.parse)? => (),
+                _ => inputs.push(arg),
+            }
+        }
+        let stats = batch::run(&inputs, &options)?;
+        eprintln!("{stats}");
+        return Ok(if stats.failed > 0 {
+            ExitCode::FAILURE
+        } else {
+            ExitCode::SUCCESS
+        });
+    }
+    let mut options = ParseOptions::default();
+    while parse_option(&dem_path, &mut args, &mut options)? {
+        dem_path = args.next().ok_or("need dem file path")?;
+    }
+    let metadata_only = dem_path == "--metadata";
+    let anonymize = dem_path == "--anonymize";
+    let entities_tick = if dem_path == "--entities" {
+        Some(args.next().ok_or("need tick")?.parse()?)
+    } else {
+        None
+    };
+    let cut_ticks = if dem_path == "--cut" {
+        let start: i32 = args.next().ok_or("need start tick")?.parse()?;
+        let end: i32 = args.next().ok_or("need end tick")?.parse()?;
+        Some((start, end))
+    } else {
+        None
+    };
+    let properties = if dem_path == "--properties" {
+        let patterns = args
+            .next()
+            .ok_or("need comma-separated class:path patterns")?;
+        Some(parse_property_patterns(&patterns)?)
+    } else {
+        None
+    };
+    if metadata_only
+        || anonymize
+        || entities_tick.is_some()
+        || properties.is_some()
+        || cut_ticks.is_some()
+    {
+        dem_path = args.next().ok_or("need dem file path")?;
+    }
     let mut demo_file = File::open(dem_path)?;
-    let demoinfo = csdemoparser::parse(&mut demo_file)?;
-    serde_json::to_writer(std::io::stdout(), &demoinfo)?;
-    Ok(())
+    if let Some(patterns) = properties {
+        let changes = csdemoparser::record_properties(&mut demo_file, patterns)?;
+        changes.write_csv(&mut std::io::BufWriter::new(std::io::stdout().lock()))?;
+    } else if let Some(tick) = entities_tick {
+        let snapshot = csdemoparser::snapshot(&mut demo_file, tick)?;
+        serde_json::to_writer(std::io::stdout(), &snapshot)?;
+    } else if let Some((start_tick, end_tick)) = cut_ticks {
+        let out_path = args.next().ok_or("need output dem file path")?;
+        csdemoparser::cut(
+            &mut demo_file,
+            File::create(out_path)?,
+            start_tick,
+            end_tick,
+        )?;
+    } else if anonymize {
+        let out_path = args.next().ok_or("need output dem file path")?;
+        csdemoparser::anonymize(&mut demo_file, File::create(out_path)?)?;
+    } else if metadata_only {
+        let metadata = csdemoparser::parse_metadata(&mut demo_file)?;
+        serde_json::to_writer(std::io::stdout(), &metadata)?;
+    } else {
+        match csdemoparser::parse_with_options(&mut demo_file, &options) {
+            Ok(demoinfo) => serde_json::to_writer(std::io::stdout(), &demoinfo)?,
+            Err(e) => {
+                // Written as JSON so that callers can tell where the demo is broken.
+                serde_json::to_writer(std::io::stderr(), &ParseError::from(&e))?;
+                eprintln!();
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Reads `arg` into `options` if it is a parsing option, along with its value from `args`.
+/// Returns false for other arguments.
+fn parse_option(
+    arg: &str,
+    args: &mut env::Args,
+    options: &mut ParseOptions,
+) -> Result<bool, Box<dyn error::Error>> {
+    let mut event_names = || -> Result<HashSet<String>, &str> {
+        let names = args.next().ok_or("need comma-separated event names")?;
+        Ok(names.split(',').map(str::to_string).collect())
+    };
+    match arg {
+        "--lenient" => options.lenient = true,
+        "--all-events" => options.extra_events = EventFilter::All,
+        "--events" => options.extra_events = EventFilter::Allow(event_names()?),
+        "--exclude-events" => options.extra_events = EventFilter::Deny(event_names()?),
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Parses patterns such as `CCSPlayerPawn:m_iHealth,*:m_pEntity.m_nameStringableIndex`.
+fn parse_property_patterns(patterns: &str) -> Result<Vec<PropertyPattern>, String> {
+    patterns
+        .split(',')
+        .map(|pattern| match pattern.split_once(':') {
+            Some((class, path)) => Ok(PropertyPattern::new(class, path)),
+            None => Err(format!(
+                "invalid property pattern {pattern}, expected class:path"
+            )),
+        })
+        .collect()
 }
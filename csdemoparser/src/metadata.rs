@@ -0,0 +1,97 @@
+use crate::decompress::ReadSeek;
+use crate::Tick;
+use cs2_demo::entity::TreeEntity;
+use cs2_demo::proto::demo::CDemoFileHeader;
+use cs2_demo::{ParseOptions, UserInfo, Visitor};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+
+/// The output of csdemoparser in metadata mode. It only needs the start and the end of the demo
+/// file, so it is much faster to get than [`crate::DemoInfo`].
+#[derive(Serialize, Default, Debug)]
+pub struct DemoMetadata {
+    pub map: String,
+    pub servername: String,
+    pub client_name: String,
+    pub network_protocol: i32,
+    /// Duration of the demo, in seconds. Missing for incomplete CS2 demos.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ticks: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frames: Option<i32>,
+    /// Maps player xuid to name. Only available for CS2 demos.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub player_names: HashMap<String, String>,
+}
+
+pub(crate) fn parse_csgo(read: &mut dyn ReadSeek) -> anyhow::Result<DemoMetadata> {
+    let parser = csgo_demo::DemoParser::try_new(read)?;
+    let header = parser.header();
+    Ok(DemoMetadata {
+        map: header.map_name().clone(),
+        servername: header.server_name().clone(),
+        client_name: header.client_name().clone(),
+        network_protocol: *header.network_protocol() as i32,
+        duration: Some(*header.duration()),
+        ticks: Some(*header.ticks() as i32),
+        frames: Some(*header.frames() as i32),
+        player_names: HashMap::new(),
+    })
+}
+
+pub(crate) fn parse_cs2(mut read: &mut dyn ReadSeek) -> anyhow::Result<DemoMetadata> {
+    let mut visitor = MetadataVisitor::default();
+    let options = ParseOptions {
+        decode_entities: false,
+        ..Default::default()
+    };
+    cs2_demo::parse_with_options(read, &mut visitor, &TreeEntity::factory, options)?;
+    let mut metadata = visitor.metadata;
+    if let Some(file_info) = cs2_demo::read_file_info(&mut read)? {
+        metadata.duration = Some(file_info.playback_time());
+        metadata.ticks = Some(file_info.playback_ticks());
+        metadata.frames = Some(file_info.playback_frames());
+    }
+    Ok(metadata)
+}
+
+/// Collects the file header and the first userinfo table, then stops the parsing.
+#[derive(Default)]
+struct MetadataVisitor {
+    metadata: DemoMetadata,
+    found_userinfo: bool,
+}
+
+impl Visitor for MetadataVisitor {
+    fn visit_tick(&mut self, _tick: Tick) -> anyhow::Result<ControlFlow<()>> {
+        Ok(if self.found_userinfo {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        })
+    }
+
+    fn visit_file_header(&mut self, header: CDemoFileHeader) -> anyhow::Result<()> {
+        self.metadata.map = header.map_name().to_string();
+        self.metadata.servername = header.server_name().to_string();
+        self.metadata.client_name = header.client_name().to_string();
+        self.metadata.network_protocol = header.network_protocol();
+        Ok(())
+    }
+
+    fn visit_userinfo_table(&mut self, user_info: Vec<UserInfo>) -> anyhow::Result<()> {
+        self.found_userinfo = true;
+        for ui in user_info {
+            if ui.info.is_hltv || ui.info.fakeplayer {
+                continue;
+            }
+            self.metadata
+                .player_names
+                .insert(ui.info.xuid.to_string(), ui.info.name);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,274 @@
+use crate::demoinfo::{PlayerConnection, PlayerIdentity};
+use crate::{DemoInfo, Tick};
+use std::collections::HashMap;
+
+const TEAM_SPECTATOR: i32 = 1;
+const TEAM_TERRORIST: i32 = 2;
+const TEAM_CT: i32 = 3;
+
+/// Events used in kill and round stats.
+const STATS_EVENTS: &[&str] = &[
+    "bomb_defused",
+    "bomb_exploded",
+    "player_death",
+    "player_hurt",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Role {
+    Unassigned,
+    Playing,
+    Spectating,
+    Coaching,
+}
+
+/// Tracks the teams of every player slot over time, to tell coaches and spectators apart from
+/// players.
+#[derive(Default)]
+pub(crate) struct PlayerRoles {
+    slots: HashMap<i32, SlotRoles>,
+    /// Tick of the last update.
+    last_tick: Tick,
+}
+
+#[derive(Default)]
+struct SlotRoles {
+    team: i32,
+    coaching_team: i32,
+    /// Tick and role of every role change, ordered by tick.
+    changes: Vec<(Tick, Role)>,
+}
+
+impl SlotRoles {
+    fn update(&mut self, tick: Tick) {
+        let role = match (self.coaching_team, self.team) {
+            (TEAM_TERRORIST | TEAM_CT, _) => Role::Coaching,
+            (_, TEAM_SPECTATOR) => Role::Spectating,
+            (_, TEAM_TERRORIST | TEAM_CT) => Role::Playing,
+            _ => Role::Unassigned,
+        };
+        match self.changes.last_mut() {
+            Some((_, last)) if *last == role => (),
+            // The team and coaching team of a tick are updated one after the other.
+            Some((last_tick, last)) if *last_tick == tick => *last = role,
+            _ => self.changes.push((tick, role)),
+        }
+    }
+
+    fn role_at(&self, tick: Tick) -> Role {
+        let pos = self.changes.partition_point(|&(t, _)| t <= tick);
+        pos.checked_sub(1)
+            .map_or(Role::Unassigned, |i| self.changes[i].1)
+    }
+
+    /// Adds the number of ticks spent in each role between `start` and `end` to `durations`.
+    fn add_durations(&self, start: Tick, end: Tick, durations: &mut HashMap<Role, i64>) {
+        let mut from = start;
+        let mut role = self.role_at(start);
+        for &(tick, next) in self.changes.iter().filter(|&&(t, _)| t > start && t < end) {
+            *durations.entry(role).or_default() += (tick - from) as i64;
+            (from, role) = (tick, next);
+        }
+        *durations.entry(role).or_default() += (end - from).max(0) as i64;
+    }
+}
+
+impl PlayerRoles {
+    pub(crate) fn update_team(&mut self, slot: i32, team: i32, tick: Tick) {
+        let roles = self.slots.entry(slot).or_default();
+        roles.team = team;
+        roles.update(tick);
+        self.last_tick = self.last_tick.max(tick);
+    }
+
+    pub(crate) fn update_coaching_team(&mut self, slot: i32, team: i32, tick: Tick) {
+        let roles = self.slots.entry(slot).or_default();
+        roles.coaching_team = team;
+        roles.update(tick);
+        self.last_tick = self.last_tick.max(tick);
+    }
+
+    /// Returns the role of the player using `connection` at `tick`.
+    fn role_at(&self, connection: &PlayerConnection, tick: Tick) -> Role {
+        self.slots
+            .get(&connection.slot)
+            .map_or(Role::Unassigned, |roles| roles.role_at(tick))
+    }
+
+    /// Flags coaches and spectators in `demoinfo.players` and moves them from `player_names` and
+    /// `player_slots` to `coaches` and `spectators`. Roles are decided by the time spent in them
+    /// during the connections of each player, until `end_tick` for the ones still connected:
+    /// coaches coached longer than they played, spectators never played nor coached.
+    ///
+    /// The stats events of players who were coaching or spectating at the time of the event are
+    /// removed from `demoinfo.events`, so they don't show up in player stats.
+    pub(crate) fn apply(&self, demoinfo: &mut DemoInfo, end_tick: Tick) {
+        // The demo lasts at least until the last update.
+        let end_tick = end_tick.max(self.last_tick + 1);
+        for player in demoinfo.players.iter_mut() {
+            let mut durations = HashMap::new();
+            for c in &player.connections {
+                if let Some(roles) = self.slots.get(&c.slot) {
+                    let end = c.disconnect_tick.unwrap_or(end_tick);
+                    roles.add_durations(c.connect_tick, end, &mut durations);
+                }
+            }
+            let duration = |role| durations.get(&role).copied().unwrap_or_default();
+            player.coach = duration(Role::Coaching) > duration(Role::Playing);
+            player.spectator =
+                !player.coach && duration(Role::Playing) == 0 && duration(Role::Spectating) > 0;
+        }
+        for player in demoinfo.players.iter().filter(|p| !p.bot) {
+            let target = if player.coach {
+                &mut demoinfo.coaches
+            } else if player.spectator {
+                &mut demoinfo.spectators
+            } else {
+                continue;
+            };
+            let xuid = player.xuid.to_string();
+            target.insert(xuid.clone(), last_name(player));
+            demoinfo.player_names.remove(&xuid);
+            demoinfo.player_slots.remove(&xuid);
+        }
+        let connections: HashMap<u64, &[PlayerConnection]> = demoinfo
+            .players
+            .iter()
+            .filter(|p| !p.bot)
+            .map(|p| (p.xuid, p.connections.as_slice()))
+            .collect();
+        demoinfo.events.retain(|event| {
+            let Some(tick) = event.get("tick").and_then(|t| t.as_i64()) else {
+                return true;
+            };
+            let tick = tick as Tick;
+            let is_excluded = |key| {
+                let Some(connections) = event
+                    .get(key)
+                    .and_then(|xuid| xuid.as_u64())
+                    .and_then(|xuid| connections.get(&xuid))
+                else {
+                    return false;
+                };
+                connections
+                    .iter()
+                    .filter(|c| {
+                        c.connect_tick <= tick && !matches!(c.disconnect_tick, Some(t) if t <= tick)
+                    })
+                    .any(|c| matches!(self.role_at(c, tick), Role::Coaching | Role::Spectating))
+            };
+            let stats = event
+                .get("type")
+                .and_then(|t| t.as_str())
+                .is_some_and(|t| STATS_EVENTS.contains(&t));
+            !(stats && (is_excluded("userid") || is_excluded("attacker")))
+        });
+    }
+}
+
+fn last_name(player: &PlayerIdentity) -> String {
+    player
+        .names
+        .last()
+        .map(|n| n.name.clone())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player_timeline::PlayerTimeline;
+    use serde_json::json;
+
+    fn make_demoinfo(timeline: PlayerTimeline) -> DemoInfo {
+        let mut demoinfo = DemoInfo {
+            players: timeline.into_players(),
+            ..Default::default()
+        };
+        for player in &demoinfo.players {
+            let xuid = player.xuid.to_string();
+            demoinfo
+                .player_names
+                .insert(xuid.clone(), player.names[0].name.clone());
+            demoinfo
+                .player_slots
+                .insert(xuid, player.connections[0].slot);
+        }
+        demoinfo
+    }
+
+    #[test]
+    fn coach_and_spectator() {
+        let mut timeline = PlayerTimeline::default();
+        timeline.update(1, 1001, "player", 1, 11, false);
+        timeline.update(1, 1002, "coach", 2, 12, false);
+        timeline.update(1, 1003, "spectator", 3, 13, false);
+        let mut demoinfo = make_demoinfo(timeline);
+
+        let mut roles = PlayerRoles::default();
+        roles.update_team(1, TEAM_SPECTATOR, 1);
+        roles.update_team(1, TEAM_CT, 10);
+        roles.update_coaching_team(2, TEAM_CT, 1);
+        roles.update_team(2, TEAM_CT, 1);
+        roles.update_team(3, TEAM_SPECTATOR, 1);
+        demoinfo.events = vec![
+            json!({"type": "player_hurt", "tick": 20, "userid": 1001, "attacker": 1003}),
+            json!({"type": "player_death", "tick": 30, "userid": 1002, "attacker": 1001}),
+            json!({"type": "player_death", "tick": 40, "userid": 1001, "attacker": 0}),
+            json!({"type": "player_disconnect", "tick": 50, "userid": 1002}),
+        ];
+        roles.apply(&mut demoinfo, 100);
+
+        assert_eq!(
+            demoinfo.player_names.keys().collect::<Vec<_>>(),
+            vec!["1001"]
+        );
+        assert_eq!(demoinfo.coaches.get("1002").unwrap(), "coach");
+        assert_eq!(demoinfo.spectators.get("1003").unwrap(), "spectator");
+        assert!(demoinfo.players[1].coach && !demoinfo.players[1].spectator);
+        assert!(demoinfo.players[2].spectator);
+        assert_eq!(
+            demoinfo.events,
+            vec![
+                json!({"type": "player_death", "tick": 40, "userid": 1001, "attacker": 0}),
+                json!({"type": "player_disconnect", "tick": 50, "userid": 1002}),
+            ]
+        );
+    }
+
+    #[test]
+    fn slot_reuse() {
+        let mut timeline = PlayerTimeline::default();
+        // A coach leaves and a player takes their slot.
+        timeline.update(1, 1001, "coach", 1, 11, false);
+        timeline.disconnect(100, 11);
+        timeline.connect(12);
+        timeline.update(200, 1002, "player", 1, 12, false);
+        // A coach becomes a player for most of the demo.
+        timeline.update(1, 1003, "substitute", 2, 13, false);
+        let mut demoinfo = make_demoinfo(timeline);
+
+        let mut roles = PlayerRoles::default();
+        roles.update_coaching_team(1, TEAM_CT, 1);
+        roles.update_team(1, TEAM_CT, 1);
+        roles.update_coaching_team(1, 0, 200);
+        roles.update_coaching_team(2, TEAM_TERRORIST, 1);
+        roles.update_team(2, TEAM_TERRORIST, 1);
+        roles.update_coaching_team(2, 0, 100);
+        demoinfo.events = vec![
+            json!({"type": "player_death", "tick": 50, "userid": 1002, "attacker": 1003}),
+            json!({"type": "player_death", "tick": 300, "userid": 1003, "attacker": 1002}),
+        ];
+        roles.apply(&mut demoinfo, 1000);
+
+        assert_eq!(demoinfo.coaches.keys().collect::<Vec<_>>(), vec!["1001"]);
+        let mut player_names: Vec<_> = demoinfo.player_names.keys().collect();
+        player_names.sort();
+        assert_eq!(player_names, vec!["1002", "1003"]);
+        // The substitute was still coaching at tick 50.
+        assert_eq!(
+            demoinfo.events,
+            vec![json!({"type": "player_death", "tick": 300, "userid": 1003, "attacker": 1002})]
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use crate::demoinfo::{PlayerConnection, PlayerIdentity, PlayerName};
+use crate::Tick;
+use std::collections::HashMap;
+
+/// Identifies the same player across reconnects. Bots have no xuid so they are identified by
+/// name.
+#[derive(PartialEq, Eq, Hash)]
+enum PlayerKey {
+    Xuid(u64),
+    Bot(String),
+}
+
+/// The player info of a userinfo table entry.
+#[derive(PartialEq, Eq)]
+struct Entry {
+    xuid: u64,
+    name: String,
+    user_id: i32,
+    bot: bool,
+}
+
+/// Builds [`PlayerIdentity`] timelines from player info updates seen during the demo.
+#[derive(Default)]
+pub(crate) struct PlayerTimeline {
+    players: Vec<PlayerIdentity>,
+    index: HashMap<PlayerKey, usize>,
+    /// The last entry of every slot of the userinfo table.
+    entries: HashMap<i32, Entry>,
+}
+
+impl PlayerTimeline {
+    /// Records the current info of a player. Name, slot and user id changes start a new entry in
+    /// the corresponding timeline.
+    ///
+    /// CS2 FullPackets and CS:GO StringTables commands send the whole userinfo table again, so
+    /// the info is ignored if the entry of `slot` is unchanged. Otherwise a player who has
+    /// disconnected but whose entry is still in the table would reconnect.
+    pub(crate) fn update(
+        &mut self,
+        tick: Tick,
+        xuid: u64,
+        name: &str,
+        slot: i32,
+        user_id: i32,
+        bot: bool,
+    ) {
+        let entry = Entry {
+            xuid,
+            name: name.to_string(),
+            user_id,
+            bot,
+        };
+        if self.entries.get(&slot) == Some(&entry) {
+            return;
+        }
+        self.entries.insert(slot, entry);
+        let key = if bot {
+            PlayerKey::Bot(name.to_string())
+        } else {
+            PlayerKey::Xuid(xuid)
+        };
+        let players = &mut self.players;
+        let i = *self.index.entry(key).or_insert_with(|| {
+            players.push(PlayerIdentity {
+                xuid,
+                bot,
+                coach: false,
+                spectator: false,
+                names: Vec::new(),
+                connections: Vec::new(),
+            });
+            players.len() - 1
+        });
+        let player = &mut self.players[i];
+        if player.names.last().map(|n| n.name.as_str()) != Some(name) {
+            player.names.push(PlayerName {
+                tick,
+                name: name.to_string(),
+            });
+        }
+        if let Some(last) = player.connections.last_mut() {
+            if last.disconnect_tick.is_none() {
+                if last.slot == slot && last.user_id == user_id {
+                    return;
+                }
+                last.disconnect_tick = Some(tick);
+            }
+        }
+        player.connections.push(PlayerConnection {
+            connect_tick: tick,
+            disconnect_tick: None,
+            slot,
+            user_id,
+        });
+    }
+
+    /// Records that a player connected with `user_id`: the next update of their slot starts a new
+    /// connection, even if the userinfo entry of the slot is unchanged.
+    pub(crate) fn connect(&mut self, user_id: i32) {
+        self.entries.retain(|_, entry| entry.user_id != user_id);
+    }
+
+    /// Ends the connection of the player currently using `user_id`.
+    pub(crate) fn disconnect(&mut self, tick: Tick, user_id: i32) {
+        for player in self.players.iter_mut() {
+            if let Some(last) = player.connections.last_mut() {
+                if last.disconnect_tick.is_none() && last.user_id == user_id {
+                    last.disconnect_tick = Some(tick);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn into_players(self) -> Vec<PlayerIdentity> {
+        self.players
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rename_and_reconnect() {
+        let mut timeline = PlayerTimeline::default();
+        timeline.update(10, 1007, "a", 1, 7, false);
+        timeline.update(12, 1007, "a", 1, 7, false);
+        timeline.update(20, 1007, "b", 1, 7, false);
+        timeline.disconnect(30, 7);
+        timeline.update(40, 1007, "b", 3, 9, false);
+        timeline.update(50, 0, "Bot", 4, 10, true);
+
+        let players = timeline.into_players();
+        assert_eq!(players.len(), 2);
+        let names: Vec<_> = players[0]
+            .names
+            .iter()
+            .map(|n| (n.tick, n.name.as_str()))
+            .collect();
+        assert_eq!(names, vec![(10, "a"), (20, "b")]);
+        let connections: Vec<_> = players[0]
+            .connections
+            .iter()
+            .map(|c| (c.connect_tick, c.disconnect_tick, c.slot, c.user_id))
+            .collect();
+        assert_eq!(connections, vec![(10, Some(30), 1, 7), (40, None, 3, 9)]);
+        assert!(players[1].bot);
+    }
+
+    #[test]
+    fn unchanged_table_after_disconnect() {
+        let mut timeline = PlayerTimeline::default();
+        timeline.update(10, 1007, "a", 1, 7, false);
+        timeline.disconnect(30, 7);
+        // The table is sent again, with the entry of the disconnected player.
+        timeline.update(40, 1007, "a", 1, 7, false);
+        let connections = |timeline: &PlayerTimeline| -> Vec<_> {
+            timeline.players[0]
+                .connections
+                .iter()
+                .map(|c| (c.connect_tick, c.disconnect_tick))
+                .collect()
+        };
+        assert_eq!(connections(&timeline), vec![(10, Some(30))]);
+
+        timeline.connect(7);
+        timeline.update(50, 1007, "a", 1, 7, false);
+        timeline.update(60, 1007, "a", 1, 7, false);
+        assert_eq!(connections(&timeline), vec![(10, Some(30)), (50, None)]);
+    }
+}
@@ -0,0 +1,29 @@
+use cs2_demo::entity::{PropertyChanges, PropertyPattern, TreeEntity};
+use cs2_demo::{ParseOptions, Visitor};
+use std::io::Read;
+
+pub(crate) fn parse_cs2(
+    read: &mut dyn Read,
+    patterns: Vec<PropertyPattern>,
+) -> anyhow::Result<PropertyChanges> {
+    let mut collector = PropertyCollector::default();
+    let options = ParseOptions {
+        properties: patterns,
+        ..Default::default()
+    };
+    cs2_demo::parse_with_options(read, &mut collector, &TreeEntity::factory, options)?;
+    Ok(collector.changes)
+}
+
+/// Collects the property changes of the whole demo.
+#[derive(Default)]
+struct PropertyCollector {
+    changes: PropertyChanges,
+}
+
+impl Visitor for PropertyCollector {
+    fn visit_property_changes(&mut self, mut changes: PropertyChanges) -> anyhow::Result<()> {
+        self.changes.append(&mut changes);
+        Ok(())
+    }
+}
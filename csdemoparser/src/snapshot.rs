@@ -0,0 +1,417 @@
+use crate::decompress::ReadSeek;
+use crate::error::ResultExt;
+use crate::Tick;
+use anyhow::bail;
+use cs2_demo::entity::{Property, TreeEntity};
+use cs2_demo::{DemoIndex, DemoSeeker, Visitor};
+use csgo_demo::entity::{Entities, PropValue, Scalar, ServerClasses, TrackProp};
+use csgo_demo::string_table::{StringTable, StringTableEntry, StringTableUpdates, StringTables};
+use csgo_demo::{Message, PacketContent};
+use demo_context::Context;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// The output of csdemoparser in entities mode: the state of all the entities at a tick.
+#[derive(Serialize, Debug)]
+pub struct EntitySnapshot {
+    pub tick: Tick,
+    /// Ordered by id.
+    pub entities: Vec<EntityState>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct EntityState {
+    pub id: u32,
+    pub class: String,
+    /// Maps the name of every property set to its value. For CS2, the properties of nested
+    /// objects and arrays are named after their path, for example `m_pEntity.m_name` or
+    /// `m_vecX.0003`.
+    pub properties: serde_json::Map<String, Value>,
+}
+
+pub(crate) fn parse_csgo(read: &mut dyn ReadSeek, tick: Tick) -> anyhow::Result<EntitySnapshot> {
+    let mut parser = csgo_demo::DemoParser::try_new(read)?;
+    let mut baselines = InstanceBaselines::default();
+    // Entities are only sent after the DataTables, the instance baselines can be sent before.
+    let mut server_classes = loop {
+        let offset = parser.offset();
+        let Some((header, content)) = parser.parse_next_packet()? else {
+            bail!("no DataTables found in demo")
+        };
+        let context = Context::Command {
+            offset,
+            command: Some(header.command_name()),
+            tick: Some(*header.tick()),
+        };
+        match content {
+            PacketContent::DataTables(dt) => {
+                break ServerClasses::try_new(dt).in_context(context)?;
+            }
+            content => baselines.handle_content(&content).in_context(context)?,
+        }
+    };
+    for prop in server_classes
+        .server_classes
+        .iter_mut()
+        .flat_map(|sc| sc.props.iter_mut())
+    {
+        prop.track = TrackProp::Value;
+    }
+    let mut entities = Entities::new(&server_classes);
+    loop {
+        for (class_id, data) in baselines.changed.drain(..) {
+            entities.set_baseline(class_id, data);
+        }
+        let offset = parser.offset();
+        let Some((header, content)) = parser.parse_next_packet()? else {
+            break;
+        };
+        let cmd_tick = *header.tick();
+        if cmd_tick > tick {
+            break;
+        }
+        let context = Context::Command {
+            offset,
+            command: Some(header.command_name()),
+            tick: Some(cmd_tick),
+        };
+        baselines
+            .handle_content(&content)
+            .in_context(context.clone())?;
+        let PacketContent::Packet(messages) = content else {
+            continue;
+        };
+        for message in messages {
+            if let Message::PacketEntities(msg) = message {
+                entities
+                    .read_packet_entities(msg, cmd_tick)
+                    .in_context(Context::Message("PacketEntities".to_string()))
+                    .in_context(context.clone())?;
+            }
+        }
+    }
+    let entities = entities
+        .iter()
+        .map(|entity| EntityState {
+            id: entity.id as u32,
+            class: entity.class_name().to_string(),
+            properties: entity
+                .props()
+                .map(|(name, value)| (name.to_string(), prop_value_json(value)))
+                .collect(),
+        })
+        .collect();
+    Ok(EntitySnapshot { tick, entities })
+}
+
+/// Decodes the CS:GO instancebaseline string table, whose entries map server class ids to the
+/// props of their new entities.
+#[derive(Default)]
+struct InstanceBaselines {
+    string_tables: StringTables,
+    /// The class id of every entry, which updates do not repeat.
+    class_ids: HashMap<i32, u32>,
+    /// The baselines changed since they were last applied to the entities.
+    changed: Vec<(u32, Vec<u8>)>,
+}
+
+impl InstanceBaselines {
+    const TABLE: &'static str = "instancebaseline";
+
+    fn handle_content(&mut self, content: &PacketContent) -> anyhow::Result<()> {
+        match content {
+            PacketContent::Packet(messages) => {
+                for message in messages {
+                    let entries = match message {
+                        Message::CreateStringTable(table) => {
+                            Self::entries(self.string_tables.create_string_table(table))?
+                        }
+                        Message::UpdateStringTable(table) => {
+                            Self::entries(self.string_tables.update_string_table(table)?)?
+                        }
+                        _ => continue,
+                    };
+                    for entry in entries {
+                        self.set(entry.index, entry.string.as_deref(), entry.data)?;
+                    }
+                }
+            }
+            PacketContent::StringTables(tables) => self.handle_string_tables(tables)?,
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn entries(mut updates: StringTableUpdates) -> anyhow::Result<Vec<StringTableEntry>> {
+        let mut entries = Vec::new();
+        if updates.table_name() == Self::TABLE {
+            while let Some(entry) = updates.next_entry()? {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    fn handle_string_tables(&mut self, tables: &[StringTable]) -> anyhow::Result<()> {
+        for table in tables.iter().filter(|t| t.name() == Self::TABLE) {
+            for (index, strings) in table.strings().iter().enumerate() {
+                self.set(index as i32, Some(strings.name()), strings.data().clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, index: i32, key: Option<&str>, data: Option<Vec<u8>>) -> anyhow::Result<()> {
+        if let Some(key) = key {
+            let Ok(class_id) = key.parse() else {
+                bail!("invalid instance baseline class id {key:?}")
+            };
+            self.class_ids.insert(index, class_id);
+        }
+        if let (Some(&class_id), Some(data)) = (self.class_ids.get(&index), data) {
+            self.changed.push((class_id, data));
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn parse_cs2(read: &mut dyn ReadSeek, tick: Tick) -> anyhow::Result<EntitySnapshot> {
+    struct NoVisitor;
+    impl Visitor for NoVisitor {}
+
+    let index = DemoIndex::try_new(read)?;
+    let mut visitor = NoVisitor;
+    let mut seeker = DemoSeeker::try_new(read, index, &mut visitor, &TreeEntity::factory)?;
+    seeker.seek(tick)?;
+    let list = seeker.entities();
+    let entities = (0..list.len())
+        .filter_map(|id| Some((id, list.get(id)?)))
+        .map(|(id, entity)| EntityState {
+            id: id as u32,
+            class: entity.serializer().name().to_string(),
+            properties: entity
+                .properties()
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), property_json(value)))
+                .collect(),
+        })
+        .collect();
+    Ok(EntitySnapshot { tick, entities })
+}
+
+fn prop_value_json(value: &PropValue) -> Value {
+    fn scalar(value: &Scalar) -> Value {
+        match value {
+            Scalar::I32(v) => json!(v),
+            Scalar::F32(v) => json!(v),
+            Scalar::String(v) => json!(v),
+            Scalar::I64(v) => json!(v),
+            Scalar::Vector(v) => json!([v.x, v.y, v.z]),
+        }
+    }
+    match value {
+        PropValue::Scalar(v) => scalar(v),
+        PropValue::Array(v) => v.iter().map(scalar).collect(),
+    }
+}
+
+fn property_json(value: &Property) -> Value {
+    match value {
+        Property::Bool(v) => json!(v),
+        Property::I32(v) => json!(v),
+        Property::U32(v) => json!(v),
+        Property::U64(v) => json!(v),
+        Property::F32(v) => json!(v),
+        Property::Str(v) => json!(v),
+        Property::Vec2(v) => json!(v),
+        Property::Vec3(v) => json!(v),
+        Property::Vec4(v) => json!(v),
+        Property::Vec6(v) => json!(v),
+        // Entity::properties returns the content of objects and arrays instead.
+        Property::Object(_) | Property::Array(_) => Value::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{make_csgo_demo, make_data_tables};
+    use csgo_demo::entity::Vector;
+    use protobuf::Message as _;
+    use std::io::Cursor;
+
+    /// Packs bits, given as (value, count) in the order they are read, into bytes.
+    fn pack_bits(fields: &[(u32, u32)]) -> Vec<u8> {
+        let bits: Vec<_> = fields
+            .iter()
+            .flat_map(|&(value, count)| (0..count).map(move |i| value >> i & 1))
+            .collect();
+        let mut bytes = vec![0u8; bits.len().div_ceil(8)];
+        for (i, bit) in bits.iter().enumerate() {
+            bytes[i / 8] |= (*bit as u8) << (i % 8);
+        }
+        bytes
+    }
+
+    #[test]
+    fn csgo() -> anyhow::Result<()> {
+        use csgo_demo::proto::netmessages::{
+            CSVCMsg_CreateStringTable, CSVCMsg_PacketEntities, SVC_Messages,
+        };
+        const SIGNON: u8 = 1;
+        const PACKET: u8 = 2;
+        const DATA_TABLES: u8 = 6;
+        const STOP: u8 = 7;
+
+        // Returns a Packet command setting the health of entity 1, creating it if `new`.
+        let packet = |new: bool, health: u32| -> anyhow::Result<Vec<u8>> {
+            let mut packet_entities = CSVCMsg_PacketEntities::new();
+            packet_entities.set_updated_entries(1);
+            packet_entities.set_entity_data(pack_bits(&[
+                // Entity id delta and not removed, followed by the class id and serial number
+                // of new entities.
+                (1, 6),
+                if new { (0b10, 2) } else { (0b00, 2) },
+                (0, if new { 11 } else { 0 }),
+                // New way field index 0, followed by the end marker and the value.
+                (0b11, 2),
+                (0b00, 2),
+                (0x3fff, 14),
+                (health, 8),
+            ]));
+            packet_entities.set_is_delta(!new);
+            let mut messages = vec![SVC_Messages::svc_PacketEntities as u8];
+            messages.extend(packet_entities.write_length_delimited_to_bytes()?);
+            let mut packet = vec![0; 160];
+            packet.extend((messages.len() as u32).to_le_bytes());
+            packet.extend(messages);
+            Ok(packet)
+        };
+        // The instance baseline of class 0 sets its armor, the field index 1.
+        let baseline = pack_bits(&[
+            (1, 1),
+            (0, 1),
+            (1, 1),
+            (1, 3),
+            (0b00, 2),
+            (0x3fff, 14),
+            (42, 8),
+        ]);
+        let mut string_data = vec![(0, 1), (1, 1), (1, 1), (0, 1), (b'0' as u32, 8), (0, 8)];
+        string_data.extend([(1, 1), (baseline.len() as u32, 14)]);
+        string_data.extend(baseline.iter().map(|&b| (b as u32, 8)));
+        let mut baselines = CSVCMsg_CreateStringTable::new();
+        baselines.set_name("instancebaseline".to_string());
+        baselines.set_max_entries(1024);
+        baselines.set_num_entries(1);
+        baselines.set_string_data(pack_bits(&string_data));
+        let mut signon = vec![SVC_Messages::svc_CreateStringTable as u8];
+        signon.extend(baselines.write_length_delimited_to_bytes()?);
+        let mut signon_packet = vec![0; 160];
+        signon_packet.extend((signon.len() as u32).to_le_bytes());
+        signon_packet.extend(signon);
+
+        let demo = make_csgo_demo(&[
+            (SIGNON, 0, &signon_packet),
+            (
+                DATA_TABLES,
+                0,
+                &make_data_tables(&["m_iHealth", "m_iArmor"]),
+            ),
+            (PACKET, 1, &packet(true, 100)?),
+            (PACKET, 5, &packet(false, 50)?),
+            (STOP, 5, &[]),
+        ]);
+
+        let properties = |tick| -> anyhow::Result<Value> {
+            let snapshot = crate::snapshot(&mut Cursor::new(&demo), tick)?;
+            assert_eq!(snapshot.tick, tick);
+            let [entity] = &snapshot.entities[..] else {
+                panic!("unexpected entities {:?}", snapshot.entities);
+            };
+            assert_eq!((entity.id, entity.class.as_str()), (1, "CCSPlayer"));
+            Ok(Value::Object(entity.properties.clone()))
+        };
+        assert_eq!(properties(1)?, json!({"m_iHealth": 100, "m_iArmor": 42}));
+        assert_eq!(properties(4)?, json!({"m_iHealth": 100, "m_iArmor": 42}));
+        assert_eq!(properties(5)?, json!({"m_iHealth": 50, "m_iArmor": 42}));
+        Ok(())
+    }
+
+    #[test]
+    fn cs2() -> anyhow::Result<()> {
+        use cs2_demo::proto::demo::{
+            CDemoFullPacket, CDemoPacket, CDemoStringTables, EDemoCommands::*,
+        };
+        use cs2_demo::proto::netmessages::SVC_Messages;
+        // A packet with a single message: its type as a UBitVar, its size and its content.
+        let packet_entities = include_bytes!("../../cs2-demo/src/testdata/packetentities.binpb");
+        let msg_type = SVC_Messages::svc_PacketEntities as u32;
+        assert!((16..256).contains(&msg_type));
+        let mut fields = vec![(msg_type & 15 | 16, 6), (msg_type >> 4, 4)];
+        let mut size = packet_entities.len() as u32;
+        while size >= 0x80 {
+            fields.push((size & 0x7f | 0x80, 8));
+            size >>= 7;
+        }
+        fields.push((size, 8));
+        fields.extend(packet_entities.iter().map(|&b| (b as u32, 8)));
+        let mut packet = CDemoPacket::new();
+        packet.set_data(pack_bits(&fields));
+        let mut full_packet = CDemoFullPacket::new();
+        full_packet.string_table = Some(CDemoStringTables::parse_from_bytes(include_bytes!(
+            "../../cs2-demo/src/testdata/CDemoStringTables.binpb"
+        ))?)
+        .into();
+        full_packet.packet = Some(packet).into();
+
+        let mut writer = cs2_demo::DemoWriter::try_new(Cursor::new(Vec::new()))?;
+        writer.write_demo_command(
+            DEM_SendTables as u32,
+            0,
+            include_bytes!("../../cs2-demo/src/testdata/cdemosendtables.binpb"),
+        )?;
+        writer.write_demo_command(
+            DEM_ClassInfo as u32,
+            0,
+            include_bytes!("../../cs2-demo/src/testdata/cdemoclassinfo.binpb"),
+        )?;
+        writer.write_demo_command(DEM_SyncTick as u32, 0, &[])?;
+        writer.write_demo_command(DEM_FullPacket as u32, 10, &full_packet.write_to_bytes()?)?;
+        writer.write_demo_command(DEM_Stop as u32, 20, &[])?;
+        let demo = writer.finish()?.into_inner();
+
+        let snapshot = crate::snapshot(&mut Cursor::new(&demo), 10)?;
+        assert_eq!(snapshot.tick, 10);
+        let controller = snapshot
+            .entities
+            .iter()
+            .find(|e| e.class == "CCSPlayerController")
+            .unwrap();
+        assert!(controller.properties["m_iTeamNum"].is_number());
+        assert!(controller
+            .properties
+            .contains_key("m_pEntity.m_nameStringableIndex"));
+        Ok(())
+    }
+
+    #[test]
+    fn values() {
+        let origin = Scalar::Vector(Vector {
+            x: 1.0,
+            y: 2.5,
+            z: -3.0,
+        });
+        assert_eq!(
+            prop_value_json(&PropValue::Scalar(origin)),
+            json!([1.0, 2.5, -3.0])
+        );
+        let ammo = PropValue::Array(vec![Scalar::I32(30), Scalar::I32(0)]);
+        assert_eq!(prop_value_json(&ammo), json!([30, 0]));
+        assert_eq!(property_json(&Property::U64(u64::MAX)), json!(u64::MAX));
+        assert_eq!(property_json(&Property::Str("ak47".into())), json!("ak47"));
+        // JSON has no NaN.
+        assert_eq!(property_json(&Property::F32(f32::NAN)), Value::Null);
+    }
+}
@@ -1,6 +1,7 @@
 use getset::Getters;
 use protobuf::CodedInputStream;
 
+use crate::custom_data::CustomData;
 use crate::data_table::DataTables;
 use crate::error::{Error, Result};
 use crate::message::Message;
@@ -54,6 +55,11 @@ pub struct PacketHeader {
 }
 
 impl PacketHeader {
+    /// Returns the name of the command type, e.g. `Packet`.
+    pub fn command_name(&self) -> String {
+        format!("{:?}", self.command)
+    }
+
     pub(crate) fn try_new(reader: &mut CodedInputStream) -> Result<Self> {
         Ok(Self {
             command: reader.read_raw_byte()?.try_into()?,
@@ -87,6 +93,9 @@ pub enum PacketContent {
     ///
     /// [Valve Doc DT]: https://developer.valvesoftware.com/wiki/Networking_Entities#Network_Data_Tables
     DataTables(DataTables),
+    /// A custom data block. See [`CustomData`] documentation for more
+    /// information.
+    CustomData(CustomData),
 }
 
 #[cfg(test)]
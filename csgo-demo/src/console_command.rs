@@ -1,7 +1,7 @@
 use protobuf::CodedInputStream;
 
-use crate::Result;
 use crate::read::ReadExt;
+use crate::Result;
 
 #[derive(Debug)]
 pub(crate) struct ConsoleCommand {
@@ -0,0 +1,41 @@
+use protobuf::CodedInputStream;
+
+use crate::error::Result;
+
+/// Custom data block.
+///
+/// Custom data is recorded by the game or by server plugins and is handled by
+/// the callback registered at `callback_index` when the demo is played back.
+/// Its content is opaque to the parser.
+#[derive(Debug)]
+pub struct CustomData {
+    pub callback_index: i32,
+    pub data: Vec<u8>,
+}
+
+impl CustomData {
+    pub(crate) fn try_new(reader: &mut CodedInputStream) -> Result<Self> {
+        let callback_index = reader.read_sfixed32()?;
+        let size = reader.read_fixed32()?;
+
+        Ok(Self {
+            callback_index,
+            data: reader.read_raw_bytes(size)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_data() {
+        let mut bytes: &[u8] = &[1, 0, 0, 0, 3, 0, 0, 0, 7, 8, 9];
+        let mut reader = CodedInputStream::new(&mut bytes);
+        let custom_data = CustomData::try_new(&mut reader).unwrap();
+        assert_eq!(custom_data.callback_index, 1);
+        assert_eq!(custom_data.data, vec![7, 8, 9]);
+        assert!(reader.eof().unwrap());
+    }
+}
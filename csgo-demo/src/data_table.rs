@@ -27,7 +27,7 @@ impl DataTables {
         trace!("reading send tables");
         let mut send_tables: Vec<CSVCMsg_SendTable> = Vec::new();
         loop {
-            let message = Message::try_new(reader)?;
+            let message = Message::try_new(reader, &[])?;
             trace!(?message);
             match message {
                 Message::SendTable(send_table) => {
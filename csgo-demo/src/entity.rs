@@ -2,7 +2,9 @@
 mod serverclass;
 
 use bitstream_io::BitRead;
+use demo_context::InContext;
 use serverclass::ServerClass;
+use std::collections::HashMap;
 use std::io;
 use std::rc::Rc;
 
@@ -30,6 +32,8 @@ pub type EntityId = u16;
 pub struct Entities<'a> {
     server_classes: &'a ServerClasses,
     entities: Vec<Option<Entity<'a>>>,
+    /// Maps server class ids to the props of their new entities.
+    baselines: HashMap<u32, Vec<u8>>,
     /// Only used by read_props to avoid allocations.
     field_indices: Vec<i32>,
 }
@@ -39,14 +43,27 @@ impl<'a> Entities<'a> {
         Self {
             server_classes,
             entities: (0..MAX_ENTITIES).map(|_| None).collect(),
+            baselines: HashMap::new(),
             field_indices: Vec::with_capacity(512),
         }
     }
 
-    pub fn get(&self, id: EntityId) -> Option<&Entity> {
+    pub fn get(&self, id: EntityId) -> Option<&Entity<'_>> {
         self.entities.get(id as usize)?.as_ref()
     }
 
+    /// Returns the existing entities, ordered by id.
+    pub fn iter(&self) -> impl Iterator<Item = &Entity<'_>> {
+        self.entities.iter().flatten()
+    }
+
+    /// Sets the instance baseline of a server class, from the instancebaseline string table:
+    /// the props its new entities have before the ones sent with them, encoded like in
+    /// PacketEntities.
+    pub fn set_baseline(&mut self, class_id: u32, data: Vec<u8>) {
+        self.baselines.insert(class_id, data);
+    }
+
     pub fn read_packet_entities(&mut self, msg: CSVCMsg_PacketEntities, tick: Tick) -> Result<()> {
         let mut next_entity_id = 0;
         let mut reader = BitReader::new(msg.entity_data());
@@ -60,11 +77,12 @@ impl<'a> Entities<'a> {
             let new = reader.read_bit()?;
             match (remove, new) {
                 (false, false) => {
-                    if let Some(entity) = self.entities[entity_id as usize].as_mut() {
-                        entity.read_props(&mut reader, &mut self.field_indices, tick)?;
-                    } else {
-                        return Err(Error::Entity("entity id not found"));
-                    }
+                    let Some(entity) = self.entities[entity_id as usize].as_mut() else {
+                        return Err(Error::Entity("entity id not found").in_entity(entity_id, None));
+                    };
+                    entity
+                        .read_props(&mut reader, &mut self.field_indices, tick)
+                        .map_err(|e| e.in_entity(entity_id, Some(&entity.class.name)))?;
                 }
                 (false, true) => {
                     let class_id = reader.read::<u32>(self.server_classes.bits)?;
@@ -72,11 +90,24 @@ impl<'a> Entities<'a> {
                         .server_classes
                         .server_classes
                         .get(class_id as usize)
-                        .ok_or_else(|| Error::Entity("class id not found"))?;
+                        .ok_or_else(|| {
+                            Error::Entity("class id not found").in_entity(entity_id, None)
+                        })?;
                     // Discard serial_num.
                     reader.read::<u32>(10)?;
                     let mut entity = Entity::new(entity_id as EntityId, class);
-                    entity.read_props(&mut reader, &mut self.field_indices, tick)?;
+                    if let Some(baseline) = self.baselines.get(&class_id) {
+                        entity
+                            .read_props(
+                                &mut BitReader::new(baseline),
+                                &mut self.field_indices,
+                                tick,
+                            )
+                            .map_err(|e| e.in_entity(entity_id, Some(&class.name)))?;
+                    }
+                    entity
+                        .read_props(&mut reader, &mut self.field_indices, tick)
+                        .map_err(|e| e.in_entity(entity_id, Some(&class.name)))?;
                     self.entities[entity_id as usize] = Some(entity);
                 }
                 (true, _) => {
@@ -128,16 +159,25 @@ pub struct Entity<'a> {
 impl std::fmt::Display for Entity<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "{}", self.class.name)?;
-        for (d, p) in std::iter::zip(self.class.props.iter(), self.props.iter()) {
-            if let Some(p) = p {
-                writeln!(f, "  {}={:?}", d.name, p)?
-            }
+        for (name, p) in self.props() {
+            writeln!(f, "  {name}={p:?}")?
         }
         std::fmt::Result::Ok(())
     }
 }
 
 impl Entity<'_> {
+    pub fn class_name(&self) -> &str {
+        &self.class.name
+    }
+
+    /// Returns the name and value of every prop set. Only the props tracked with
+    /// [`TrackProp::Value`] or [`TrackProp::Changes`] are set.
+    pub fn props(&self) -> impl Iterator<Item = (&str, &PropValue)> {
+        let props = std::iter::zip(self.class.props.iter(), self.props.iter());
+        props.filter_map(|(d, p)| Some((d.name.as_str(), p.as_ref()?)))
+    }
+
     pub fn get_prop(&self, prop_name: &str) -> Option<&PropValue> {
         let idx = self
             .class
@@ -147,7 +187,7 @@ impl Entity<'_> {
         self.props.get(idx)?.as_ref()
     }
 
-    fn new(id: EntityId, class: &ServerClass) -> Entity {
+    fn new(id: EntityId, class: &ServerClass) -> Entity<'_> {
         let props = class.props.iter().map(|_| None).collect();
         Entity { id, class, props }
     }
@@ -166,7 +206,9 @@ impl Entity<'_> {
             index = val;
             field_indices.push(index);
             if field_indices.len() > 20000 {
-                return Err(Error::Entity("found too many entity field indices, probably corrupt demo"))
+                return Err(Error::Entity(
+                    "found too many entity field indices, probably corrupt demo",
+                ));
             }
         }
         for i in field_indices {
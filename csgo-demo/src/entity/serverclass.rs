@@ -7,7 +7,6 @@ use crate::{num_bits, DataTables, Error, Result};
 use bitstream_io::BitRead;
 use std::collections::HashMap;
 use std::io;
-use std::string::FromUtf8Error;
 
 use super::TrackProp;
 
@@ -178,7 +177,7 @@ impl PropDescriptor {
                 Ok(Scalar::Vector(self.decode_vector_xy(f, reader)?))
             }
             ScalarPropDescriptor::String => Ok(Scalar::String(self.decode_string(reader)?)),
-            ScalarPropDescriptor::Int64 => todo!(),
+            ScalarPropDescriptor::Int64(int) => Ok(Scalar::I64(self.decode_int64(int, reader)?)),
         }
     }
 
@@ -193,7 +192,7 @@ impl PropDescriptor {
             ScalarPropDescriptor::Vector(f, unit) => Ok(self.skip_vector(f, *unit, reader)?),
             ScalarPropDescriptor::VectorXY(f) => Ok(self.skip_vector_xy(f, reader)?),
             ScalarPropDescriptor::String => self.skip_string(reader),
-            ScalarPropDescriptor::Int64 => todo!(),
+            ScalarPropDescriptor::Int64(int) => self.skip_int64(int, reader),
         }
     }
 
@@ -211,7 +210,11 @@ impl PropDescriptor {
         Ok(array)
     }
 
-    fn skip_array(&self, type_: &ArrayPropDescriptor, reader: &mut BitReader) -> std::io::Result<()> {
+    fn skip_array(
+        &self,
+        type_: &ArrayPropDescriptor,
+        reader: &mut BitReader,
+    ) -> std::io::Result<()> {
         let len_bits = num_bits(type_.num_elements);
         let len = reader.read::<u32>(len_bits)?;
         for _ in 0..len {
@@ -248,6 +251,32 @@ impl PropDescriptor {
         }
     }
 
+    fn decode_int64(&self, desc: &IntPropDescriptor, reader: &mut BitReader) -> io::Result<i64> {
+        if desc.varint {
+            return if desc.unsigned {
+                Ok(reader.read_varint64()? as i64)
+            } else {
+                reader.read_signed_varint64()
+            };
+        }
+        // The value is sent as sign bit (only for signed props), low 32 bits, high bits.
+        let negative = !desc.unsigned && reader.read_bit()?;
+        let high_bits = desc.num_bits.saturating_sub(32 + !desc.unsigned as u32);
+        let low = reader.read::<u32>(32)? as i64;
+        let high = reader.read::<u32>(high_bits)? as i64;
+        let val = (high << 32) | low;
+        Ok(if negative { -val } else { val })
+    }
+
+    fn skip_int64(&self, desc: &IntPropDescriptor, reader: &mut BitReader) -> io::Result<()> {
+        if desc.varint {
+            reader.read_varint64()?;
+            Ok(())
+        } else {
+            reader.skip(desc.num_bits.max(32 + !desc.unsigned as u32))
+        }
+    }
+
     fn decode_float(&self, f: &FloatPropDescriptor, reader: &mut BitReader) -> io::Result<f32> {
         let val = match f.special {
             FloatEncoding::Scaled => {
@@ -364,14 +393,6 @@ impl PropDescriptor {
     }
 }
 
-#[derive(thiserror::Error, Debug)]
-pub enum DecodeError {
-    #[error(transparent)]
-    Io(#[from] io::Error),
-    #[error(transparent)]
-    Protobuf(#[from] FromUtf8Error),
-}
-
 #[derive(Debug)]
 enum ScalarPropDescriptor {
     Int(IntPropDescriptor),
@@ -379,7 +400,7 @@ enum ScalarPropDescriptor {
     Vector(FloatPropDescriptor, bool),
     VectorXY(FloatPropDescriptor),
     String,
-    Int64,
+    Int64(IntPropDescriptor),
 }
 
 impl ScalarPropDescriptor {
@@ -393,7 +414,7 @@ impl ScalarPropDescriptor {
             ),
             DPT_VECTOR_XY => Self::VectorXY(FloatPropDescriptor::from(sendprop)),
             DPT_STRING => Self::String,
-            DPT_INT64 => Self::Int64,
+            DPT_INT64 => Self::Int64(IntPropDescriptor::from(sendprop)),
             _ => Err(Error::ServerClass(
                 "invalid scalar sendprop type".to_string(),
             ))?,
@@ -609,9 +630,73 @@ const DPT_INT64: i32 = 7;
 
 #[cfg(test)]
 mod tests {
-    use super::{FloatEncoding, ServerClassesParser};
+    use super::*;
+    use bitstream_io::{BitWrite, BitWriter, LittleEndian};
     use protobuf::text_format::parse_from_str;
 
+    fn int64_prop(flags: i32, num_bits: i32) -> PropDescriptor {
+        let sendprop = parse_from_str(&format!(
+            "type: 7 var_name: \"m_int64\" flags: {flags} num_bits: {num_bits}"
+        ))
+        .unwrap();
+        let type_ = ScalarPropDescriptor::try_new(&sendprop).unwrap();
+        PropDescriptor::new("m_int64", PropDescriptorType::Scalar(type_))
+    }
+
+    /// Decodes `data` followed by a 0xAB marker byte.
+    fn decode_int64(prop: &PropDescriptor, data: &[u8]) -> i64 {
+        let data = [data, &[0xAB]].concat();
+        let mut reader = BitReader::new(&data);
+        let value = prop.decode(&mut reader).unwrap();
+        assert_eq!(reader.read::<u8>(8).unwrap(), 0xAB);
+        let mut reader = BitReader::new(&data);
+        prop.skip(&mut reader).unwrap();
+        assert_eq!(reader.read::<u8>(8).unwrap(), 0xAB);
+        match value {
+            PropValue::Scalar(Scalar::I64(v)) => v,
+            v => panic!("unexpected value {v:?}"),
+        }
+    }
+
+    #[test]
+    fn int64_fixed() {
+        let mut writer = BitWriter::endian(Vec::new(), LittleEndian);
+        writer.write(32, 0xDEADBEEFu32).unwrap();
+        writer.write(32, 0x12345678u32).unwrap();
+        writer.byte_align().unwrap();
+        let unsigned = int64_prop(SPROP_UNSIGNED, 64);
+        assert_eq!(
+            decode_int64(&unsigned, &writer.into_writer()),
+            0x12345678DEADBEEF
+        );
+
+        let mut writer = BitWriter::endian(Vec::new(), LittleEndian);
+        writer.write_bit(true).unwrap();
+        writer.write(32, 5u32).unwrap();
+        writer.write(31, 1u32).unwrap();
+        writer.byte_align().unwrap();
+        let signed = int64_prop(0, 64);
+        assert_eq!(
+            decode_int64(&signed, &writer.into_writer()),
+            -((1 << 32) + 5)
+        );
+    }
+
+    #[test]
+    fn int64_varint() {
+        let unsigned = int64_prop(SPROP_UNSIGNED | SPROP_VARINT, 64);
+        assert_eq!(
+            decode_int64(&unsigned, &[0x80, 0x80, 0x80, 0x80, 0x10]),
+            1 << 32
+        );
+
+        let signed = int64_prop(SPROP_VARINT, 64);
+        assert_eq!(
+            decode_int64(&signed, &[0xFF, 0xFF, 0xFF, 0xFF, 0x1F]),
+            -(1 << 32)
+        );
+    }
+
     #[test]
     fn float_encoding() {
         // Coord* special types have higher priority than NoScale. Some demo files set both flags.
@@ -1,3 +1,4 @@
+use demo_context::{Context, InContext};
 use std::io;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -47,4 +48,20 @@ pub enum Error {
     Entity(&'static str),
     #[error("ServerClass error: {0}")]
     ServerClass(String),
+    /// Adds the part of the demo being parsed to `source`.
+    #[error("{context}")]
+    InContext {
+        context: Context,
+        #[source]
+        source: Box<Error>,
+    },
+}
+
+impl InContext for Error {
+    fn in_context(self, context: Context) -> Self {
+        Error::InContext {
+            context,
+            source: Box::new(self),
+        }
+    }
 }
@@ -4,7 +4,7 @@ use protobuf::CodedInputStream;
 use crate::error::{HeaderParsingError, Result};
 use crate::read::ReadExt;
 
-const MAX_OS_PATH: usize = 260;
+pub(crate) const MAX_OS_PATH: usize = 260;
 
 /// Expected demo type.
 const EXPECTED_DEMO_TYPE: &[u8; 8] = b"HL2DEMO\0";
@@ -44,7 +44,8 @@ impl DemoHeader {
     pub(crate) fn try_new(reader: &mut CodedInputStream) -> Result<Self> {
         let mut demo_type = [std::mem::MaybeUninit::<u8>::uninit(); 8];
         reader.read_exact(&mut demo_type)?;
-        let demo_type = unsafe { std::mem::transmute::<_, [u8; 8]>(demo_type) };
+        let demo_type =
+            unsafe { std::mem::transmute::<[std::mem::MaybeUninit<u8>; 8], [u8; 8]>(demo_type) };
         if &demo_type != EXPECTED_DEMO_TYPE {
             Err(HeaderParsingError::InvalidDemoType(Box::new(demo_type)))?
         }
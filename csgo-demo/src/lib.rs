@@ -1,32 +1,39 @@
 mod command;
 mod console_command;
+mod custom_data;
 mod data_table;
 pub mod entity;
 mod error;
 mod header;
 mod message;
 mod packet;
+#[allow(renamed_and_removed_lints)]
 pub mod proto;
 mod read;
 mod read_to_terminator;
+mod rewrite;
 pub mod string_table;
 mod user_command;
 
 use crate::command::{Command, PacketHeader};
 use crate::console_command::ConsoleCommand;
 use crate::packet::Packet;
+use crate::proto::netmessages::SVC_Messages;
 use crate::string_table::parse_string_tables;
 use crate::user_command::UserCommandCompressed;
+use demo_context::InContext;
 use getset::Getters;
 use protobuf::CodedInputStream;
 use std::io;
 use tracing::trace;
 
 pub use command::PacketContent;
+pub use custom_data::CustomData;
 pub use data_table::DataTables;
-pub use error::{Error, Result};
+pub use error::{DataTablesParsingError, Error, HeaderParsingError, Result};
 pub use header::DemoHeader;
 pub use message::Message;
+pub use rewrite::{rewrite, Rewriter};
 
 pub type Tick = i32;
 
@@ -38,6 +45,12 @@ pub struct DemoParser<'a> {
     #[getset(skip)]
     reader: CodedInputStream<'a>,
     header: DemoHeader,
+    #[getset(skip)]
+    start_tick: Tick,
+    #[getset(skip)]
+    end_tick: Tick,
+    #[getset(skip)]
+    decode_entities: bool,
 }
 
 impl<'a> DemoParser<'a> {
@@ -46,48 +59,119 @@ impl<'a> DemoParser<'a> {
         let header = DemoHeader::try_new(&mut reader)?;
         trace!(?header);
 
-        Ok(Self { header, reader })
+        Ok(Self {
+            header,
+            reader,
+            start_tick: Tick::MIN,
+            end_tick: Tick::MAX,
+            decode_entities: true,
+        })
+    }
+
+    /// Game events emitted before `start_tick` are not decoded and are returned as
+    /// [`Message::Unknown`]. Everything else is still decoded because it is needed to build the
+    /// state at `start_tick`: CS:GO demos have no full snapshots of the entities, so they are
+    /// decoded from the start of the demo.
+    pub fn set_start_tick(&mut self, start_tick: Tick) {
+        self.start_tick = start_tick;
+    }
+
+    /// Stops the parsing at the first command emitted after `end_tick`.
+    pub fn set_end_tick(&mut self, end_tick: Tick) {
+        self.end_tick = end_tick;
+    }
+
+    /// When `decode` is false, PacketEntities messages are not decoded and are returned as
+    /// [`Message::Unknown`]. This makes parsing much faster when entities are not needed.
+    pub fn set_decode_entities(&mut self, decode: bool) {
+        self.decode_entities = decode;
+    }
+
+    /// Returns the offset in the demo file of the next command.
+    pub fn offset(&self) -> u64 {
+        self.reader.pos()
     }
 
     pub fn parse_next_packet(&mut self) -> Result<Option<(PacketHeader, PacketContent)>> {
-        if self.reader.eof()? {
+        let offset = self.offset();
+        let header = match self.read_packet_header() {
+            Ok(Some(header)) => header,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(e.in_command(offset, None, None)),
+        };
+        trace!(?header);
+        let in_command =
+            |e: Error| e.in_command(offset, Some(header.command_name()), Some(*header.tick()));
+        if *header.tick() > self.end_tick {
+            // Leave the stream at the start of the next command.
+            self.skip_packet_content(&header).map_err(in_command)?;
             return Ok(None);
         }
+        let content = self.read_packet_content(&header).map_err(in_command)?;
+        Ok(Some((header, content)))
+    }
 
-        let header = PacketHeader::try_new(&mut self.reader)?;
-        trace!(?header);
+    fn read_packet_header(&mut self) -> Result<Option<PacketHeader>> {
+        if self.reader.eof()? {
+            return Ok(None);
+        }
+        Ok(Some(PacketHeader::try_new(&mut self.reader)?))
+    }
 
+    fn read_packet_content(&mut self, header: &PacketHeader) -> Result<PacketContent> {
         Ok(match header.command {
-            Command::Stop => Some((header, PacketContent::Stop)),
-            Command::SyncTick => Some((header, PacketContent::SyncTick)),
+            Command::Stop => PacketContent::Stop,
+            Command::SyncTick => PacketContent::SyncTick,
             Command::ConsoleCommand => {
                 let console_command = ConsoleCommand::try_new(&mut self.reader)?;
                 trace!(?console_command);
-                Some((
-                    header,
-                    PacketContent::ConsoleCommand(console_command.command),
-                ))
+                PacketContent::ConsoleCommand(console_command.command)
             }
             Command::UserCommand => {
                 let user_command = UserCommandCompressed::try_new(&mut self.reader)?;
                 trace!(?user_command);
-                Some((header, PacketContent::UserCommand(user_command)))
+                PacketContent::UserCommand(user_command)
             }
             Command::Packet | Command::Signon => {
-                let packet = Packet::try_new(&mut self.reader)?;
-                Some((header, PacketContent::Packet(packet.messages)))
+                let mut skipped_messages = Vec::new();
+                if !self.decode_entities {
+                    skipped_messages.push(SVC_Messages::svc_PacketEntities as i32);
+                }
+                if *header.tick() < self.start_tick {
+                    skipped_messages.push(SVC_Messages::svc_GameEvent as i32);
+                }
+                let packet = Packet::try_new(&mut self.reader, &skipped_messages)?;
+                PacketContent::Packet(packet.messages)
             }
             Command::StringTables => {
-                let string_tables = parse_string_tables(&mut self.reader)?;
-                Some((header, PacketContent::StringTables(string_tables)))
+                PacketContent::StringTables(parse_string_tables(&mut self.reader)?)
             }
             Command::DataTables => {
-                let data_tables = DataTables::try_new(&mut self.reader)?;
-                Some((header, PacketContent::DataTables(data_tables)))
+                PacketContent::DataTables(DataTables::try_new(&mut self.reader)?)
+            }
+            Command::CustomData => {
+                let custom_data = CustomData::try_new(&mut self.reader)?;
+                trace!(?custom_data);
+                PacketContent::CustomData(custom_data)
             }
-            Command::CustomData => unimplemented!("custom data found"),
         })
     }
+
+    fn skip_packet_content(&mut self, header: &PacketHeader) -> Result<()> {
+        match header.command {
+            Command::Stop | Command::SyncTick => return Ok(()),
+            Command::ConsoleCommand | Command::DataTables | Command::StringTables => (),
+            // Sequence number.
+            Command::UserCommand => self.reader.skip_raw_bytes(4)?,
+            // Callback index.
+            Command::CustomData => self.reader.skip_raw_bytes(4)?,
+            // CommandInfo and sequence numbers, see Packet::try_new.
+            Command::Packet | Command::Signon => self.reader.skip_raw_bytes(152 + 4 + 4)?,
+        }
+        let size = self.reader.read_fixed32()?;
+        self.reader.skip_raw_bytes(size)?;
+        Ok(())
+    }
 }
 
 // Number of  bits needed to represent values in the 0..=n interval.
@@ -98,3 +182,73 @@ fn num_bits(n: u32) -> u32 {
         u32::BITS - n.leading_zeros()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(crate) fn make_demo(commands: &[(u8, Tick, &[u8])]) -> Vec<u8> {
+        let string = |s: &str| {
+            let mut buf = s.as_bytes().to_vec();
+            buf.resize(260, 0);
+            buf
+        };
+        let mut demo = b"HL2DEMO\0".to_vec();
+        demo.extend(4u32.to_le_bytes());
+        demo.extend(13881u32.to_le_bytes());
+        demo.extend(string("server"));
+        demo.extend(string("GOTV Demo"));
+        demo.extend(string("de_dust2"));
+        demo.extend(string("csgo"));
+        demo.extend(0f32.to_le_bytes());
+        demo.extend([0; 12]);
+        for (command, tick, data) in commands {
+            demo.push(*command);
+            demo.extend(tick.to_le_bytes());
+            demo.push(0);
+            demo.extend(data.iter());
+        }
+        demo
+    }
+
+    #[test]
+    fn tick_range() -> Result<()> {
+        const PACKET: u8 = 2;
+        const SYNC_TICK: u8 = 3;
+        const CONSOLE_COMMAND: u8 = 4;
+        let packet_entities = SVC_Messages::svc_PacketEntities as i32;
+        let game_event = SVC_Messages::svc_GameEvent as i32;
+        let mut packet = vec![0; 160];
+        let message = [packet_entities as u8, 0, game_event as u8, 0];
+        packet.extend((message.len() as u32).to_le_bytes());
+        packet.extend(message);
+        let console_command = [5, 0, 0, 0, b'q', b'u', b'i', b't', 0];
+        let demo = make_demo(&[
+            (PACKET, 1, &packet),
+            (PACKET, 2, &packet),
+            (CONSOLE_COMMAND, 5, &console_command),
+            (SYNC_TICK, 6, &[]),
+        ]);
+
+        let mut read = demo.as_slice();
+        let mut parser = DemoParser::try_new(&mut read)?;
+        parser.set_start_tick(2);
+        parser.set_end_tick(3);
+        parser.set_decode_entities(false);
+        let mut message_types = Vec::new();
+        while let Some((_, content)) = parser.parse_next_packet()? {
+            let PacketContent::Packet(messages) = content else {
+                panic!("unexpected {content:?}");
+            };
+            message_types.push(messages.iter().map(|m| m.name()).collect::<Vec<_>>());
+        }
+        assert_eq!(
+            message_types,
+            [["Unknown", "Unknown"], ["Unknown", "GameEvent"]]
+        );
+        // The command after `end_tick` was skipped as a whole.
+        assert!(parser.parse_next_packet()?.is_none());
+        assert_eq!(parser.offset(), demo.len() as u64);
+        Ok(())
+    }
+}
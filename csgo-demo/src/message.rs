@@ -1,6 +1,7 @@
 use crate::proto::cstrike15_usermessages::*;
 use crate::proto::netmessages::*;
 use crate::Result;
+use demo_context::InContext;
 use paste::paste;
 use protobuf::Message as ProtoMessage;
 use protobuf::{CodedInputStream, Enum};
@@ -31,8 +32,29 @@ macro_rules! create_message_impl {
         }
 
         impl Message {
-            pub(crate) fn try_new(reader: &mut CodedInputStream) -> Result<Message> {
+            /// Reads the next message. Messages whose type is in `skip` are not decoded and are
+            /// returned as [`Message::Unknown`].
+            pub(crate) fn try_new(reader: &mut CodedInputStream, skip: &[i32]) -> Result<Message> {
                 let msg_type = reader.read_int32()?;
+                Self::read(reader, skip, msg_type)
+                    .map_err(|e| e.in_message(Self::type_name(msg_type)))
+            }
+
+            /// Returns the name of the message variant, e.g. `PacketEntities`.
+            pub fn name(&self) -> &'static str {
+                match self {
+                    Message::Unknown(_) => "Unknown",
+                    $($(Message::$name(_) => stringify!($name),)*)*
+                    $(Message::$usr_msg(_) => stringify!($usr_msg),)*
+                }
+            }
+
+            fn read(reader: &mut CodedInputStream, skip: &[i32], msg_type: i32) -> Result<Message> {
+                if skip.contains(&msg_type) {
+                    let size = reader.read_raw_varint64()?;
+                    reader.skip_raw_bytes(size as u32)?;
+                    return Ok(Message::Unknown(msg_type));
+                }
 
                 // Handle "User message" before anything else
                 //
@@ -74,6 +96,16 @@ macro_rules! create_message_impl {
             }
         }
 
+        impl Message {
+            /// Returns the name of a message type, as in the proto enums.
+            fn type_name(t: i32) -> String {
+                match SVC_Messages::from_i32(t) {
+                    Some(t) => format!("{t:?}"),
+                    None => t.to_string(),
+                }
+            }
+        }
+
         impl fmt::Debug for Message {
             fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
                 match self {
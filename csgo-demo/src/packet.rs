@@ -11,7 +11,7 @@ pub(crate) struct Packet {
 
 impl Packet {
     #[instrument(level = "trace", skip(reader))]
-    pub(crate) fn try_new(reader: &mut CodedInputStream) -> Result<Self> {
+    pub(crate) fn try_new(reader: &mut CodedInputStream, skip: &[i32]) -> Result<Self> {
         // Skip command header, it contains —supposedly— no useful information
         // CommandInfo (152 bytes), SeqNrIn (4 bytes) and SeqNrOut (4 bytes)
         reader.skip_raw_bytes(152 + 4 + 4)?;
@@ -20,7 +20,7 @@ impl Packet {
         let end_position = reader.read_fixed32()? as u64 + reader.pos();
 
         while reader.pos() < end_position {
-            let message = Message::try_new(reader)?;
+            let message = Message::try_new(reader, skip)?;
             trace!(?message);
             messages.push(message);
         }
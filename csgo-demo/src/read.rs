@@ -51,6 +51,8 @@ pub trait ValveBitReader {
     fn read_ubitvar(&mut self) -> io::Result<u32>;
     fn read_varint32(&mut self) -> io::Result<u32>;
     fn read_signed_varint32(&mut self) -> io::Result<i32>;
+    fn read_varint64(&mut self) -> io::Result<u64>;
+    fn read_signed_varint64(&mut self) -> io::Result<i64>;
 
     fn read_coord(&mut self) -> io::Result<f32>;
     fn skip_coord(&mut self) -> io::Result<()>;
@@ -106,6 +108,22 @@ impl<R: io::Read> ValveBitReader for bitstream_io::BitReader<R, bitstream_io::Li
         Ok(zigzag_decode(self.read_varint32()?))
     }
 
+    fn read_varint64(&mut self) -> io::Result<u64> {
+        let mut result = 0;
+        for byte in 0..10 {
+            let b = self.read::<u8>(8)?;
+            result |= ((b & 0x7F) as u64) << (byte * 7);
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+        Ok(result)
+    }
+
+    fn read_signed_varint64(&mut self) -> io::Result<i64> {
+        Ok(zigzag_decode64(self.read_varint64()?))
+    }
+
     fn read_coord(&mut self) -> io::Result<f32> {
         let int = self.read_bit()?;
         let fract = self.read_bit()?;
@@ -216,6 +234,10 @@ fn zigzag_decode(n: u32) -> i32 {
     ((n >> 1) as i32) ^ -((n & 1) as i32)
 }
 
+fn zigzag_decode64(n: u64) -> i64 {
+    ((n >> 1) as i64) ^ -((n & 1) as i64)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -233,6 +255,21 @@ mod tests {
         assert_eq!(read.read_varint32().unwrap(), 4294967295);
     }
 
+    #[test]
+    fn varint64() {
+        let mut read = BitReader::new(&[0x81, 0x23]);
+        assert_eq!(read.read_varint64().unwrap(), 4481);
+
+        let mut read = BitReader::new(&[0x80, 0x80, 0x80, 0x80, 0x10]);
+        assert_eq!(read.read_varint64().unwrap(), 1 << 32);
+
+        let mut read = BitReader::new(&[0xFF; 10]);
+        assert_eq!(read.read_varint64().unwrap(), u64::MAX);
+
+        let mut read = BitReader::new(&[0x03]);
+        assert_eq!(read.read_signed_varint64().unwrap(), -2);
+    }
+
     #[test]
     fn zigzag() {
         let cases: [(u32, i32); 7] = [
@@ -252,4 +289,22 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn zigzag64() {
+        let cases: [(u64, i64); 5] = [
+            (0, 0),
+            (1, -1),
+            (2, 1),
+            (u64::MAX - 1, i64::MAX),
+            (u64::MAX, i64::MIN),
+        ];
+        for (encoded, decoded) in cases {
+            assert_eq!(
+                decoded,
+                zigzag_decode64(encoded),
+                "zigzag_decode64({encoded}) should be {decoded}"
+            );
+        }
+    }
 }
@@ -43,8 +43,7 @@ impl<RB: ReadByte> ReadToTerminator for RB {
             bytes.push(c);
         }
 
-        Err(io::Error::new(
-            io::ErrorKind::Other,
+        Err(io::Error::other(
             "limit has been reached without finding a null terminator",
         ))
     }
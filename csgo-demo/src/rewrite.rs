@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+use protobuf::{CodedInputStream, CodedOutputStream, Enum, Message};
+
+use crate::command::Command;
+use crate::header::{DemoHeader, MAX_OS_PATH};
+use crate::proto::cstrike15_usermessages::{
+    CUSRMsg_SayText2, CUSRMsg_ServerRankUpdate, USR_Messages,
+};
+use crate::proto::netmessages::{
+    csvcmsg_game_event_list, CSVCMsg_CreateStringTable, CSVCMsg_GameEvent, CSVCMsg_GameEventList,
+    CSVCMsg_ServerInfo, CSVCMsg_UpdateStringTable, CSVCMsg_UserMessage, SVC_Messages,
+};
+use crate::string_table::{
+    parse_player_info, parse_string_tables_data, write_player_info, PlayerInfo, StringTables,
+};
+use crate::Result;
+
+/// Size of the demo header, in bytes.
+const HEADER_SIZE: usize = 8 + 4 + 4 + 4 * MAX_OS_PATH + 4 + 4 + 4 + 4;
+/// Offset of the server name in the demo header.
+const SERVER_NAME_OFFSET: usize = 8 + 4 + 4;
+/// Size of the command info at the start of Signon and Packet commands.
+const COMMAND_INFO_SIZE: usize = 152 + 4 + 4;
+
+/// Hooks called by [`rewrite`] on the parts of a demo which can be modified in place.
+pub trait Rewriter {
+    /// Called with the server name found in the demo header. It is truncated to 259 bytes.
+    fn rewrite_server_name(&mut self, _server_name: &mut String) {}
+    fn rewrite_server_info(&mut self, _server_info: &mut CSVCMsg_ServerInfo) {}
+    /// Called for each entry of the userinfo string table. Only the fields up to `is_hltv` are
+    /// written back, and strings are truncated to the size of their player_info_t field.
+    fn rewrite_player_info(&mut self, _player_info: &mut PlayerInfo) {}
+    fn rewrite_game_event(
+        &mut self,
+        _event: &mut CSVCMsg_GameEvent,
+        _descriptor: &csvcmsg_game_event_list::Descriptor_t,
+    ) {
+    }
+    /// Called for chat messages.
+    fn rewrite_say_text2(&mut self, _msg: &mut CUSRMsg_SayText2) {}
+    fn rewrite_server_rank_update(&mut self, _msg: &mut CUSRMsg_ServerRankUpdate) {}
+}
+
+/// Copies a demo from `read` to `write`, letting `rewriter` modify its content.
+pub fn rewrite(
+    read: &mut dyn Read,
+    write: &mut dyn Write,
+    rewriter: &mut dyn Rewriter,
+) -> Result<()> {
+    let mut header = [0; HEADER_SIZE];
+    read.read_exact(&mut header)?;
+    let mut server_name = DemoHeader::try_new(&mut CodedInputStream::from_bytes(&header))?
+        .server_name()
+        .clone();
+    rewriter.rewrite_server_name(&mut server_name);
+    let field = &mut header[SERVER_NAME_OFFSET..SERVER_NAME_OFFSET + MAX_OS_PATH];
+    let mut len = server_name.len().min(MAX_OS_PATH - 1);
+    while !server_name.is_char_boundary(len) {
+        len -= 1;
+    }
+    field.fill(0);
+    field[..len].copy_from_slice(&server_name.as_bytes()[..len]);
+    write.write_all(&header)?;
+
+    let mut packet_rewriter = PacketRewriter {
+        rewriter,
+        string_tables: StringTables::new(),
+        game_event_descriptors: HashMap::new(),
+    };
+    let mut reader = CodedInputStream::new(read);
+    let mut os = CodedOutputStream::new(write);
+    while !reader.eof()? {
+        let command = reader.read_raw_byte()?;
+        os.write_raw_byte(command)?;
+        // Tick and player slot.
+        os.write_raw_bytes(&reader.read_raw_bytes(4 + 1)?)?;
+        match Command::try_from(command)? {
+            Command::Signon | Command::Packet => {
+                os.write_raw_bytes(&reader.read_raw_bytes(COMMAND_INFO_SIZE as u32)?)?;
+                let size = reader.read_fixed32()?;
+                let data = packet_rewriter.rewrite_packet(&reader.read_raw_bytes(size)?)?;
+                os.write_fixed32_no_tag(data.len() as u32)?;
+                os.write_raw_bytes(&data)?;
+            }
+            Command::StringTables => {
+                let size = reader.read_fixed32()?;
+                let mut data = reader.read_raw_bytes(size)?;
+                packet_rewriter.rewrite_string_tables(&mut data)?;
+                os.write_fixed32_no_tag(size)?;
+                os.write_raw_bytes(&data)?;
+            }
+            Command::SyncTick => {}
+            Command::Stop => break,
+            Command::UserCommand | Command::CustomData => {
+                // Outgoing sequence number or callback index, then the data.
+                os.write_raw_bytes(&reader.read_raw_bytes(4)?)?;
+                copy_data(&mut reader, &mut os)?;
+            }
+            Command::ConsoleCommand | Command::DataTables => copy_data(&mut reader, &mut os)?,
+        }
+    }
+    // Copy anything found after the Stop command.
+    while !reader.eof()? {
+        os.write_raw_byte(reader.read_raw_byte()?)?;
+    }
+    os.flush()?;
+    Ok(())
+}
+
+/// Copies a size followed by as many bytes.
+fn copy_data(reader: &mut CodedInputStream, os: &mut CodedOutputStream) -> Result<()> {
+    let size = reader.read_fixed32()?;
+    os.write_fixed32_no_tag(size)?;
+    os.write_raw_bytes(&reader.read_raw_bytes(size)?)?;
+    Ok(())
+}
+
+struct PacketRewriter<'a> {
+    rewriter: &'a mut dyn Rewriter,
+    string_tables: StringTables,
+    game_event_descriptors: HashMap<i32, csvcmsg_game_event_list::Descriptor_t>,
+}
+
+impl<'a> PacketRewriter<'a> {
+    fn rewrite_packet(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut reader = CodedInputStream::from_bytes(data);
+        let mut out = Vec::with_capacity(data.len());
+        let mut os = CodedOutputStream::vec(&mut out);
+        while !reader.eof()? {
+            let msg_type = reader.read_int32()?;
+            let size = reader.read_raw_varint32()?;
+            let bytes = reader.read_raw_bytes(size)?;
+            let bytes = self.rewrite_message(msg_type, &bytes)?.unwrap_or(bytes);
+            os.write_int32_no_tag(msg_type)?;
+            os.write_raw_varint32(bytes.len() as u32)?;
+            os.write_raw_bytes(&bytes)?;
+        }
+        os.flush()?;
+        drop(os);
+        Ok(out)
+    }
+
+    /// Returns the new content of the message, or None if it is unchanged.
+    fn rewrite_message(&mut self, msg_type: i32, bytes: &[u8]) -> Result<Option<Vec<u8>>> {
+        const SERVER_INFO: i32 = SVC_Messages::svc_ServerInfo as i32;
+        const CREATE_STRING_TABLE: i32 = SVC_Messages::svc_CreateStringTable as i32;
+        const UPDATE_STRING_TABLE: i32 = SVC_Messages::svc_UpdateStringTable as i32;
+        const USER_MESSAGE: i32 = SVC_Messages::svc_UserMessage as i32;
+        const GAME_EVENT_LIST: i32 = SVC_Messages::svc_GameEventList as i32;
+        const GAME_EVENT: i32 = SVC_Messages::svc_GameEvent as i32;
+        Ok(match msg_type {
+            SERVER_INFO => {
+                let mut msg = CSVCMsg_ServerInfo::parse_from_bytes(bytes)?;
+                self.rewriter.rewrite_server_info(&mut msg);
+                Some(msg.write_to_bytes()?)
+            }
+            CREATE_STRING_TABLE => {
+                let mut msg = CSVCMsg_CreateStringTable::parse_from_bytes(bytes)?;
+                let mut player_infos = Vec::new();
+                let mut updates = self.string_tables.create_string_table(&msg);
+                while let Some(player_info) = updates.next_player_info_data()? {
+                    player_infos.push(player_info);
+                }
+                self.rewrite_player_infos(msg.mut_string_data(), player_infos)?;
+                Some(msg.write_to_bytes()?)
+            }
+            UPDATE_STRING_TABLE => {
+                let mut msg = CSVCMsg_UpdateStringTable::parse_from_bytes(bytes)?;
+                let mut player_infos = Vec::new();
+                let mut updates = self.string_tables.update_string_table(&msg)?;
+                while let Some(player_info) = updates.next_player_info_data()? {
+                    player_infos.push(player_info);
+                }
+                self.rewrite_player_infos(msg.mut_string_data(), player_infos)?;
+                Some(msg.write_to_bytes()?)
+            }
+            USER_MESSAGE => {
+                let mut msg = CSVCMsg_UserMessage::parse_from_bytes(bytes)?;
+                let msg_data = match USR_Messages::from_i32(msg.msg_type()) {
+                    Some(USR_Messages::usr_SayText2) => {
+                        let mut say_text2 = CUSRMsg_SayText2::parse_from_bytes(msg.msg_data())?;
+                        self.rewriter.rewrite_say_text2(&mut say_text2);
+                        say_text2.write_to_bytes()?
+                    }
+                    Some(USR_Messages::usr_ServerRankUpdate) => {
+                        let mut rank_update =
+                            CUSRMsg_ServerRankUpdate::parse_from_bytes(msg.msg_data())?;
+                        self.rewriter.rewrite_server_rank_update(&mut rank_update);
+                        rank_update.write_to_bytes()?
+                    }
+                    _ => return Ok(None),
+                };
+                msg.set_msg_data(msg_data);
+                Some(msg.write_to_bytes()?)
+            }
+            GAME_EVENT_LIST => {
+                let msg = CSVCMsg_GameEventList::parse_from_bytes(bytes)?;
+                self.game_event_descriptors = msg
+                    .descriptors
+                    .into_iter()
+                    .map(|d| (d.eventid(), d))
+                    .collect();
+                None
+            }
+            GAME_EVENT => {
+                let mut msg = CSVCMsg_GameEvent::parse_from_bytes(bytes)?;
+                let Some(descriptor) = self.game_event_descriptors.get(&msg.eventid()) else {
+                    return Ok(None);
+                };
+                self.rewriter.rewrite_game_event(&mut msg, descriptor);
+                Some(msg.write_to_bytes()?)
+            }
+            _ => None,
+        })
+    }
+
+    fn rewrite_string_tables(&mut self, data: &mut [u8]) -> Result<()> {
+        let mut player_infos = Vec::new();
+        for table in parse_string_tables_data(data)? {
+            if table.name() != "userinfo" {
+                continue;
+            }
+            for (entity_id, string) in table.strings().iter().enumerate() {
+                if let Some(data) = string.data() {
+                    player_infos.push((entity_id as i32, data.clone()));
+                }
+            }
+        }
+        self.rewrite_player_infos(data, player_infos)
+    }
+
+    /// Rewrites the player_info_t found in the bit-packed string table `data`. The entries keep
+    /// their size so they are replaced where they are found.
+    fn rewrite_player_infos(
+        &mut self,
+        data: &mut [u8],
+        player_infos: Vec<(i32, Vec<u8>)>,
+    ) -> Result<()> {
+        for (entity_id, raw) in player_infos {
+            let mut player_info = parse_player_info(&raw, entity_id)?;
+            self.rewriter.rewrite_player_info(&mut player_info);
+            let mut new_raw = raw.clone();
+            write_player_info(&player_info, &mut new_raw)?;
+            if new_raw != raw {
+                replace_bits(data, &raw, &new_raw);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Replaces the first occurrence of `from` in `data`, at any bit offset, with `to`, which must
+/// have the same size.
+fn replace_bits(data: &mut [u8], from: &[u8], to: &[u8]) -> bool {
+    let byte_at = |data: &[u8], bit: usize| {
+        let (index, shift) = (bit / 8, bit % 8);
+        if shift == 0 {
+            data[index]
+        } else {
+            (data[index] >> shift) | (data[index + 1] << (8 - shift))
+        }
+    };
+    let size = from.len() * 8;
+    for bit in 0..(data.len() * 8 + 1).saturating_sub(size) {
+        if (0..from.len()).all(|i| byte_at(data, bit + i * 8) == from[i]) {
+            for (i, byte) in to.iter().enumerate() {
+                let (index, shift) = ((bit + i * 8) / 8, (bit + i * 8) % 8);
+                if shift == 0 {
+                    data[index] = *byte;
+                } else {
+                    data[index] = (data[index] & (0xff >> (8 - shift))) | (byte << shift);
+                    data[index + 1] = (data[index + 1] & (0xff << shift)) | (byte >> (8 - shift));
+                }
+            }
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::make_demo;
+    use crate::{DemoParser, PacketContent};
+
+    #[test]
+    fn replace_bits_at_any_offset() {
+        for shift in 0..16 {
+            let mut data = vec![0u8; 8];
+            let value = 0xabcdu64 << shift;
+            data[..8].copy_from_slice(&value.to_le_bytes());
+            assert!(replace_bits(&mut data, &[0xcd, 0xab], &[0x34, 0x12]));
+            assert_eq!(
+                u64::from_le_bytes(data.try_into().unwrap()),
+                0x1234 << shift
+            );
+        }
+        assert!(!replace_bits(&mut [0xcd], &[0xcd, 0xab], &[0x34, 0x12]));
+    }
+
+    struct Anonymous;
+
+    impl Rewriter for Anonymous {
+        fn rewrite_server_name(&mut self, server_name: &mut String) {
+            *server_name = "anonymous".to_string();
+        }
+
+        fn rewrite_say_text2(&mut self, msg: &mut CUSRMsg_SayText2) {
+            msg.params[0] = "player".to_string();
+        }
+    }
+
+    #[test]
+    fn rewrite_demo() -> Result<()> {
+        const PACKET: u8 = 2;
+        const STOP: u8 = 7;
+        let mut say_text2 = CUSRMsg_SayText2::new();
+        say_text2.params = vec!["someone".to_string(), "hello".to_string()];
+        let mut user_message = CSVCMsg_UserMessage::new();
+        user_message.set_msg_type(USR_Messages::usr_SayText2 as i32);
+        user_message.set_msg_data(say_text2.write_to_bytes()?);
+        let user_message = user_message.write_to_bytes()?;
+        let mut packet = vec![0; COMMAND_INFO_SIZE];
+        let mut messages = vec![
+            SVC_Messages::svc_UserMessage as u8,
+            user_message.len() as u8,
+        ];
+        messages.extend(user_message);
+        packet.extend((messages.len() as u32).to_le_bytes());
+        packet.extend(messages);
+        let demo = make_demo(&[(PACKET, 1, &packet), (STOP, 2, &[])]);
+
+        let mut out = Vec::new();
+        rewrite(&mut demo.as_slice(), &mut out, &mut Anonymous)?;
+
+        let mut read = out.as_slice();
+        let mut parser = DemoParser::try_new(&mut read)?;
+        assert_eq!(parser.header().server_name(), "anonymous");
+        assert_eq!(parser.header().map_name(), "de_dust2");
+        let Some((_, PacketContent::Packet(messages))) = parser.parse_next_packet()? else {
+            panic!("expected a packet");
+        };
+        let [crate::Message::UserMessage(user_message)] = messages.as_slice() else {
+            panic!("unexpected {messages:?}");
+        };
+        let say_text2 = CUSRMsg_SayText2::parse_from_bytes(user_message.msg_data())?;
+        assert_eq!(say_text2.params, vec!["player", "hello"]);
+        assert!(matches!(
+            parser.parse_next_packet()?,
+            Some((_, PacketContent::Stop))
+        ));
+        assert!(parser.parse_next_packet()?.is_none());
+        Ok(())
+    }
+}
@@ -1,9 +1,9 @@
 use bitstream_io::BitRead;
-use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
 use getset::Getters;
 use protobuf::CodedInputStream;
 use std::ffi::CStr;
-use std::io::{BufRead, Cursor};
+use std::io::{BufRead, Cursor, Write};
 use tracing::{instrument, trace};
 
 use crate::proto::netmessages::{CSVCMsg_CreateStringTable, CSVCMsg_UpdateStringTable};
@@ -96,8 +96,12 @@ impl StringTable {
 pub(crate) fn parse_string_tables(reader: &mut CodedInputStream) -> Result<Vec<StringTable>> {
     let size = reader.read_fixed32()?;
     let data = reader.read_raw_bytes(size)?;
+    parse_string_tables_data(&data)
+}
 
-    let mut reader = BitReader::new(data.as_slice());
+/// Parses the content of a StringTables command.
+pub(crate) fn parse_string_tables_data(data: &[u8]) -> Result<Vec<StringTable>> {
+    let mut reader = BitReader::new(data);
     let tables_number = reader.read::<u8>(8)?;
     let mut tables: Vec<StringTable> = Vec::with_capacity(tables_number as usize);
 
@@ -115,6 +119,7 @@ struct StringTableDescriptor {
     name: String,
     max_entries: u32,
     user_data_fixed_size: bool,
+    user_data_size_bits: u32,
 }
 
 pub struct StringTables {
@@ -136,6 +141,7 @@ impl StringTables {
             name: table.name().to_string(),
             max_entries: table.max_entries() as u32,
             user_data_fixed_size: table.user_data_fixed_size(),
+            user_data_size_bits: table.user_data_size_bits() as u32,
         });
         StringTableUpdates::new(
             self.string_tables.last().unwrap(),
@@ -181,6 +187,16 @@ pub struct PlayerInfo {
     pub entity_id: i32,
 }
 
+/// An entry added or changed by a string table update.
+#[derive(Debug)]
+pub struct StringTableEntry {
+    pub index: i32,
+    /// None if the entry keeps its string.
+    pub string: Option<String>,
+    /// None if the entry keeps its user data.
+    pub data: Option<Vec<u8>>,
+}
+
 pub struct StringTableUpdates<'a> {
     table_descriptor: &'a StringTableDescriptor,
     entries: i32,
@@ -188,6 +204,8 @@ pub struct StringTableUpdates<'a> {
     entry: i32,
     next_entity_id: i32,
     entry_bits: u32,
+    /// The strings of the last entries, which the following ones can start with.
+    history: Vec<String>,
 }
 
 impl<'a> StringTableUpdates<'a> {
@@ -200,13 +218,23 @@ impl<'a> StringTableUpdates<'a> {
             entry: 0,
             next_entity_id: 0,
             entry_bits,
+            history: Vec::new(),
         }
     }
 
+    pub fn table_name(&self) -> &str {
+        &self.table_descriptor.name
+    }
+
     pub fn next_player_info(&mut self) -> Result<Option<PlayerInfo>> {
-        if self.entry >= self.entries {
-            return Ok(None);
+        match self.next_player_info_data()? {
+            Some((entity_id, data)) => Ok(Some(parse_player_info(&data, entity_id)?)),
+            None => Ok(None),
         }
+    }
+
+    /// Returns the entity id and the raw player_info_t of the next updated player.
+    pub(crate) fn next_player_info_data(&mut self) -> Result<Option<(i32, Vec<u8>)>> {
         if self.entry == 0 {
             if self.table_descriptor.name != "userinfo" {
                 return Ok(None);
@@ -214,49 +242,85 @@ impl<'a> StringTableUpdates<'a> {
             if self.table_descriptor.user_data_fixed_size {
                 Err(Error::StringTable("userinfo should not be fixed data"))?;
             }
-            if self.reader.read_bit()? {
-                Err(Error::StringTable(
-                    "cannot decode string table encoded with dictionaries",
-                ))?;
-            }
         }
-        let max_entries = self.table_descriptor.max_entries;
-        while self.entry < self.entries {
-            let entity_id = if !self.reader.read_bit()? {
-                self.reader.read::<u32>(self.entry_bits)? as i32
-            } else {
-                self.next_entity_id
-            };
-            self.next_entity_id = entity_id + 1;
-            if entity_id >= max_entries as i32 {
-                Err(Error::StringTable("update_string_table got a bad index"))?;
+        while let Some(entry) = self.next_entry()? {
+            if let Some(data) = entry.data {
+                return Ok(Some((entry.index, data)));
             }
+        }
+        Ok(None)
+    }
+
+    /// Returns the next updated entry of the table.
+    pub fn next_entry(&mut self) -> Result<Option<StringTableEntry>> {
+        // Valve's limits, see ParseStringTableUpdate in demoinfogo.
+        const MAX_HISTORY: usize = 32;
+        const MAX_STRING_LENGTH: usize = 1024;
+        if self.entry >= self.entries {
+            return Ok(None);
+        }
+        if self.entry == 0 && self.reader.read_bit()? {
+            Err(Error::StringTable(
+                "cannot decode string table encoded with dictionaries",
+            ))?;
+        }
+        let index = if !self.reader.read_bit()? {
+            self.reader.read::<u32>(self.entry_bits)? as i32
+        } else {
+            self.next_entity_id
+        };
+        self.next_entity_id = index + 1;
+        if index >= self.table_descriptor.max_entries as i32 {
+            Err(Error::StringTable("update_string_table got a bad index"))?;
+        }
+        let string = if self.reader.read_bit()? {
+            let mut string = String::new();
             if self.reader.read_bit()? {
-                if self.reader.read_bit()? {
-                    Err(Error::StringTable("substrings not implemented"))?;
-                } else {
-                    // I don't know what this is, ignore the string.
-                    while self.reader.read::<u8>(8)? != 0 {}
-                }
+                let history_index = self.reader.read::<u32>(5)? as usize;
+                let length = self.reader.read::<u32>(5)? as usize;
+                let prefix = self
+                    .history
+                    .get(history_index)
+                    .ok_or(Error::StringTable("bad string table history index"))?;
+                string.extend(prefix.chars().take(length));
             }
-            if self.reader.read_bit()? {
-                let num_bytes = self.reader.read::<u32>(14)? as usize;
-                let mut buf = vec![0; num_bytes];
-                self.reader.read_bytes(buf.as_mut_slice())?;
-                let player_info = parse_player_info(&buf, entity_id)?;
-                self.entry += 1;
-                return Ok(Some(player_info));
-            } else {
-                self.entry += 1
+            string.push_str(&self.reader.read_string_to_terminator(MAX_STRING_LENGTH)?);
+            Some(string)
+        } else {
+            None
+        };
+        let data = if !self.reader.read_bit()? {
+            None
+        } else if self.table_descriptor.user_data_fixed_size {
+            let bits = self.table_descriptor.user_data_size_bits;
+            let mut data = Vec::with_capacity(bits.div_ceil(8) as usize);
+            for i in (0..bits).step_by(8) {
+                data.push(self.reader.read::<u8>((bits - i).min(8))?);
             }
+            Some(data)
+        } else {
+            let num_bytes = self.reader.read::<u32>(14)? as usize;
+            let mut data = vec![0; num_bytes];
+            self.reader.read_bytes(data.as_mut_slice())?;
+            Some(data)
+        };
+        if self.history.len() == MAX_HISTORY {
+            self.history.remove(0);
         }
-        Ok(None)
+        self.history.push(string.clone().unwrap_or_default());
+        self.entry += 1;
+        Ok(Some(StringTableEntry {
+            index,
+            string,
+            data,
+        }))
     }
 }
 
-fn parse_player_info(buf: &[u8], entity_id: i32) -> Result<PlayerInfo> {
-    const PLAYER_NAME_LENGTH: usize = 128;
-    const GUID_LENGTH: usize = 33;
+const PLAYER_NAME_LENGTH: usize = 128;
+const GUID_LENGTH: usize = 33;
+
+pub(crate) fn parse_player_info(buf: &[u8], entity_id: i32) -> Result<PlayerInfo> {
     let mut reader = Cursor::new(buf);
     let version = reader.read_u64::<LittleEndian>()?;
     let xuid = reader.read_u64::<BigEndian>()?;
@@ -290,6 +354,24 @@ fn parse_player_info(buf: &[u8], entity_id: i32) -> Result<PlayerInfo> {
     Ok(player_info)
 }
 
+/// Writes the fields of `player_info` over the player_info_t in `buf`, up to `is_hltv`. The
+/// following fields are left unchanged.
+pub(crate) fn write_player_info(player_info: &PlayerInfo, buf: &mut [u8]) -> Result<()> {
+    let mut writer = Cursor::new(buf);
+    writer.write_u64::<LittleEndian>(player_info.version)?;
+    writer.write_u64::<BigEndian>(player_info.xuid)?;
+    write_cstring_buffer(&mut writer, &player_info.name, PLAYER_NAME_LENGTH)?;
+    writer.write_i32::<BigEndian>(player_info.user_id)?;
+    write_cstring_buffer(&mut writer, &player_info.guid, GUID_LENGTH)?;
+    // Skip padding.
+    writer.consume(3);
+    writer.write_i32::<BigEndian>(player_info.friends_id)?;
+    write_cstring_buffer(&mut writer, &player_info.friends_name, PLAYER_NAME_LENGTH)?;
+    writer.write_u8(player_info.fakeplayer as u8)?;
+    writer.write_u8(player_info.is_hltv as u8)?;
+    Ok(())
+}
+
 pub fn parse_player_infos(st: Vec<StringTable>) -> Result<Vec<PlayerInfo>> {
     let mut result = Vec::new();
     for st in st.iter().filter(|st| st.name() == "userinfo") {
@@ -310,9 +392,23 @@ fn read_cstring_buffer(cursor: &mut Cursor<&[u8]>, size: usize) -> std::io::Resu
     Ok(cstr.to_string_lossy().into_owned())
 }
 
+/// Writes `s` as a NUL-terminated string padded with zeros to `size` bytes, truncating it if
+/// needed.
+fn write_cstring_buffer(cursor: &mut Cursor<&mut [u8]>, s: &str, size: usize) -> Result<()> {
+    let mut len = s.len().min(size - 1);
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+    let mut buf = vec![0; size];
+    buf[..len].copy_from_slice(&s.as_bytes()[..len]);
+    cursor.write_all(&buf)?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
-    use super::StringTables;
+    use super::{parse_player_info, write_player_info, PlayerInfo, StringTables};
+    use crate::proto::netmessages::CSVCMsg_CreateStringTable;
     use crate::Result;
     use protobuf::text_format::parse_from_str;
 
@@ -338,4 +434,83 @@ mod tests {
         while (updates.next_player_info()?).is_some() {}
         Ok(())
     }
+
+    #[test]
+    fn entries() -> Result<()> {
+        // Packs (value, count) bits in the order they are read.
+        let bits = |fields: &[(u32, u32)]| {
+            let bits: Vec<_> = fields
+                .iter()
+                .flat_map(|&(value, count)| (0..count).map(move |i| value >> i & 1))
+                .collect();
+            let mut bytes = vec![0u8; bits.len().div_ceil(8)];
+            for (i, bit) in bits.iter().enumerate() {
+                bytes[i / 8] |= (*bit as u8) << (i % 8);
+            }
+            bytes
+        };
+        let string = |s: &str| {
+            s.bytes()
+                .chain([0])
+                .map(|b| (b as u32, 8))
+                .collect::<Vec<_>>()
+        };
+        let mut fields = vec![(0, 1)];
+        // Entry 0 with a string and no data.
+        fields.extend([(1, 1), (1, 1), (0, 1)]);
+        fields.extend(string("weapon_ak47"));
+        fields.push((0, 1));
+        // Entry 5, whose string starts with the first 7 characters of the first entry, with 2
+        // bytes of data.
+        fields.extend([(0, 1), (5, 4), (1, 1), (1, 1), (0, 5), (7, 5)]);
+        fields.extend(string("m4a1"));
+        fields.extend([(1, 1), (2, 14), (1, 8), (2, 8)]);
+        // Entry 6, unchanged.
+        fields.extend([(1, 1), (0, 1), (0, 1)]);
+
+        let mut table = CSVCMsg_CreateStringTable::new();
+        table.set_name("weapons".to_string());
+        table.set_max_entries(16);
+        table.set_num_entries(3);
+        table.set_string_data(bits(&fields));
+        let mut st = StringTables::default();
+        let mut updates = st.create_string_table(&table);
+        assert_eq!(updates.table_name(), "weapons");
+        let mut entries = Vec::new();
+        while let Some(entry) = updates.next_entry()? {
+            entries.push(format!("{entry:?}"));
+        }
+        assert_eq!(
+            entries,
+            [
+                r#"StringTableEntry { index: 0, string: Some("weapon_ak47"), data: None }"#,
+                r#"StringTableEntry { index: 5, string: Some("weapon_m4a1"), data: Some([1, 2]) }"#,
+                r#"StringTableEntry { index: 6, string: None, data: None }"#,
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn write_player_info_roundtrip() -> Result<()> {
+        let player_info = PlayerInfo {
+            version: 0xff00,
+            xuid: 76561197960265729,
+            name: "someone".to_string(),
+            user_id: 12,
+            guid: "STEAM_1:1:0".to_string(),
+            friends_id: 1,
+            friends_name: "friend".to_string(),
+            fakeplayer: false,
+            is_hltv: true,
+            files_downloaded: 3,
+            entity_id: 4,
+        };
+        let mut buf = vec![0xaa; 340];
+        write_player_info(&player_info, &mut buf)?;
+        buf[336] = 3;
+        let parsed = parse_player_info(&buf, 4)?;
+        assert_eq!(format!("{parsed:?}"), format!("{player_info:?}"));
+        Ok(())
+    }
 }
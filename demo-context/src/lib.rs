@@ -0,0 +1,107 @@
+//! Position in a demo where a parsing error happened, shared by the CS:GO and CS2 demo parsers so
+//! that their errors can be reported the same way.
+
+use std::fmt;
+
+/// Part of the demo being parsed when an error happened. Errors are wrapped with one context per
+/// level, from the demo command down to the entity.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Context {
+    /// Demo command at `offset` in the uncompressed demo. Its name and tick are missing when its
+    /// header cannot be read.
+    Command {
+        offset: u64,
+        command: Option<String>,
+        tick: Option<i32>,
+    },
+    /// Packet message, by type.
+    Message(String),
+    Entity {
+        entity_id: u32,
+        class: Option<String>,
+    },
+}
+
+impl fmt::Display for Context {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Context::Command {
+                offset,
+                command,
+                tick,
+            } => {
+                match command {
+                    Some(command) => write!(f, "{command} command")?,
+                    None => write!(f, "demo command")?,
+                }
+                if let Some(tick) = tick {
+                    write!(f, " at tick {tick}")?;
+                }
+                write!(f, ", offset {offset}")
+            }
+            Context::Message(message) => write!(f, "{message} message"),
+            Context::Entity {
+                entity_id,
+                class: Some(class),
+            } => write!(f, "entity {entity_id} ({class})"),
+            Context::Entity {
+                entity_id,
+                class: None,
+            } => write!(f, "entity {entity_id}"),
+        }
+    }
+}
+
+/// Errors that can be wrapped with a [`Context`].
+pub trait InContext: Sized {
+    /// Returns an error that holds `context`, with `self` as its source.
+    fn in_context(self, context: Context) -> Self;
+
+    fn in_command(self, offset: u64, command: Option<String>, tick: Option<i32>) -> Self {
+        self.in_context(Context::Command {
+            offset,
+            command,
+            tick,
+        })
+    }
+
+    fn in_message(self, message: impl Into<String>) -> Self {
+        self.in_context(Context::Message(message.into()))
+    }
+
+    fn in_entity(self, entity_id: u32, class: Option<&str>) -> Self {
+        self.in_context(Context::Entity {
+            entity_id,
+            class: class.map(str::to_string),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let command = Context::Command {
+            offset: 1234,
+            command: Some("Packet".to_string()),
+            tick: Some(100),
+        };
+        assert_eq!(
+            command.to_string(),
+            "Packet command at tick 100, offset 1234"
+        );
+        let command = Context::Command {
+            offset: 16,
+            command: None,
+            tick: None,
+        };
+        assert_eq!(command.to_string(), "demo command, offset 16");
+        let entity = Context::Entity {
+            entity_id: 5,
+            class: Some("CCSPlayer".to_string()),
+        };
+        assert_eq!(entity.to_string(), "entity 5 (CCSPlayer)");
+    }
+}
@@ -209,7 +209,7 @@ fn diff_array<'a>(left: &'a Value, right: &'a Value, path: Path<'a>) -> Option<D
         }
         None
     } else {
-        return Some(Diff::new(Some(left), Some(right), path));
+        Some(Diff::new(Some(left), Some(right), path))
     }
 }
 